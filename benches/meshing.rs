@@ -0,0 +1,67 @@
+// the meshers scan whole rows of voxels at once; this compares how long
+// they take against the reference scan going voxel by voxel, which the unit
+// tests check gives the exact same meshes
+//
+// run with: cargo bench --bench meshing
+
+use std::time::{Duration, Instant};
+
+use mulch::voxels::{MeshingMode, Palette, VoxelGrid};
+
+fn main() {
+    let grid = scene();
+    let palette = Palette::default();
+
+    for mode in [
+        MeshingMode::SurfaceNets,
+        MeshingMode::Blocky,
+        MeshingMode::Greedy,
+        MeshingMode::MarchingCubes,
+        MeshingMode::DualContouring,
+    ] {
+        let (_, indices) = grid.generate_mesh(mode, &palette);
+        let reference = time(|| grid.generate_reference_mesh(mode, &palette));
+        let rows = time(|| grid.generate_mesh(mode, &palette));
+        println!(
+            "{:<14} {:>7} triangles   voxels {:>8.2} ms   rows {:>8.2} ms   x{:.1}",
+            format!("{:?}", mode),
+            indices.len() / 3,
+            reference.as_secs_f64() * 1000.0,
+            rows.as_secs_f64() * 1000.0,
+            reference.as_secs_f64() / rows.as_secs_f64()
+        );
+    }
+}
+
+// a few chunks of hard and smooth shapes, with some scattered voxels
+fn scene() -> VoxelGrid {
+    let mut grid = VoxelGrid::new();
+    grid.paint_cube((-60, -60, -8), (60, 60, 0), 2);
+    grid.paint_cube((-20, -20, 0), (20, 20, 40), 1);
+    grid.paint_sphere((30, 30, 20), 18.0, 3);
+    grid.paint_sphere((-40, 20, 10), 12.5, 5);
+
+    let mut hole = VoxelGrid::new();
+    hole.paint_sphere((0, 0, 40), 15.0, 0);
+    grid.subtract(&hole);
+
+    for i in 0..2000 {
+        let hash = (i * 7919 + 13) % 10007;
+        let coords = (hash % 97 - 48, (hash / 97) % 97 - 48, 1 + hash % 13);
+        grid.paint_cube(coords, coords, (i % 16) as u8);
+    }
+
+    grid
+}
+
+// best of a few runs
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
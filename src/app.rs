@@ -27,14 +27,15 @@ impl App {
         let window_size = window.inner_size();
         let mut renderer = render::Renderer::new(&window, [window_size.width, window_size.height]);
         let mut doc = document::Document::default();
+        let mut composite = document::Composite::new();
 
-        doc.layers[0].voxel_grid.paint_cube((2, 3, 0), (9, 5, 2));
-        doc.layers[0].voxel_grid.paint_cube((4, 1, 0), (7, 8, 1));
-        doc.layers[0].voxel_grid.paint_cube((5, 4, 2), (6, 5, 5));
+        doc.layers[0].voxel_grid.paint_cube((2, 3, 0), (8, 5, 2), 0);
+        doc.layers[0].voxel_grid.paint_cube((4, 1, 0), (6, 8, 1), 3);
+        doc.layers[0].voxel_grid.paint_cube((5, 4, 2), (5, 5, 5), 5);
         doc.layers[0]
             .voxel_grid
-            .paint_cube((30, 30, 1), (40, 40, 30));
-        doc.layers[0].voxel_grid.paint_sphere((6, 6, 6), 2.5);
+            .paint_cube((30, 30, 1), (39, 40, 30), 9);
+        doc.layers[0].voxel_grid.paint_sphere((6, 6, 6), 2.5, 6);
 
         let mut ui_context = ui::UiContext::new();
         let mut editor = editor::Editor::new();
@@ -57,10 +58,11 @@ impl App {
                 let keyboard_modifiers = ui_context.modifiers_state;
 
                 let ui_render_data = ui_context.run(&window, time, |ctx| {
-                    editor.run(ctx, &mut doc, keyboard_modifiers)
+                    editor.run(ctx, &mut doc, &composite, keyboard_modifiers)
                 });
 
-                renderer.render(&doc, &ui_render_data);
+                composite.update(&doc);
+                renderer.render(&doc, &composite, &ui_render_data);
             }
 
             Event::MainEventsCleared => {
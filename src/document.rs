@@ -1,10 +1,18 @@
+use std::collections::BTreeSet;
+
 use glam::Vec4Swizzles;
+use rayon::prelude::*;
 
-use crate::voxels::VoxelGrid;
+use crate::voxels::{ChunkCoords, MeshingMode, Palette, Statistics, VoxelGrid};
 
+#[derive(Clone)]
 pub struct Document {
     pub layers: Vec<Layer>,
+    pub palette: Palette,
     pub viewport: Viewport,
+
+    // length of the side of a voxel once printed, in millimeters
+    pub voxel_size: f32,
 }
 
 impl Default for Document {
@@ -24,22 +32,135 @@ impl Default for Document {
                     ..Default::default()
                 },
             ],
+            palette: Palette::default(),
             viewport: Viewport::default(),
+            voxel_size: 0.1,
+        }
+    }
+}
+
+impl Document {
+    // surface figures follow the mesh shown in the viewport
+    pub fn layer_statistics(&self, index: usize) -> Statistics {
+        self.layers[index]
+            .displayed_voxel_grid()
+            .statistics(self.viewport.meshing_mode, self.voxel_size)
+    }
+
+    pub fn statistics(&self, composite: &Composite) -> Statistics {
+        composite
+            .voxel_grid
+            .statistics(self.viewport.meshing_mode, self.voxel_size)
+    }
+}
+
+// flattened layers, kept across frames; layers are blended chunk by chunk,
+// so only the chunks edited since the last update need to be blended again,
+// and each of them can be blended on its own thread
+#[derive(Clone)]
+pub struct Composite {
+    pub voxel_grid: VoxelGrid,
+
+    // id, revision, visibility and blend mode of each layer when last blended
+    layers: Vec<(u64, u64, bool, BlendMode)>,
+}
+
+impl Composite {
+    pub fn new() -> Self {
+        Self {
+            voxel_grid: VoxelGrid::new(),
+            layers: vec![],
+        }
+    }
+
+    pub fn update(&mut self, doc: &Document) {
+        let layers: Vec<(u64, u64, bool, BlendMode)> = doc
+            .layers
+            .iter()
+            .map(|layer| {
+                (
+                    layer.displayed_voxel_grid().id(),
+                    layer.displayed_voxel_grid().revision(),
+                    layer.visible,
+                    layer.blend_mode,
+                )
+            })
+            .collect();
+
+        // any change to the stack of layers itself means blending everything
+        let same_stack = layers.len() == self.layers.len()
+            && layers.iter().zip(&self.layers).all(|(layer, previous)| {
+                (layer.0, layer.2, layer.3) == (previous.0, previous.2, previous.3)
+            });
+
+        let mut dirty: BTreeSet<ChunkCoords> = BTreeSet::new();
+        if same_stack {
+            for (layer, previous) in doc.layers.iter().zip(&self.layers) {
+                dirty.extend(layer.displayed_voxel_grid().changed_chunks(previous.1));
+            }
+        } else {
+            dirty.extend(self.voxel_grid.chunk_coords());
+            for layer in &doc.layers {
+                dirty.extend(layer.displayed_voxel_grid().chunk_coords());
+            }
+        }
+
+        let blended: Vec<VoxelGrid> = dirty
+            .par_iter()
+            .map(|chunk_coords| {
+                let mut voxel_grid = VoxelGrid::new();
+                for layer in doc.layers.iter().filter(|layer| layer.visible) {
+                    match layer.blend_mode {
+                        BlendMode::Add => {
+                            voxel_grid.add_chunk(layer.displayed_voxel_grid(), *chunk_coords);
+                        }
+                        BlendMode::Subtract => {
+                            voxel_grid.subtract_chunk(layer.displayed_voxel_grid(), *chunk_coords);
+                        }
+                        BlendMode::Intersect => {
+                            voxel_grid.intersect_chunk(layer.displayed_voxel_grid(), *chunk_coords);
+                        }
+                        BlendMode::Xor => {
+                            voxel_grid.xor_chunk(layer.displayed_voxel_grid(), *chunk_coords);
+                        }
+                        BlendMode::MaskToBelow => {
+                            voxel_grid.replace_chunk(layer.displayed_voxel_grid(), *chunk_coords);
+                        }
+                    }
+                }
+                voxel_grid
+            })
+            .collect();
+
+        for (chunk_coords, voxel_grid) in dirty.iter().zip(&blended) {
+            self.voxel_grid.copy_chunk(voxel_grid, *chunk_coords);
         }
+
+        self.layers = layers;
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BlendMode {
     Add,
     Subtract,
+    Intersect,
+    Xor,
+
+    // only repaints the layers below, where they are solid
+    MaskToBelow,
 }
 
+#[derive(Clone)]
 pub struct Layer {
     pub name: String,
     pub visible: bool,
     pub blend_mode: BlendMode,
     pub voxel_grid: VoxelGrid,
+
+    // result of an operation being set up on the layer, shown instead of
+    // its voxels until it is applied or cancelled
+    pub preview: Option<VoxelGrid>,
 }
 
 impl Default for Layer {
@@ -49,13 +170,22 @@ impl Default for Layer {
             visible: true,
             blend_mode: BlendMode::Add,
             voxel_grid: VoxelGrid::new(),
+            preview: None,
         }
     }
 }
 
+impl Layer {
+    pub fn displayed_voxel_grid(&self) -> &VoxelGrid {
+        self.preview.as_ref().unwrap_or(&self.voxel_grid)
+    }
+}
+
+#[derive(Clone)]
 pub struct Viewport {
     pub rect: egui::Rect, // in points
     pub grid_enabled: bool,
+    pub meshing_mode: MeshingMode,
     pub camera: Camera,
 }
 
@@ -64,11 +194,13 @@ impl Default for Viewport {
         Self {
             rect: egui::Rect::NOTHING,
             grid_enabled: true,
+            meshing_mode: MeshingMode::SurfaceNets,
             camera: Camera::default(),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Camera {
     pub position: glam::Vec3,
     pub pitch: f32,
@@ -133,3 +265,111 @@ impl Camera {
         (view, projection)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // all visible layers blended in order, from scratch
+    fn flatten(doc: &Document) -> VoxelGrid {
+        let mut flat_voxel_grid = VoxelGrid::new();
+        for layer in &doc.layers {
+            if !layer.visible {
+                continue;
+            }
+
+            match layer.blend_mode {
+                BlendMode::Add => {
+                    flat_voxel_grid.add(layer.displayed_voxel_grid());
+                }
+                BlendMode::Subtract => {
+                    flat_voxel_grid.subtract(layer.displayed_voxel_grid());
+                }
+                BlendMode::Intersect => {
+                    flat_voxel_grid.intersect(layer.displayed_voxel_grid());
+                }
+                BlendMode::Xor => {
+                    flat_voxel_grid.xor(layer.displayed_voxel_grid());
+                }
+                BlendMode::MaskToBelow => {
+                    flat_voxel_grid.replace(layer.displayed_voxel_grid());
+                }
+            }
+        }
+
+        flat_voxel_grid
+    }
+
+    // both grids give the same mesh, colors included
+    fn assert_same_voxels(composite: &Composite, doc: &Document) {
+        let palette = Palette::default();
+        let (vertices, indices) = composite
+            .voxel_grid
+            .generate_mesh(MeshingMode::Blocky, &palette);
+        let (flat_vertices, flat_indices) =
+            flatten(doc).generate_mesh(MeshingMode::Blocky, &palette);
+        assert!(!indices.is_empty());
+        assert!(
+            bytemuck::cast_slice::<_, u8>(&vertices)
+                == bytemuck::cast_slice::<_, u8>(&flat_vertices)
+                && indices == flat_indices
+        );
+    }
+
+    #[test]
+    fn composites_follow_the_edits_of_their_layers() {
+        let mut doc = Document::default();
+        doc.layers[0]
+            .voxel_grid
+            .paint_cube((-70, -10, -10), (70, 10, 10), 1);
+        doc.layers[1].voxel_grid.paint_sphere((0, 0, 0), 14.0, 2);
+        doc.layers[1].blend_mode = BlendMode::Subtract;
+        doc.layers[2]
+            .voxel_grid
+            .paint_cube((60, -20, -20), (80, 20, 0), 3);
+
+        let mut composite = Composite::new();
+        composite.update(&doc);
+        assert_same_voxels(&composite, &doc);
+
+        // edits only blend their own chunks again
+        doc.layers[0].voxel_grid.paint_sphere((-64, 0, 0), 9.0, 4);
+        composite.update(&doc);
+        assert_same_voxels(&composite, &doc);
+
+        // changes to the stack blend everything
+        doc.layers[2].blend_mode = BlendMode::Subtract;
+        composite.update(&doc);
+        assert_same_voxels(&composite, &doc);
+        doc.layers[1].visible = false;
+        composite.update(&doc);
+        assert_same_voxels(&composite, &doc);
+        doc.layers.remove(1);
+        composite.update(&doc);
+        assert_same_voxels(&composite, &doc);
+    }
+
+    #[test]
+    fn statistics_cover_the_composite_and_each_layer() {
+        let mut doc = Document::default();
+        doc.layers[0].voxel_grid.paint_cube((0, 0, 0), (9, 9, 9), 1);
+        doc.layers[1]
+            .voxel_grid
+            .paint_cube((5, 0, 0), (14, 9, 9), 2);
+        doc.layers[1].blend_mode = BlendMode::Subtract;
+
+        let mut composite = Composite::new();
+        composite.update(&doc);
+        let statistics = doc.statistics(&composite);
+        assert_eq!(statistics.voxels, 500);
+        assert_eq!(statistics.materials, vec![(1, 500)]);
+        assert_eq!(statistics.bounding_box, Some(((0, 0, 0), (4, 9, 9))));
+
+        // layers count the preview of the operation being set up on them
+        let mut preview = VoxelGrid::new();
+        preview.paint_cube((0, 0, 0), (1, 1, 1), 3);
+        doc.layers[1].preview = Some(preview);
+        assert_eq!(doc.layer_statistics(0).voxels, 1000);
+        assert_eq!(doc.layer_statistics(1).materials, vec![(3, 8)]);
+    }
+}
@@ -1,13 +1,22 @@
+mod import;
+mod mesh;
+mod operations;
 mod panels;
 mod state;
+mod statistics;
 mod tools;
 
 use egui::*;
 
 use crate::document;
+use crate::voxels::{self, Material};
 
+use self::import::*;
+use self::mesh::MeshPanel;
+use self::operations::*;
 use self::panels::*;
 use self::state::EditorState;
+use self::statistics::StatisticsPanel;
 
 pub struct Editor {
     state: EditorState,
@@ -16,6 +25,21 @@ pub struct Editor {
     selected_layer: usize,
     layer_rename: bool,
     layer_name: String,
+    selected_material: Material,
+    translation: (i32, i32, i32),
+
+    // voxels dropped at the world bounds by the last transform of the
+    // selected layer
+    clipped_voxels: Option<usize>,
+
+    operation_window: Option<OperationWindow>,
+    import_window: Option<ImportWindow>,
+
+    // where the stroke being dragged reached on the previous frame
+    stroke_position: Option<glam::Vec3>,
+
+    statistics: StatisticsPanel,
+    mesh: MeshPanel,
 }
 
 impl Editor {
@@ -34,6 +58,14 @@ impl Editor {
             selected_layer: 0,
             layer_rename: false,
             layer_name: String::new(),
+            selected_material: 0,
+            translation: (0, 0, 0),
+            clipped_voxels: None,
+            stroke_position: None,
+            operation_window: None,
+            import_window: None,
+            statistics: StatisticsPanel::new(),
+            mesh: MeshPanel::new(),
         }
     }
 
@@ -41,6 +73,7 @@ impl Editor {
         &mut self,
         ctx: &Context,
         doc: &mut document::Document,
+        composite: &document::Composite,
         keyboard_modifiers: winit::event::ModifiersState,
     ) {
         //self.egui_demo.ui(ctx);
@@ -50,15 +83,199 @@ impl Editor {
         TopBottomPanel::top("menu_bar").show(ctx, |ui| {
             menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    if ui.button("Import Heightmap...").clicked() {
+                        self.import_window = Some(ImportWindow::new(self.selected_material));
+                        ui.close_menu();
+                    }
+                    ui.separator();
                     if ui.button("Exit").clicked() {
                         std::process::exit(0);
                     }
                 });
+                ui.menu_button("Layer", |ui| {
+                    let voxel_grid = &mut doc.layers[self.selected_layer].voxel_grid;
+
+                    // rotations and mirrors keep the layer in place
+                    let pivot = match voxel_grid.bounding_box() {
+                        Some((min, max)) => {
+                            glam::vec3(
+                                (min.0 + max.0) as f32,
+                                (min.1 + max.1) as f32,
+                                (min.2 + max.2) as f32,
+                            ) * 0.5
+                        }
+                        None => glam::Vec3::ZERO,
+                    };
+
+                    ui.menu_button("Translate", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(DragValue::new(&mut self.translation.0).prefix("x "));
+                            ui.add(DragValue::new(&mut self.translation.1).prefix("y "));
+                            ui.add(DragValue::new(&mut self.translation.2).prefix("z "));
+                        });
+                        if ui.button("Apply").clicked() {
+                            self.clipped_voxels = Some(voxel_grid.translate(self.translation));
+                            ui.close_menu();
+                        }
+                    });
+                    ui.menu_button("Rotate", |ui| {
+                        for axis in voxels::Axis::ALL {
+                            for (quarter_turns, angle) in [(1, "90°"), (2, "180°"), (3, "270°")]
+                            {
+                                if ui.button(format!("{} {:?}", angle, axis)).clicked() {
+                                    self.clipped_voxels =
+                                        Some(voxel_grid.rotate(axis, quarter_turns, pivot));
+                                    ui.close_menu();
+                                }
+                            }
+                        }
+                    });
+                    ui.menu_button("Mirror", |ui| {
+                        for axis in voxels::Axis::ALL {
+                            if ui.button(format!("{:?}", axis)).clicked() {
+                                self.clipped_voxels = Some(voxel_grid.mirror(axis, pivot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.separator();
+                    if ui.button("Rotate and Scale...").clicked() {
+                        self.open_operation(
+                            doc,
+                            Operation::Transform {
+                                rotation: glam::Vec3::ZERO,
+                                scale: glam::Vec3::ONE,
+                                pivot,
+                                resampling: voxels::Resampling::Nearest,
+                            },
+                        );
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    for (morphology, label) in [
+                        (Morphology::Dilate, "Dilate..."),
+                        (Morphology::Erode, "Erode..."),
+                        (Morphology::Open, "Open..."),
+                        (Morphology::Close, "Close..."),
+                    ] {
+                        if ui.button(label).clicked() {
+                            self.open_operation(
+                                doc,
+                                Operation::Morphology {
+                                    morphology,
+                                    kernel: voxels::Kernel::Faces,
+                                    steps: 1,
+                                },
+                            );
+                            ui.close_menu();
+                        }
+                    }
+                    if ui.button("Smooth...").clicked() {
+                        let region = doc.layers[self.selected_layer]
+                            .voxel_grid
+                            .bounding_box()
+                            .unwrap_or_default();
+                        self.open_operation(
+                            doc,
+                            Operation::Smooth {
+                                size: voxels::FilterSize::Three,
+                                iterations: 1,
+                                whole_layer: true,
+                                region,
+                            },
+                        );
+                        ui.close_menu();
+                    }
+                    if ui.button("Fill Enclosed Cavities...").clicked() {
+                        self.open_operation(
+                            doc,
+                            Operation::FillCavities {
+                                connectivity: voxels::Connectivity::Faces,
+                            },
+                        );
+                        ui.close_menu();
+                    }
+                    if ui.button("Remove Islands...").clicked() {
+                        self.open_operation(
+                            doc,
+                            Operation::RemoveIslands {
+                                min_size: 100,
+                                keep_largest: false,
+                                connectivity: voxels::Connectivity::Faces,
+                            },
+                        );
+                        ui.close_menu();
+                    }
+                    if ui.button("Hollow...").clicked() {
+                        let bottom = match doc.layers[self.selected_layer].voxel_grid.bounding_box()
+                        {
+                            Some((min, _)) => glam::vec3(pivot.x, pivot.y, min.2 as f32 - 0.5),
+                            None => glam::Vec3::ZERO,
+                        };
+                        self.open_operation(
+                            doc,
+                            Operation::Hollow {
+                                thickness: 2,
+                                drain_holes: vec![],
+                                bottom,
+                            },
+                        );
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if ui.button("Generate Terrain...").clicked() {
+                        self.open_operation(
+                            doc,
+                            Operation::Terrain {
+                                terrain: voxels::Terrain {
+                                    kind: voxels::TerrainKind::Heightfield,
+                                    seed: 0,
+                                    octaves: 4,
+                                    frequency: 0.02,
+                                    threshold: 0.0,
+                                    cave_density: 0.0,
+                                    region: ((-64, -64, 0), (63, 63, 31)),
+                                },
+                                material: self.selected_material,
+                            },
+                        );
+                        ui.close_menu();
+                    }
+                });
             });
         });
 
         self.toolbar.show(ctx, &mut self.state);
 
+        if let Some(operation_window) = &mut self.operation_window {
+            match operation_window.show(ctx, &mut doc.layers) {
+                OperationState::Open => {}
+                OperationState::Applied { clipped_voxels } => {
+                    self.clipped_voxels = Some(clipped_voxels);
+                    self.operation_window = None;
+                }
+                OperationState::Cancelled => {
+                    self.operation_window = None;
+                }
+            }
+        }
+
+        if let Some(import_window) = &mut self.import_window {
+            match import_window.show(ctx) {
+                ImportState::Open => {}
+                ImportState::Imported(layer) => {
+                    doc.layers.push(layer);
+                    self.selected_layer = doc.layers.len() - 1;
+                    self.layer_rename = false;
+                    self.clipped_voxels = None;
+                    self.import_window = None;
+                }
+                ImportState::Cancelled => {
+                    self.import_window = None;
+                }
+            }
+        }
+
         SidePanel::right("side_panel")
             .default_width(200.0)
             .show(ctx, |ui| {
@@ -161,6 +378,7 @@ impl Editor {
                             if response.clicked() {
                                 self.selected_layer = i;
                                 self.layer_rename = false;
+                                self.clipped_voxels = None;
                             }
                             if response.double_clicked() {
                                 self.layer_name = layer.name.clone();
@@ -189,7 +407,72 @@ impl Editor {
                             document::BlendMode::Subtract,
                             "Subtract",
                         );
+                        ui.selectable_value(
+                            &mut selected_layer.blend_mode,
+                            document::BlendMode::Intersect,
+                            "Intersect",
+                        );
+                        ui.selectable_value(
+                            &mut selected_layer.blend_mode,
+                            document::BlendMode::Xor,
+                            "Xor",
+                        );
+                        ui.selectable_value(
+                            &mut selected_layer.blend_mode,
+                            document::BlendMode::MaskToBelow,
+                            "Mask to Below",
+                        );
+                    });
+                if let Some(clipped_voxels) = self.clipped_voxels {
+                    if clipped_voxels > 0 {
+                        ui.label(format!("{} voxels clipped", clipped_voxels));
+                    }
+                }
+
+                ui.separator();
+
+                // only computed while shown, as they need the whole mesh
+                CollapsingHeader::new("\u{f080} Statistics").show(ui, |ui| {
+                    self.statistics
+                        .show(ui, doc, composite, self.selected_layer);
+                });
+
+                ui.separator();
+
+                ui.strong("\u{f53f} Palette");
+                ui.horizontal_wrapped(|ui| {
+                    ui.spacing_mut().item_spacing = vec2(4.0, 4.0);
+                    for (i, color) in doc.palette.colors.iter().enumerate() {
+                        let is_selected = self.selected_material as usize == i;
+
+                        let (rect, response) =
+                            ui.allocate_exact_size(vec2(18.0, 18.0), Sense::click());
+
+                        if ui.is_rect_visible(rect) {
+                            let stroke = if is_selected {
+                                ui.visuals().selection.stroke
+                            } else {
+                                Stroke::NONE
+                            };
+                            ui.painter().rect(
+                                rect,
+                                2.0,
+                                Color32::from_rgb(color[0], color[1], color[2]),
+                                stroke,
+                            );
+                        }
+
+                        if response.clicked() {
+                            self.selected_material = i as Material;
+                        }
+                    }
+                });
+                if let Some(color) = doc.palette.colors.get_mut(self.selected_material as usize) {
+                    ui.horizontal(|ui| {
+                        ui.color_edit_button_srgb(color);
+                        ui.label(format!("Color {}", self.selected_material));
                     });
+                }
             });
 
         let response = CentralPanel::default()
@@ -236,55 +519,102 @@ impl Editor {
             })
         }
 
-        if response.inner.dragged_by(PointerButton::Primary) {
-            ctx.input(|input| {
-                if let Some(pos) = input.pointer.interact_pos() {
-                    // normalize viewport pos to clip space (y-inverted)
-                    let pos = 2.0 * (pos - doc.viewport.rect.min) / doc.viewport.rect.size()
-                        - egui::vec2(1.0, 1.0);
-
-                    let (view, projection) =
-                        camera.compute_matrices(doc.viewport.rect.aspect_ratio());
-
-                    // back-project clip space to world space
-                    let direction = view.transpose()
-                        * projection.inverse()
-                        * glam::vec4(pos.x, -pos.y, 0.5, 1.0);
-
-                    // intersect with plane y = 0
-                    if direction.z * camera.position.z < 0.0 {
-                        let ratio = -camera.position.z / direction.z;
-                        let intersection = egui::vec2(
-                            ratio * direction.x + camera.position.x,
-                            ratio * direction.y + camera.position.y,
-                        );
-                        let grid_position = intersection.round();
-
-                        if grid_position.x >= 0.0
-                            && grid_position.y >= 0.0
-                            && grid_position.x <= 63.0
-                            && grid_position.y <= 63.0
-                        {
-                            let x = grid_position.x as usize;
-                            let y = grid_position.y as usize;
-                            doc.layers[self.selected_layer]
-                                .voxel_grid
-                                .paint_sphere((x, y, 0), 10.0);
-                        }
+        // ray from the camera through the pointer, and the voxel it reaches
+        // on the ground plane
+        let mut pointer_ray = None;
+        let mut ground_voxel = None;
+        ctx.input(|input| {
+            if let Some(pos) = input.pointer.interact_pos() {
+                // normalize viewport pos to clip space (y-inverted)
+                let pos = 2.0 * (pos - doc.viewport.rect.min) / doc.viewport.rect.size()
+                    - egui::vec2(1.0, 1.0);
+
+                let (view, projection) = camera.compute_matrices(doc.viewport.rect.aspect_ratio());
+
+                // back-project clip space to world space
+                let direction =
+                    view.transpose() * projection.inverse() * glam::vec4(pos.x, -pos.y, 0.5, 1.0);
+                pointer_ray = Some((camera.position, direction.truncate()));
+
+                // intersect with plane y = 0
+                if direction.z * camera.position.z < 0.0 {
+                    let ratio = -camera.position.z / direction.z;
+                    let intersection = egui::vec2(
+                        ratio * direction.x + camera.position.x,
+                        ratio * direction.y + camera.position.y,
+                    );
+                    let grid_position = intersection.round();
+
+                    let world_limit = voxels::WORLD_LIMIT as f32;
+                    if grid_position.x >= -world_limit
+                        && grid_position.y >= -world_limit
+                        && grid_position.x < world_limit
+                        && grid_position.y < world_limit
+                    {
+                        ground_voxel = Some((grid_position.x as i32, grid_position.y as i32, 0));
                     }
                 }
-            });
+            }
+        });
+
+        let fills_region = self.state.tools[self.state.selected_tool].fills_region();
+
+        // strokes join the positions of consecutive frames, leaving no gaps
+        // however far the pointer moved in between
+        if response.inner.dragged_by(PointerButton::Primary) && !fills_region {
+            if let Some(ground_voxel) = ground_voxel {
+                let position = glam::vec3(
+                    ground_voxel.0 as f32,
+                    ground_voxel.1 as f32,
+                    ground_voxel.2 as f32,
+                );
+                let points: Vec<voxels::StrokePoint> = self
+                    .stroke_position
+                    .into_iter()
+                    .chain([position])
+                    .map(|position| voxels::StrokePoint {
+                        position,
+                        radius: 10.0,
+                    })
+                    .collect();
+                doc.layers[self.selected_layer]
+                    .voxel_grid
+                    .paint_stroke(&voxels::Stroke::polyline(&points), self.selected_material);
+                self.stroke_position = Some(position);
+            }
+        } else {
+            self.stroke_position = None;
         }
 
+        let mut bucket_fill = None;
         if response.inner.clicked_by(PointerButton::Primary) {
-            let pos = (
-                rand::random::<usize>() % 20 + 25,
-                rand::random::<usize>() % 20 + 25,
-                rand::random::<usize>() % 20 + 5,
-            );
-            doc.layers[self.selected_layer]
-                .voxel_grid
-                .paint_sphere(pos, 2.3);
+            if fills_region {
+                // fills from the surface under the pointer, or from the
+                // ground when there is none
+                let voxel_grid = &doc.layers[self.selected_layer].voxel_grid;
+                let seed = pointer_ray
+                    .and_then(|(origin, direction)| {
+                        voxel_grid.raycast(origin, direction, f32::INFINITY)
+                    })
+                    .map(|hit| hit.voxel)
+                    .or(ground_voxel);
+                bucket_fill = seed.map(|seed| Operation::FloodFill {
+                    seed,
+                    connectivity: voxels::Connectivity::Faces,
+                    material: self.selected_material,
+                });
+            } else {
+                let pos = (
+                    rand::random::<i32>().rem_euclid(20) + 25,
+                    rand::random::<i32>().rem_euclid(20) + 25,
+                    rand::random::<i32>().rem_euclid(20) + 5,
+                );
+                doc.layers[self.selected_layer].voxel_grid.paint_sphere(
+                    pos,
+                    2.3,
+                    self.selected_material,
+                );
+            }
         }
 
         let window_margin = ctx.style().spacing.window_margin.left;
@@ -360,6 +690,56 @@ impl Editor {
                 ui.separator();
                 ui.strong("Display");
                 ui.checkbox(&mut doc.viewport.grid_enabled, "Grid");
+                egui::ComboBox::from_label("Meshing")
+                    .selected_text(format!("{:?}", doc.viewport.meshing_mode))
+                    .show_ui(ui, |ui| {
+                        ui.style_mut().wrap = Some(false);
+                        ui.set_min_width(60.0);
+                        ui.selectable_value(
+                            &mut doc.viewport.meshing_mode,
+                            voxels::MeshingMode::SurfaceNets,
+                            "Surface Nets",
+                        );
+                        ui.selectable_value(
+                            &mut doc.viewport.meshing_mode,
+                            voxels::MeshingMode::Blocky,
+                            "Blocky",
+                        );
+                        ui.selectable_value(
+                            &mut doc.viewport.meshing_mode,
+                            voxels::MeshingMode::Greedy,
+                            "Greedy",
+                        );
+                        ui.selectable_value(
+                            &mut doc.viewport.meshing_mode,
+                            voxels::MeshingMode::MarchingCubes,
+                            "Marching Cubes",
+                        );
+                        ui.selectable_value(
+                            &mut doc.viewport.meshing_mode,
+                            voxels::MeshingMode::DualContouring,
+                            "Dual Contouring",
+                        );
+                    });
+                self.mesh.show(
+                    ui,
+                    &composite.voxel_grid,
+                    doc.viewport.meshing_mode,
+                    &doc.palette,
+                    doc.voxel_size,
+                );
             });
+
+        if let Some(operation) = bucket_fill {
+            self.open_operation(doc, operation);
+        }
+    }
+
+    // replaces the operation being set up, if any, on the selected layer
+    fn open_operation(&mut self, doc: &mut document::Document, operation: Operation) {
+        if let Some(operation_window) = self.operation_window.take() {
+            operation_window.cancel(&mut doc.layers);
+        }
+        self.operation_window = Some(OperationWindow::new(self.selected_layer, operation));
     }
 }
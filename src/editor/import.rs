@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use egui::*;
+
+use crate::document::Layer;
+use crate::voxels::{Heightmap, HeightmapImport, Material, VoxelGrid};
+
+pub enum ImportState {
+    Open,
+    Imported(Layer),
+    Cancelled,
+}
+
+// loads a grayscale image, and turns it into a new layer once set up
+pub struct ImportWindow {
+    path: String,
+    heightmap: Option<Heightmap>,
+    error: Option<String>,
+    import: HeightmapImport,
+    material: Material,
+}
+
+impl ImportWindow {
+    pub fn new(material: Material) -> Self {
+        Self {
+            path: String::new(),
+            heightmap: None,
+            error: None,
+            import: HeightmapImport {
+                max_height: 32,
+                invert: false,
+                crop: ((0, 0), (0, 0)),
+            },
+            material,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &Context) -> ImportState {
+        let (mut import, mut cancel) = (false, false);
+        Window::new("Import Heightmap")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("PNG File");
+                    ui.text_edit_singleline(&mut self.path);
+                    if ui.button("Load").clicked() {
+                        self.load();
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+
+                if let Some(heightmap) = &self.heightmap {
+                    ui.label(format!("{} x {} pixels", heightmap.width, heightmap.height));
+                    let (max_x, max_y) = (heightmap.width - 1, heightmap.height - 1);
+                    let ((min_crop_x, min_crop_y), (max_crop_x, max_crop_y)) =
+                        &mut self.import.crop;
+
+                    Grid::new("import_heightmap_grid")
+                        .num_columns(3)
+                        .spacing([8.0, 8.0])
+                        .show(ui, |ui| {
+                            ui.label("Max Height");
+                            ui.add(
+                                DragValue::new(&mut self.import.max_height)
+                                    .clamp_range(1..=1024)
+                                    .suffix(" voxels"),
+                            );
+                            ui.checkbox(&mut self.import.invert, "Invert");
+                            ui.end_row();
+
+                            ui.label("Crop Min");
+                            ui.add(DragValue::new(min_crop_x).clamp_range(0..=max_x));
+                            ui.add(DragValue::new(min_crop_y).clamp_range(0..=max_y));
+                            ui.end_row();
+
+                            ui.label("Crop Max");
+                            ui.add(DragValue::new(max_crop_x).clamp_range(0..=max_x));
+                            ui.add(DragValue::new(max_crop_y).clamp_range(0..=max_y));
+                            ui.end_row();
+                        });
+                }
+
+                ui.horizontal(|ui| {
+                    import = ui
+                        .add_enabled(self.heightmap.is_some(), Button::new("Import"))
+                        .clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        match &self.heightmap {
+            Some(heightmap) if import => {
+                let path = Path::new(&self.path);
+                ImportState::Imported(Layer {
+                    name: path
+                        .file_stem()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| "Heightmap".to_string()),
+                    voxel_grid: VoxelGrid::from_heightmap(heightmap, &self.import, self.material),
+                    ..Default::default()
+                })
+            }
+            _ if cancel => ImportState::Cancelled,
+            _ => ImportState::Open,
+        }
+    }
+
+    // the whole image is imported until cropped
+    fn load(&mut self) {
+        match Heightmap::load(Path::new(&self.path)) {
+            Ok(heightmap) => {
+                self.import.crop = ((0, 0), (heightmap.width - 1, heightmap.height - 1));
+                self.heightmap = Some(heightmap);
+                self.error = None;
+            }
+            Err(error) => {
+                self.heightmap = None;
+                self.error = Some(error.to_string());
+            }
+        }
+    }
+}
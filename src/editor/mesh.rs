@@ -0,0 +1,219 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::mpsc;
+
+use egui::*;
+
+use crate::voxels::{
+    decimate_mesh, validate_mesh, write_obj, DecimationTarget, MeshReport, MeshingMode, Palette,
+    VertexData, VoxelGrid,
+};
+
+// checks and decimates the mesh of the composite of all layers, and exports
+// the last one built; meshing the whole composite takes too long for the ui,
+// so it is done by a job in the background
+pub struct MeshPanel {
+    decimation: DecimationTarget,
+    mesh: Option<CheckedMesh>,
+    job: Option<MeshJob>,
+    path: String,
+
+    // outcome of the last export
+    exported: Option<Result<usize, String>>,
+}
+
+// id and revision of the composite, meshing mode, and decimation target when
+// the mesh was decimated
+type MeshKey = (u64, u64, MeshingMode, Option<DecimationTarget>);
+
+struct CheckedMesh {
+    key: MeshKey,
+    vertices: Vec<VertexData>,
+    indices: Vec<u32>,
+    report: MeshReport,
+
+    // triangles of the mesh before decimation, when it was decimated
+    decimated_from: Option<usize>,
+}
+
+struct MeshJob {
+    key: MeshKey,
+    receiver: mpsc::Receiver<CheckedMesh>,
+}
+
+impl MeshJob {
+    fn spawn(key: MeshKey, voxel_grid: VoxelGrid, palette: Palette) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        rayon::spawn(move || {
+            let (vertices, indices) = voxel_grid.generate_mesh(key.2, &palette);
+            let (vertices, indices, decimated_from) = match key.3 {
+                Some(target) => {
+                    let (vertices, decimated) = decimate_mesh(&vertices, &indices, target);
+                    (vertices, decimated, Some(indices.len() / 3))
+                }
+                None => (vertices, indices, None),
+            };
+            let report = validate_mesh(&vertices, &indices);
+            sender
+                .send(CheckedMesh {
+                    key,
+                    vertices,
+                    indices,
+                    report,
+                    decimated_from,
+                })
+                .ok();
+        });
+
+        Self { key, receiver }
+    }
+}
+
+impl MeshPanel {
+    pub fn new() -> Self {
+        Self {
+            decimation: DecimationTarget::Triangles(10000),
+            mesh: None,
+            job: None,
+            path: "mesh.obj".to_string(),
+            exported: None,
+        }
+    }
+
+    // voxel_size scales the exported mesh to millimeters
+    pub fn show(
+        &mut self,
+        ui: &mut Ui,
+        composite: &VoxelGrid,
+        mode: MeshingMode,
+        palette: &Palette,
+        voxel_size: f32,
+    ) {
+        if let Some(job) = &self.job {
+            match job.receiver.try_recv() {
+                Ok(mesh) => {
+                    self.job = None;
+                    self.mesh = Some(mesh);
+                    self.exported = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => self.job = None,
+            }
+        }
+
+        let (mut check, mut decimate) = (false, false);
+        ui.add_enabled_ui(self.job.is_none(), |ui| {
+            ui.horizontal(|ui| {
+                check = ui.button("Check Mesh").clicked();
+                decimate = ui.button("Decimate").clicked();
+            });
+            ui.horizontal(|ui| {
+                let triangles = matches!(self.decimation, DecimationTarget::Triangles(_));
+                if ui.radio(triangles, "Triangles").clicked() && !triangles {
+                    self.decimation = DecimationTarget::Triangles(10000);
+                }
+                if ui.radio(!triangles, "Error").clicked() && triangles {
+                    self.decimation = DecimationTarget::Error(0.1);
+                }
+                match &mut self.decimation {
+                    DecimationTarget::Triangles(triangles) => {
+                        ui.add(DragValue::new(triangles).speed(100));
+                    }
+                    DecimationTarget::Error(error) => {
+                        ui.add(
+                            DragValue::new(error)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::MAX)
+                                .suffix(" voxels"),
+                        );
+                    }
+                }
+            });
+        });
+
+        if check || decimate {
+            let key = (
+                composite.id(),
+                composite.revision(),
+                mode,
+                decimate.then_some(self.decimation),
+            );
+            self.job = Some(MeshJob::spawn(key, composite.clone(), palette.clone()));
+        }
+
+        if let Some(job) = &self.job {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(match job.key.3 {
+                    Some(_) => "Decimating",
+                    None => "Meshing",
+                });
+            });
+        }
+
+        let mesh = match &self.mesh {
+            Some(mesh) => mesh,
+            None => return,
+        };
+
+        let report = &mesh.report;
+        ui.label(if report.is_watertight() {
+            "Watertight"
+        } else {
+            "Not watertight"
+        });
+        if let Some(triangles) = mesh.decimated_from {
+            ui.label(format!("Decimated from {} triangles", triangles));
+        }
+        if (mesh.key.0, mesh.key.1, mesh.key.2) != (composite.id(), composite.revision(), mode) {
+            ui.label("Layers edited since");
+        }
+        Grid::new("mesh_report_grid")
+            .num_columns(2)
+            .spacing([8.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("Triangles");
+                ui.label(report.triangles.to_string());
+                ui.end_row();
+
+                ui.label("Boundary edges");
+                ui.label(report.boundary_edges.to_string());
+                ui.end_row();
+
+                ui.label("Non-manifold edges");
+                ui.label(report.non_manifold_edges.to_string());
+                ui.end_row();
+
+                ui.label("Degenerate triangles");
+                ui.label(report.degenerate_triangles.to_string());
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            ui.label("OBJ File");
+            ui.text_edit_singleline(&mut self.path);
+        });
+        if ui.button("Export").clicked() {
+            self.exported = Some(
+                export(Path::new(&self.path), mesh, voxel_size)
+                    .map(|_| mesh.report.triangles)
+                    .map_err(|error| error.to_string()),
+            );
+        }
+        match &self.exported {
+            Some(Ok(triangles)) => {
+                ui.label(format!("Exported {} triangles", triangles));
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+    }
+}
+
+fn export(path: &Path, mesh: &CheckedMesh, voxel_size: f32) -> std::io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    write_obj(writer, &mesh.vertices, &mesh.indices, voxel_size)
+}
@@ -0,0 +1,620 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use egui::*;
+
+use crate::document::Layer;
+use crate::voxels::{
+    Connectivity, Coords, DrainHole, FilterSize, Kernel, Material, Resampling, Terrain,
+    TerrainKind, VoxelGrid,
+};
+
+// operations set up in their own window, and previewed on the layer until
+// they are applied
+#[derive(Clone, PartialEq)]
+pub enum Operation {
+    Transform {
+        rotation: glam::Vec3, // in degrees, applied around x, then y, then z
+        scale: glam::Vec3,
+        pivot: glam::Vec3,
+        resampling: Resampling,
+    },
+    Morphology {
+        morphology: Morphology,
+        kernel: Kernel,
+        steps: u32,
+    },
+    Smooth {
+        size: FilterSize,
+        iterations: u32,
+        whole_layer: bool,
+        region: (Coords, Coords),
+    },
+    Hollow {
+        thickness: u32,
+        drain_holes: Vec<DrainHole>,
+
+        // where new drain holes start, below the middle of the layer
+        bottom: glam::Vec3,
+    },
+    RemoveIslands {
+        min_size: usize,
+        keep_largest: bool,
+        connectivity: Connectivity,
+    },
+    Terrain {
+        terrain: Terrain,
+        material: Material,
+    },
+    FillCavities {
+        connectivity: Connectivity,
+    },
+    FloodFill {
+        seed: Coords,
+        connectivity: Connectivity,
+        material: Material,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Morphology {
+    Dilate,
+    Erode,
+    Open,
+    Close,
+}
+
+impl Operation {
+    fn title(&self) -> &'static str {
+        match self {
+            Operation::Transform { .. } => "Rotate and Scale",
+            Operation::Morphology { .. } => "Morphology",
+            Operation::Smooth { .. } => "Smooth",
+            Operation::Hollow { .. } => "Hollow",
+            Operation::RemoveIslands { .. } => "Remove Islands",
+            Operation::Terrain { .. } => "Generate Terrain",
+            Operation::FillCavities { .. } => "Fill Enclosed Cavities",
+            Operation::FloodFill { .. } => "Bucket Fill",
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        match self {
+            Operation::Transform {
+                rotation,
+                scale,
+                pivot,
+                resampling,
+            } => {
+                Grid::new("transform_operation_grid")
+                    .num_columns(4)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.label("Rotation");
+                        for angle in [&mut rotation.x, &mut rotation.y, &mut rotation.z] {
+                            ui.add(DragValue::new(angle).speed(1.0).suffix("°"));
+                        }
+                        ui.end_row();
+
+                        ui.label("Scale");
+                        for factor in [&mut scale.x, &mut scale.y, &mut scale.z] {
+                            ui.add(DragValue::new(factor).speed(0.01).clamp_range(0.01..=10.0));
+                        }
+                        ui.end_row();
+
+                        ui.label("Pivot");
+                        for coordinate in [&mut pivot.x, &mut pivot.y, &mut pivot.z] {
+                            ui.add(DragValue::new(coordinate).speed(0.5));
+                        }
+                        ui.end_row();
+                    });
+
+                ComboBox::from_label("Resampling")
+                    .selected_text(format!("{:?}", resampling))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(resampling, Resampling::Nearest, "Nearest");
+                        ui.selectable_value(resampling, Resampling::Majority, "Majority");
+                    });
+            }
+            Operation::Morphology {
+                morphology,
+                kernel,
+                steps,
+            } => {
+                ComboBox::from_label("Operation")
+                    .selected_text(format!("{:?}", morphology))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(morphology, Morphology::Dilate, "Dilate");
+                        ui.selectable_value(morphology, Morphology::Erode, "Erode");
+                        ui.selectable_value(morphology, Morphology::Open, "Open");
+                        ui.selectable_value(morphology, Morphology::Close, "Close");
+                    });
+
+                let radius = match kernel {
+                    Kernel::Sphere(radius) => *radius,
+                    _ => 2.0,
+                };
+                ComboBox::from_label("Kernel")
+                    .selected_text(match kernel {
+                        Kernel::Faces => "6 Neighbors",
+                        Kernel::Cube => "26 Neighbors",
+                        Kernel::Sphere(_) => "Sphere",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(kernel, Kernel::Faces, "6 Neighbors");
+                        ui.selectable_value(kernel, Kernel::Cube, "26 Neighbors");
+                        ui.selectable_value(kernel, Kernel::Sphere(radius), "Sphere");
+                    });
+
+                Grid::new("morphology_operation_grid")
+                    .num_columns(2)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        if let Kernel::Sphere(radius) = kernel {
+                            ui.label("Radius");
+                            ui.add(DragValue::new(radius).speed(0.1).clamp_range(1.0..=16.0));
+                            ui.end_row();
+                        }
+
+                        ui.label("Steps");
+                        ui.add(DragValue::new(steps).speed(0.1).clamp_range(1..=32));
+                        ui.end_row();
+                    });
+            }
+            Operation::Smooth {
+                size,
+                iterations,
+                whole_layer,
+                region,
+            } => {
+                ComboBox::from_label("Filter")
+                    .selected_text(match size {
+                        FilterSize::Three => "3x3x3",
+                        FilterSize::Five => "5x5x5",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(size, FilterSize::Three, "3x3x3");
+                        ui.selectable_value(size, FilterSize::Five, "5x5x5");
+                    });
+
+                Grid::new("smooth_operation_grid")
+                    .num_columns(4)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.label("Iterations");
+                        ui.add(DragValue::new(iterations).speed(0.1).clamp_range(1..=16));
+                        ui.end_row();
+
+                        ui.label("Whole Layer");
+                        ui.checkbox(whole_layer, "");
+                        ui.end_row();
+
+                        if !*whole_layer {
+                            let (min, max) = region;
+                            for (label, corner) in [("Min", min), ("Max", max)] {
+                                ui.label(label);
+                                for coordinate in [&mut corner.0, &mut corner.1, &mut corner.2] {
+                                    ui.add(DragValue::new(coordinate));
+                                }
+                                ui.end_row();
+                            }
+                        }
+                    });
+            }
+            Operation::Hollow {
+                thickness,
+                drain_holes,
+                bottom,
+            } => {
+                Grid::new("hollow_operation_grid")
+                    .num_columns(4)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.label("Wall Thickness");
+                        ui.add(DragValue::new(thickness).speed(0.1).clamp_range(1..=32));
+                        ui.end_row();
+                    });
+
+                let mut removed = None;
+                for (index, drain_hole) in drain_holes.iter_mut().enumerate() {
+                    ui.separator();
+                    Grid::new(("drain_hole_grid", index))
+                        .num_columns(4)
+                        .spacing([8.0, 8.0])
+                        .show(ui, |ui| {
+                            ui.label("Position");
+                            let position = &mut drain_hole.position;
+                            for coordinate in [&mut position.x, &mut position.y, &mut position.z] {
+                                ui.add(DragValue::new(coordinate).speed(0.5));
+                            }
+                            ui.end_row();
+
+                            ui.label("Direction");
+                            let direction = &mut drain_hole.direction;
+                            for coordinate in [&mut direction.x, &mut direction.y, &mut direction.z]
+                            {
+                                ui.add(DragValue::new(coordinate).speed(0.01));
+                            }
+                            ui.end_row();
+
+                            ui.label("Radius");
+                            ui.add(
+                                DragValue::new(&mut drain_hole.radius)
+                                    .speed(0.1)
+                                    .clamp_range(0.5..=32.0),
+                            );
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                            ui.end_row();
+                        });
+                }
+                if let Some(index) = removed {
+                    drain_holes.remove(index);
+                }
+
+                ui.separator();
+                if ui.button("Add Drain Hole").clicked() {
+                    drain_holes.push(DrainHole {
+                        position: *bottom,
+                        direction: glam::Vec3::Z,
+                        radius: 2.0,
+                    });
+                }
+            }
+            Operation::RemoveIslands {
+                min_size,
+                keep_largest,
+                connectivity,
+            } => {
+                connectivity_ui(ui, connectivity);
+
+                Grid::new("remove_islands_operation_grid")
+                    .num_columns(2)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.label("Keep Largest Only");
+                        ui.checkbox(keep_largest, "");
+                        ui.end_row();
+
+                        if !*keep_largest {
+                            ui.label("Minimum Size");
+                            ui.add(DragValue::new(min_size).speed(1.0).clamp_range(1..=1000000));
+                            ui.end_row();
+                        }
+                    });
+            }
+            Operation::Terrain { terrain, .. } => {
+                ComboBox::from_label("Noise")
+                    .selected_text(match terrain.kind {
+                        TerrainKind::Heightfield => "2D Heightfield",
+                        TerrainKind::Volume => "3D Volume",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut terrain.kind,
+                            TerrainKind::Heightfield,
+                            "2D Heightfield",
+                        );
+                        ui.selectable_value(&mut terrain.kind, TerrainKind::Volume, "3D Volume");
+                    });
+
+                Grid::new("terrain_operation_grid")
+                    .num_columns(4)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.label("Seed");
+                        ui.add(DragValue::new(&mut terrain.seed));
+                        ui.end_row();
+
+                        ui.label("Octaves");
+                        ui.add(
+                            DragValue::new(&mut terrain.octaves)
+                                .speed(0.1)
+                                .clamp_range(1..=8),
+                        );
+                        ui.end_row();
+
+                        ui.label("Frequency");
+                        ui.add(
+                            DragValue::new(&mut terrain.frequency)
+                                .speed(0.001)
+                                .clamp_range(0.001..=0.5),
+                        );
+                        ui.end_row();
+
+                        ui.label("Threshold");
+                        ui.add(
+                            DragValue::new(&mut terrain.threshold)
+                                .speed(0.01)
+                                .clamp_range(-1.0..=1.0),
+                        );
+                        ui.end_row();
+
+                        ui.label("Cave Density");
+                        ui.add(
+                            DragValue::new(&mut terrain.cave_density)
+                                .speed(0.01)
+                                .clamp_range(0.0..=1.0),
+                        );
+                        ui.end_row();
+
+                        let (min, max) = &mut terrain.region;
+                        for (label, corner) in [("Min", min), ("Max", max)] {
+                            ui.label(label);
+                            for coordinate in [&mut corner.0, &mut corner.1, &mut corner.2] {
+                                ui.add(DragValue::new(coordinate));
+                            }
+                            ui.end_row();
+                        }
+                    });
+            }
+            Operation::FillCavities { connectivity } => connectivity_ui(ui, connectivity),
+            Operation::FloodFill {
+                seed, connectivity, ..
+            } => {
+                connectivity_ui(ui, connectivity);
+
+                Grid::new("flood_fill_operation_grid")
+                    .num_columns(4)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.label("Seed");
+                        for coordinate in [&mut seed.0, &mut seed.1, &mut seed.2] {
+                            ui.add(DragValue::new(coordinate));
+                        }
+                        ui.end_row();
+                    });
+            }
+        }
+    }
+
+    // returns how many solid voxels were dropped at the world bounds
+    fn apply(&self, voxel_grid: &mut VoxelGrid) -> usize {
+        match self {
+            Operation::Transform {
+                rotation,
+                scale,
+                pivot,
+                resampling,
+            } => {
+                let rotation = glam::Quat::from_euler(
+                    glam::EulerRot::ZYX,
+                    rotation.z.to_radians(),
+                    rotation.y.to_radians(),
+                    rotation.x.to_radians(),
+                );
+                voxel_grid.resample(rotation, *scale, *pivot, *resampling)
+            }
+            Operation::Morphology {
+                morphology,
+                kernel,
+                steps,
+            } => {
+                match morphology {
+                    Morphology::Dilate => voxel_grid.dilate(*kernel, *steps),
+                    Morphology::Erode => voxel_grid.erode(*kernel, *steps),
+                    Morphology::Open => voxel_grid.open(*kernel, *steps),
+                    Morphology::Close => voxel_grid.close(*kernel, *steps),
+                }
+                0
+            }
+            Operation::Smooth {
+                size,
+                iterations,
+                whole_layer,
+                region,
+            } => {
+                voxel_grid.smooth(*size, *iterations, (!whole_layer).then_some(*region));
+                0
+            }
+            Operation::Hollow {
+                thickness,
+                drain_holes,
+                ..
+            } => {
+                voxel_grid.hollow(*thickness, drain_holes);
+                0
+            }
+            Operation::RemoveIslands {
+                min_size,
+                keep_largest,
+                connectivity,
+            } => {
+                if *keep_largest {
+                    voxel_grid.keep_largest_component(*connectivity);
+                } else {
+                    voxel_grid.remove_islands(*min_size, *connectivity);
+                }
+                0
+            }
+            Operation::Terrain { terrain, material } => {
+                voxel_grid.generate_terrain(terrain, *material);
+                0
+            }
+            Operation::FillCavities { connectivity } => {
+                voxel_grid.fill_cavities(*connectivity);
+                0
+            }
+            Operation::FloodFill {
+                seed,
+                connectivity,
+                material,
+            } => {
+                voxel_grid.flood_fill(*seed, *connectivity, *material);
+                0
+            }
+        }
+    }
+}
+
+fn connectivity_ui(ui: &mut Ui, connectivity: &mut Connectivity) {
+    ComboBox::from_label("Connectivity")
+        .selected_text(match connectivity {
+            Connectivity::Faces => "6 Neighbors",
+            Connectivity::Corners => "26 Neighbors",
+        })
+        .show_ui(ui, |ui| {
+            ui.selectable_value(connectivity, Connectivity::Faces, "6 Neighbors");
+            ui.selectable_value(connectivity, Connectivity::Corners, "26 Neighbors");
+        });
+}
+
+pub enum OperationState {
+    Open,
+    Applied { clipped_voxels: usize },
+    Cancelled,
+}
+
+pub struct OperationWindow {
+    layer: usize,
+    operation: Operation,
+
+    // what the preview shown on the layer was built from
+    previewed: Option<PreviewKey>,
+    clipped_voxels: usize,
+
+    // preview being built in the background; the previous one stays shown
+    // until it is ready
+    job: Option<PreviewJob>,
+}
+
+// grid id and revision of the layer, and the operation applied to it
+type PreviewKey = (u64, u64, Operation);
+
+struct PreviewJob {
+    key: PreviewKey,
+
+    // set once the job is outdated; a job that has not started yet then
+    // skips its work, and one that has is left to finish
+    cancelled: Arc<AtomicBool>,
+
+    receiver: mpsc::Receiver<(VoxelGrid, usize)>,
+}
+
+impl PreviewJob {
+    fn spawn(key: PreviewKey, voxel_grid: VoxelGrid) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let operation = key.2.clone();
+        let job_cancelled = cancelled.clone();
+        rayon::spawn(move || {
+            if job_cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut preview = voxel_grid;
+            let clipped_voxels = operation.apply(&mut preview);
+            sender.send((preview, clipped_voxels)).ok();
+        });
+
+        Self {
+            key,
+            cancelled,
+            receiver,
+        }
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl OperationWindow {
+    pub fn new(layer: usize, operation: Operation) -> Self {
+        Self {
+            layer,
+            operation,
+            previewed: None,
+            clipped_voxels: 0,
+            job: None,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &Context, layers: &mut [Layer]) -> OperationState {
+        let layer = match layers.get_mut(self.layer) {
+            Some(layer) => layer,
+            None => {
+                self.cancel_job();
+                return OperationState::Cancelled;
+            }
+        };
+
+        if let Some(job) = &self.job {
+            match job.receiver.try_recv() {
+                Ok((preview, clipped_voxels)) => {
+                    let job = self.job.take().unwrap();
+                    layer.preview = Some(preview);
+                    self.clipped_voxels = clipped_voxels;
+                    self.previewed = Some(job.key);
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => self.job = None,
+            }
+        }
+
+        let (id, revision) = (layer.voxel_grid.id(), layer.voxel_grid.revision());
+        let (mut apply, mut cancel) = (false, false);
+        Window::new(self.operation.title())
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.operation.ui(ui);
+
+                // only a preview of the current settings can be applied
+                let key = (id, revision, self.operation.clone());
+                let ready = self.previewed.as_ref() == Some(&key);
+                if !ready {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Updating preview");
+                    });
+                } else if self.clipped_voxels > 0 {
+                    ui.label(format!("{} voxels clipped", self.clipped_voxels));
+                }
+                ui.horizontal(|ui| {
+                    apply = ui.add_enabled(ready, Button::new("Apply")).clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        // the preview follows the settings, and edits made to the layer; one
+        // job runs at a time, and the next one starts from the latest
+        // settings once it is done
+        let key = (id, revision, self.operation.clone());
+        match &self.job {
+            Some(job) if job.key != key => job.cancel(),
+            Some(_) => {}
+            None if self.previewed.as_ref() != Some(&key) => {
+                self.job = Some(PreviewJob::spawn(key, layer.voxel_grid.clone()));
+            }
+            None => {}
+        }
+
+        if apply {
+            self.cancel_job();
+            layer.voxel_grid = layer.preview.take().unwrap();
+            OperationState::Applied {
+                clipped_voxels: self.clipped_voxels,
+            }
+        } else if cancel {
+            self.cancel_job();
+            layer.preview = None;
+            OperationState::Cancelled
+        } else {
+            OperationState::Open
+        }
+    }
+
+    pub fn cancel(mut self, layers: &mut [Layer]) {
+        self.cancel_job();
+        if let Some(layer) = layers.get_mut(self.layer) {
+            layer.preview = None;
+        }
+    }
+
+    fn cancel_job(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.cancel();
+        }
+    }
+}
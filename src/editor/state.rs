@@ -10,8 +10,11 @@ pub struct EditorState {
 
 impl Default for EditorState {
     fn default() -> Self {
-        let tools: Vec<Box<dyn tools::Tool>> =
-            vec![Box::new(tools::Paintbrush {}), Box::new(tools::Eraser {})];
+        let tools: Vec<Box<dyn tools::Tool>> = vec![
+            Box::new(tools::Paintbrush {}),
+            Box::new(tools::Eraser {}),
+            Box::new(tools::Bucket {}),
+        ];
 
         Self {
             tools,
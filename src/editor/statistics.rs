@@ -0,0 +1,178 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use egui::*;
+
+use crate::document::{Composite, Document};
+use crate::voxels::{MeshingMode, Statistics};
+
+// statistics need the whole mesh, so they are computed away from the ui, and
+// not more often than this while edits keep coming
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+// figures about the selected layer and the composite of all layers, kept
+// until anything they were computed from changes
+pub struct StatisticsPanel {
+    shown: Option<(StatisticsKey, Statistics, Statistics)>,
+    job: Option<StatisticsJob>,
+    last_started: Option<Instant>,
+}
+
+// id and revision of the selected layer and of the composite, meshing mode
+// and voxel size
+type StatisticsKey = (u64, u64, u64, u64, MeshingMode, f32);
+
+struct StatisticsJob {
+    key: StatisticsKey,
+    receiver: mpsc::Receiver<(Statistics, Statistics)>,
+}
+
+impl StatisticsJob {
+    // the job works on copies of the document and composite, which share
+    // their chunks with the originals
+    fn spawn(
+        key: StatisticsKey,
+        doc: Document,
+        composite: Composite,
+        selected_layer: usize,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        rayon::spawn(move || {
+            let layer_statistics = doc.layer_statistics(selected_layer);
+            let statistics = doc.statistics(&composite);
+            sender.send((layer_statistics, statistics)).ok();
+        });
+
+        Self { key, receiver }
+    }
+}
+
+impl StatisticsPanel {
+    pub fn new() -> Self {
+        Self {
+            shown: None,
+            job: None,
+            last_started: None,
+        }
+    }
+
+    pub fn show(
+        &mut self,
+        ui: &mut Ui,
+        doc: &mut Document,
+        composite: &Composite,
+        selected_layer: usize,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Voxel size");
+            ui.add(
+                DragValue::new(&mut doc.voxel_size)
+                    .speed(0.01)
+                    .clamp_range(0.001..=100.0)
+                    .suffix(" mm"),
+            );
+        });
+
+        let layer = match doc.layers.get(selected_layer) {
+            Some(layer) => layer.displayed_voxel_grid(),
+            None => return,
+        };
+
+        if let Some(job) = &self.job {
+            match job.receiver.try_recv() {
+                Ok((layer_statistics, statistics)) => {
+                    let job = self.job.take().unwrap();
+                    self.shown = Some((job.key, layer_statistics, statistics));
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => self.job = None,
+            }
+        }
+
+        // one job runs at a time, and the next one starts from the latest
+        // grids once it is done
+        let key = (
+            layer.id(),
+            layer.revision(),
+            composite.voxel_grid.id(),
+            composite.voxel_grid.revision(),
+            doc.viewport.meshing_mode,
+            doc.voxel_size,
+        );
+        let up_to_date = matches!(&self.shown, Some((shown, _, _)) if *shown == key);
+        let waited = self
+            .last_started
+            .is_none_or(|last_started| last_started.elapsed() >= MIN_INTERVAL);
+        if !up_to_date && self.job.is_none() && waited {
+            self.job = Some(StatisticsJob::spawn(
+                key,
+                doc.clone(),
+                composite.clone(),
+                selected_layer,
+            ));
+            self.last_started = Some(Instant::now());
+        }
+
+        if !up_to_date {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Updating statistics");
+            });
+        }
+
+        if let Some((_, layer_statistics, statistics)) = &self.shown {
+            ui.strong("Layer");
+            statistics_grid(
+                ui,
+                "layer_statistics_grid",
+                layer_statistics,
+                doc.voxel_size,
+            );
+            ui.strong("All Layers");
+            statistics_grid(ui, "statistics_grid", statistics, doc.voxel_size);
+        }
+    }
+}
+
+fn statistics_grid(ui: &mut Ui, id: &str, statistics: &Statistics, voxel_size: f32) {
+    Grid::new(id)
+        .num_columns(2)
+        .spacing([8.0, 4.0])
+        .show(ui, |ui| {
+            ui.label("Voxels");
+            ui.label(statistics.voxels.to_string());
+            ui.end_row();
+
+            for (material, count) in &statistics.materials {
+                ui.label(format!("Color {}", material));
+                ui.label(count.to_string());
+                ui.end_row();
+            }
+
+            if let Some((min, max)) = statistics.bounding_box {
+                let size = glam::ivec3(max.0 - min.0 + 1, max.1 - min.1 + 1, max.2 - min.2 + 1);
+                ui.label("Size");
+                ui.label(format!("{} × {} × {}", size.x, size.y, size.z));
+                ui.end_row();
+
+                let size = size.as_vec3() * voxel_size;
+                ui.label("Size (mm)");
+                ui.label(format!("{:.1} × {:.1} × {:.1}", size.x, size.y, size.z));
+                ui.end_row();
+            }
+
+            if let Some(center) = statistics.center_of_mass {
+                ui.label("Center of mass");
+                ui.label(format!("{:.1}, {:.1}, {:.1}", center.x, center.y, center.z));
+                ui.end_row();
+            }
+
+            ui.label("Surface area");
+            ui.label(format!("{:.1} mm²", statistics.surface_area));
+            ui.end_row();
+
+            ui.label("Volume");
+            ui.label(format!("{:.1} mm³", statistics.volume));
+            ui.end_row();
+        });
+}
@@ -1,6 +1,8 @@
+mod bucket;
 mod eraser;
 mod paintbrush;
 
+pub use bucket::*;
 pub use eraser::*;
 pub use paintbrush::*;
 
@@ -10,4 +12,9 @@ pub trait Tool {
     fn tooltip(&self) -> &'static str;
 
     fn shortcut(&self) -> egui::KeyboardShortcut;
+
+    // fills the region clicked in the viewport instead of painting
+    fn fills_region(&self) -> bool {
+        false
+    }
 }
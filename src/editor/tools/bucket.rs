@@ -0,0 +1,21 @@
+use crate::editor::tools::Tool;
+
+pub struct Bucket {}
+
+impl Tool for Bucket {
+    fn icon(&self) -> &'static str {
+        "\u{f576}"
+    }
+
+    fn tooltip(&self) -> &'static str {
+        "Bucket Fill"
+    }
+
+    fn shortcut(&self) -> egui::KeyboardShortcut {
+        egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::G)
+    }
+
+    fn fills_region(&self) -> bool {
+        true
+    }
+}
@@ -5,7 +5,8 @@ mod document;
 mod editor;
 mod render;
 mod ui;
-mod voxels;
+
+use mulch::voxels;
 
 fn main() {
     let app = app::App::new();
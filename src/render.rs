@@ -134,7 +134,12 @@ impl Renderer {
         }
     }
 
-    pub fn render(&mut self, doc: &document::Document, ui_render_data: &UiRenderData) {
+    pub fn render(
+        &mut self,
+        doc: &document::Document,
+        composite: &document::Composite,
+        ui_render_data: &UiRenderData,
+    ) {
         self.refresh_targets();
 
         let current_texture = self.surface.get_current_texture();
@@ -160,6 +165,7 @@ impl Renderer {
 
         if doc.viewport.rect.is_positive() {
             let view_rect = ui::ScissorRect::from_egui_rect(doc.viewport.rect, &ui_viewport);
+            self.view_renderer.update(&self.device, doc, composite);
             self.view_renderer.render(
                 &self.device,
                 &self.queue,
@@ -3,7 +3,6 @@ use crate::render::grid;
 use crate::render::shaders;
 use crate::render::ui;
 use crate::render::voxel;
-use crate::voxels;
 
 pub struct ViewRenderer {
     grid_renderer: grid::GridRenderer,
@@ -38,8 +37,22 @@ impl ViewRenderer {
         }
     }
 
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        doc: &document::Document,
+        composite: &document::Composite,
+    ) {
+        self.voxel_renderer.update(
+            device,
+            &composite.voxel_grid,
+            doc.viewport.meshing_mode,
+            &doc.palette,
+        );
+    }
+
     pub fn render(
-        &self,
+        &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         color_target_view: &wgpu::TextureView,
@@ -95,23 +108,8 @@ impl ViewRenderer {
             );
             pass.set_scissor_rect(view_rect.x, view_rect.y, view_rect.width, view_rect.height);
 
-            let mut flat_voxel_grid = voxels::VoxelGrid::new();
-            for layer in &doc.layers {
-                if !layer.visible {
-                    continue;
-                }
-
-                match layer.blend_mode {
-                    document::BlendMode::Add => {
-                        flat_voxel_grid.add(&layer.voxel_grid);
-                    }
-                    document::BlendMode::Subtract => {
-                        flat_voxel_grid.subtract(&layer.voxel_grid);
-                    }
-                }
-            }
-
-            self.voxel_renderer.draw(queue, &mut pass, &flat_voxel_grid);
+            self.voxel_renderer
+                .draw(&mut pass, doc.viewport.camera.position);
 
             if doc.viewport.grid_enabled {
                 self.grid_renderer.draw(&mut pass);
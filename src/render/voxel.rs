@@ -1,14 +1,65 @@
-use crate::{render::shaders, voxels::VoxelGrid};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::mpsc;
 
-// allocate 1MB for each buffer for now
-const BUFFER_SIZE: usize = 1024 * 1024;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    render::shaders,
+    voxels::{self, ChunkCoords, MeshingMode, Octree, Palette, VertexData, VoxelGrid, CHUNK_SIZE},
+};
+
+// far enough from the camera, groups of 2^lod bricks on a side are drawn from
+// a single brick of a coarser level of detail, meshed from an octree of the
+// grid; the surfaces of different levels do not quite meet where they touch
+const LOD_LEVELS: u32 = 2;
+
+// how far groups of a single brick go before using a coarser level; larger
+// groups go further in proportion
+const LOD_DISTANCE: f32 = 256.0;
+
+// grid id and revision, meshing mode and palette colors of a mesh
+type MeshState = (u64, u64, MeshingMode, Vec<[u8; 3]>);
 
 pub struct VoxelRenderer {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
 
+    // mesh of each brick holding part of the surface, at full detail, then at
+    // each coarser level, where bricks are in the chunk coordinates of the
+    // level
+    bricks: Vec<BTreeMap<ChunkCoords, BrickBuffers>>,
+
+    // state the bricks were built from
+    meshed: Option<MeshState>,
+
+    // coarse levels are taken from an octree of the grid, kept in step with
+    // it; the octree is moved to the job meshing the bricks, and back once
+    // they are ready
+    octree: Option<Octree>,
+
+    // bricks being meshed in the background; the previous ones are drawn
+    // until they are ready
+    job: Option<MeshJob>,
+}
+
+struct MeshJob {
+    state: MeshState,
+
+    // all bricks are remeshed, so the previous ones must go
+    full: bool,
+
+    receiver: mpsc::Receiver<(Vec<LevelBrickMesh>, Octree)>,
+}
+
+type BrickMesh = (Vec<VertexData>, Vec<u32>);
+
+// level of detail and coordinates of a brick, and its mesh
+type LevelBrickMesh = (u32, ChunkCoords, BrickMesh);
+
+struct BrickBuffers {
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
+    index_count: u32,
 }
 
 impl VoxelRenderer {
@@ -48,7 +99,7 @@ impl VoxelRenderer {
         });
 
         let vertex_layout = wgpu::VertexBufferLayout {
-            array_stride: 24,
+            array_stride: 36,
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 wgpu::VertexAttribute {
@@ -61,6 +112,11 @@ impl VoxelRenderer {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: 24,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         };
 
@@ -105,44 +161,226 @@ impl VoxelRenderer {
             multiview: None,
         });
 
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Voxel Vertex Buffer"),
-            size: BUFFER_SIZE as u64,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Voxel Index Buffer"),
-            size: BUFFER_SIZE as u64,
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
         Self {
             pipeline,
             bind_group,
 
-            vertex_buffer,
-            index_buffer,
+            bricks: (0..=LOD_LEVELS).map(|_| BTreeMap::new()).collect(),
+            meshed: None,
+            octree: None,
+            job: None,
         }
     }
 
-    pub fn draw<'a>(
-        &'a self,
-        queue: &wgpu::Queue,
-        pass: &mut wgpu::RenderPass<'a>,
+    // remeshes the bricks around the chunks edited since the last update, or
+    // all of them when the grid itself or the way it is meshed changed; this
+    // happens on a snapshot of the grid in the background, and the new
+    // bricks are uploaded by a later update once they are ready
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
         voxel_grid: &VoxelGrid,
+        meshing_mode: MeshingMode,
+        palette: &Palette,
     ) {
-        let (vertices, indices) = voxel_grid.generate_mesh();
+        if let Some(job) = &self.job {
+            match job.receiver.try_recv() {
+                Ok((meshes, octree)) => {
+                    let job = self.job.take().unwrap();
+                    self.octree = Some(octree);
+                    if job.full {
+                        self.bricks.iter_mut().for_each(BTreeMap::clear);
+                    }
+                    self.upload(device, meshes);
+                    self.meshed = Some(job.state);
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => self.job = None,
+            }
+        }
+
+        let state = (
+            voxel_grid.id(),
+            voxel_grid.revision(),
+            meshing_mode,
+            palette.colors.clone(),
+        );
+        if self.meshed.as_ref() == Some(&state) {
+            return;
+        }
+
+        let changed: Option<Vec<ChunkCoords>> = match &self.meshed {
+            Some((id, revision, mode, colors))
+                if *id == voxel_grid.id() && *mode == meshing_mode && *colors == palette.colors =>
+            {
+                Some(voxel_grid.changed_chunks(*revision).collect())
+            }
+            _ => None,
+        };
+        let full = changed.is_none();
 
-        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
-        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+        // bricks of each level to remesh
+        let dirty: Vec<Vec<ChunkCoords>> = (0..=LOD_LEVELS)
+            .map(|lod| {
+                let bricks: BTreeSet<ChunkCoords> = match &changed {
+                    Some(changed) => changed
+                        .iter()
+                        .flat_map(|chunk_coords| {
+                            voxels::bricks_around(coarse_coords(*chunk_coords, lod))
+                        })
+                        .collect(),
+                    None if lod == 0 => voxel_grid.bricks(),
+                    None => voxel_grid
+                        .chunk_coords()
+                        .flat_map(|chunk_coords| {
+                            voxels::bricks_around(coarse_coords(chunk_coords, lod))
+                        })
+                        .collect(),
+                };
+                bricks.into_iter().collect()
+            })
+            .collect();
 
+        // the octree is only built again in full along with the bricks
+        let octree = match (&changed, self.octree.take()) {
+            (Some(changed), Some(octree)) => Some((octree, changed.clone())),
+            _ => None,
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let voxel_grid = voxel_grid.clone();
+        let palette = palette.clone();
+        rayon::spawn(move || {
+            let mut meshes: Vec<LevelBrickMesh> = vec![];
+            let bricks = voxel_grid.generate_brick_meshes(&dirty[0], meshing_mode, &palette);
+            meshes.extend(
+                dirty[0]
+                    .iter()
+                    .zip(bricks)
+                    .map(|(brick, mesh)| (0, *brick, mesh)),
+            );
+
+            let octree = match octree {
+                Some((mut octree, changed)) => {
+                    octree.update(&voxel_grid, &changed);
+                    octree
+                }
+                None => voxel_grid.octree(),
+            };
+            for lod in 1..=LOD_LEVELS {
+                let level = &dirty[lod as usize];
+                let bricks = octree.generate_brick_meshes(level, lod, meshing_mode, &palette);
+                meshes.extend(
+                    level
+                        .iter()
+                        .zip(bricks)
+                        .map(|(brick, mesh)| (lod, *brick, mesh)),
+                );
+            }
+
+            sender.send((meshes, octree)).ok();
+        });
+
+        self.job = Some(MeshJob {
+            state,
+            full,
+            receiver,
+        });
+    }
+
+    fn upload(&mut self, device: &wgpu::Device, meshes: Vec<LevelBrickMesh>) {
+        for (lod, brick, (vertices, indices)) in meshes {
+            let bricks = &mut self.bricks[lod as usize];
+            if indices.is_empty() {
+                bricks.remove(&brick);
+                continue;
+            }
+
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Voxel Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Voxel Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            bricks.insert(
+                brick,
+                BrickBuffers {
+                    vertex_buffer,
+                    index_buffer,
+                    index_count: indices.len() as u32,
+                },
+            );
+        }
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, camera_position: glam::Vec3) {
         pass.set_pipeline(&self.pipeline);
-        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         pass.set_bind_group(0, &self.bind_group, &[]);
-        pass.draw_indexed(0..indices.len() as u32, 0, 0..1);
+
+        // groups of the coarsest level holding bricks of any level
+        let groups: BTreeSet<ChunkCoords> = self
+            .bricks
+            .iter()
+            .enumerate()
+            .flat_map(|(lod, bricks)| {
+                bricks
+                    .keys()
+                    .map(move |brick| coarse_coords(*brick, LOD_LEVELS - lod as u32))
+            })
+            .collect();
+
+        for group in groups {
+            self.draw_group(pass, LOD_LEVELS, group, camera_position);
+        }
+    }
+
+    // draws the brick of the group at its level when far enough from the
+    // camera, or else the smaller groups it holds, down to full detail
+    fn draw_group<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        lod: u32,
+        group: ChunkCoords,
+        camera_position: glam::Vec3,
+    ) {
+        if lod > 0 {
+            let side = (CHUNK_SIZE << lod) as f32;
+            let min = glam::IVec3::from(group).as_vec3() * side;
+            let distance = camera_position
+                .clamp(min, min + side)
+                .distance(camera_position);
+            if distance < LOD_DISTANCE * (1 << lod) as f32 {
+                for i in 0..8 {
+                    let child = (
+                        group.0 * 2 + (i & 1),
+                        group.1 * 2 + ((i >> 1) & 1),
+                        group.2 * 2 + (i >> 2),
+                    );
+                    self.draw_group(pass, lod - 1, child, camera_position);
+                }
+                return;
+            }
+        }
+
+        if let Some(brick) = self.bricks[lod as usize].get(&group) {
+            pass.set_vertex_buffer(0, brick.vertex_buffer.slice(..));
+            pass.set_index_buffer(brick.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..brick.index_count, 0, 0..1);
+        }
     }
 }
+
+// chunk of a level of detail holding the given chunk
+fn coarse_coords(chunk_coords: ChunkCoords, lod: u32) -> ChunkCoords {
+    (
+        chunk_coords.0 >> lod,
+        chunk_coords.1 >> lod,
+        chunk_coords.2 >> lod,
+    )
+}
@@ -1,209 +1,870 @@
+mod bit_volume;
+mod chunk;
+mod filling;
+mod heightmap;
+mod hollowing;
+mod labeling;
+mod meshing;
+mod morphology;
+mod octree;
+mod palette;
+mod raycasting;
+mod resampling;
+mod shapes;
+mod smoothing;
+mod statistics;
+mod strokes;
+mod terrain;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use glam::*;
 
-pub type Coords = (usize, usize, usize);
+pub use chunk::CHUNK_SIZE;
+pub use filling::*;
+pub use heightmap::*;
+pub use hollowing::*;
+pub use labeling::*;
+pub use meshing::*;
+pub use morphology::*;
+pub use octree::*;
+pub use palette::*;
+pub use raycasting::*;
+pub use resampling::*;
+pub use shapes::*;
+pub use smoothing::*;
+pub use statistics::*;
+pub use strokes::*;
+pub use terrain::*;
+
+use bit_volume::BitGrid;
+use chunk::*;
+
+pub type Coords = (i32, i32, i32);
+pub type ChunkCoords = (i32, i32, i32);
+
+// index into the document palette
+pub type Material = u8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// world coordinates are kept within [-WORLD_LIMIT, WORLD_LIMIT) on each axis,
+// so that vertex positions stay exact in f32
+pub const WORLD_LIMIT: i32 = 1 << 20;
+
+// smooth brushes also write distances to the empty voxels this close to their
+// surface; the mesher only interpolates between a voxel and its 6 neighbors,
+// so anything further away would never be used
+const DISTANCE_BAND: f32 = 1.0;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct VertexData {
     pub position: Vec3,
     pub normal: Vec3,
+    pub color: Vec3,
 }
 
+// sparse grid of 64x64x64 chunks; space that was never painted holds no chunk.
+// chunks are shared between clones until either side edits them, so that
+// a clone is a cheap snapshot that other threads can work on
 pub struct VoxelGrid {
-    data: [u64; 64 * 64],
+    chunks: BTreeMap<ChunkCoords, Arc<Chunk>>,
+
+    // every edit gets a new revision, unique across all grids; chunks keep
+    // the revision of the last edit that touched them, even once removed,
+    // so that caches built from a grid can tell which chunks are stale
+    id: u64,
+    revision: u64,
+    chunk_revisions: BTreeMap<ChunkCoords, u64>,
+}
+
+// clones get their own id, as their edits are unrelated to the ones of the
+// original grid
+impl Clone for VoxelGrid {
+    fn clone(&self) -> Self {
+        let id = next_revision();
+
+        Self {
+            chunks: self.chunks.clone(),
+            id,
+            revision: id,
+            chunk_revisions: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for VoxelGrid {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VoxelGrid {
     pub fn new() -> Self {
-        let data = [0u64; 64 * 64];
+        let chunks = BTreeMap::new();
+        let id = next_revision();
 
-        Self { data }
+        Self {
+            chunks,
+            id,
+            revision: id,
+            chunk_revisions: BTreeMap::new(),
+        }
     }
 
-    pub fn read(&self, (x, y, z): Coords) -> u64 {
-        let line = self.data[z * 64 + y];
-        (line & (1 << x)) >> x
+    pub fn id(&self) -> u64 {
+        self.id
     }
 
-    pub fn add(&mut self, other: &Self) {
-        for z in 0..64 {
-            for y in 0..64 {
-                let index = z * 64 + y;
-                self.data[index] |= other.data[index];
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    // chunks edited after the given revision of this grid
+    pub fn changed_chunks(&self, since: u64) -> impl Iterator<Item = ChunkCoords> + '_ {
+        self.chunk_revisions
+            .iter()
+            .filter(move |(_, revision)| **revision > since)
+            .map(|(chunk_coords, _)| *chunk_coords)
+    }
+
+    pub fn chunk_coords(&self) -> impl Iterator<Item = ChunkCoords> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    // smallest box holding all solid voxels, inclusive
+    pub fn bounding_box(&self) -> Option<(Coords, Coords)> {
+        let mut bounding_box: Option<(Coords, Coords)> = None;
+        for (chunk_coords, chunk) in &self.chunks {
+            if let Some((lo, hi)) = chunk.bounds() {
+                let origin = chunk_origin(*chunk_coords);
+                let lo = (
+                    origin.0 + lo.0 as i32,
+                    origin.1 + lo.1 as i32,
+                    origin.2 + lo.2 as i32,
+                );
+                let hi = (
+                    origin.0 + hi.0 as i32,
+                    origin.1 + hi.1 as i32,
+                    origin.2 + hi.2 as i32,
+                );
+                bounding_box = Some(match bounding_box {
+                    Some((min, max)) => (
+                        (min.0.min(lo.0), min.1.min(lo.1), min.2.min(lo.2)),
+                        (max.0.max(hi.0), max.1.max(hi.1), max.2.max(hi.2)),
+                    ),
+                    None => (lo, hi),
+                });
             }
         }
+        bounding_box
+    }
+
+    pub fn read(&self, coords: Coords) -> u64 {
+        let (chunk_coords, local) = split_coords(coords);
+        match self.chunks.get(&chunk_coords) {
+            Some(chunk) => chunk.read(local),
+            None => 0,
+        }
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.begin_edit();
+        for (chunk_coords, other_chunk) in &other.chunks {
+            self.chunk_mut(*chunk_coords).add(other_chunk);
+        }
     }
 
     pub fn subtract(&mut self, other: &Self) {
-        for z in 0..64 {
-            for y in 0..64 {
-                let index = z * 64 + y;
-                self.data[index] &= !other.data[index];
+        for chunk_coords in other.chunks.keys() {
+            self.subtract_chunk(other, *chunk_coords);
+        }
+    }
+
+    pub fn intersect(&mut self, other: &Self) {
+        let chunk_coords: Vec<ChunkCoords> = self.chunk_coords().collect();
+        for chunk_coords in chunk_coords {
+            self.intersect_chunk(other, chunk_coords);
+        }
+    }
+
+    pub fn xor(&mut self, other: &Self) {
+        for chunk_coords in other.chunks.keys() {
+            self.xor_chunk(other, *chunk_coords);
+        }
+    }
+
+    // repaints the voxels also solid in the other grid with its materials
+    pub fn replace(&mut self, other: &Self) {
+        for chunk_coords in other.chunks.keys() {
+            self.replace_chunk(other, *chunk_coords);
+        }
+    }
+
+    // flips all voxels between min and max, inclusive; the grid being
+    // sparse, only a bounded region can be inverted
+    pub fn invert(&mut self, min: Coords, max: Coords, material: Material) {
+        let min = clamp_to_world(min);
+        let max = clamp_to_world(max);
+        if min.0 > max.0 || min.1 > max.1 || min.2 > max.2 {
+            return;
+        }
+
+        self.begin_edit();
+
+        let mut touched = vec![];
+        for chunk_x in min.0.div_euclid(CHUNK_SIZE)..=max.0.div_euclid(CHUNK_SIZE) {
+            let chunk_min_x = chunk_x * CHUNK_SIZE;
+            let lo = (min.0 - chunk_min_x).max(0) as usize;
+            let hi = (max.0 - chunk_min_x).min(CHUNK_SIZE - 1) as usize;
+
+            for z in min.2..=max.2 {
+                for y in min.1..=max.1 {
+                    let ((_, chunk_y, chunk_z), (_, local_y, local_z)) = split_coords((0, y, z));
+                    let chunk_coords = (chunk_x, chunk_y, chunk_z);
+                    self.chunk_mut(chunk_coords)
+                        .invert_row(local_y, local_z, lo, hi, material);
+                    touched.push(chunk_coords);
+                }
             }
         }
+
+        for chunk_coords in touched {
+            self.remove_if_empty(chunk_coords);
+        }
     }
 
-    pub fn paint_cube(&mut self, min: Coords, max: Coords) {
-        let min_mask: u64 = (1 << min.0) - 1;
-        let max_mask: u64 = (1 << max.0) - 1;
-        let mask: u64 = max_mask - min_mask;
-        for z in min.2..=max.2 {
-            for y in min.1..=max.1 {
-                self.data[z * 64 + y] |= mask;
+    // rigid transforms move whole voxels, distances included, and return how
+    // many solid voxels were dropped for leaving the world
+    pub fn translate(&mut self, offset: Coords) -> usize {
+        self.remap(|(x, y, z)| {
+            [
+                x as i64 + offset.0 as i64,
+                y as i64 + offset.1 as i64,
+                z as i64 + offset.2 as i64,
+            ]
+        })
+    }
+
+    // counterclockwise around the axis when it points towards the viewer.
+    // the pivot is rounded to half voxels; when it is on a voxel center
+    // along one axis and between voxels along the other, voxels land halfway
+    // and are moved down, which four quarter turns still undo exactly
+    pub fn rotate(&mut self, axis: Axis, quarter_turns: i32, pivot: Vec3) -> usize {
+        let axis = axis.index();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let pivot = doubled(pivot);
+        let (sum, difference) = (
+            (pivot[u] + pivot[v]).div_euclid(2),
+            (pivot[v] - pivot[u]).div_euclid(2),
+        );
+        let quarter_turns = quarter_turns.rem_euclid(4);
+
+        self.remap(|(x, y, z)| {
+            let mut position = [x as i64, y as i64, z as i64];
+            let (a, b) = (position[u], position[v]);
+            (position[u], position[v]) = match quarter_turns {
+                0 => (a, b),
+                1 => (sum - b, difference + a),
+                2 => (sum - difference - a, sum + difference - b),
+                _ => (b - difference, sum - a),
+            };
+            position
+        })
+    }
+
+    // across the plane orthogonal to the axis going through the pivot
+    pub fn mirror(&mut self, axis: Axis, pivot: Vec3) -> usize {
+        let axis = axis.index();
+        let pivot = doubled(pivot);
+
+        self.remap(|(x, y, z)| {
+            let mut position = [x as i64, y as i64, z as i64];
+            position[axis] = pivot[axis] - position[axis];
+            position
+        })
+    }
+
+    // same as the operations above, limited to a single chunk
+    pub fn add_chunk(&mut self, other: &Self, chunk_coords: ChunkCoords) {
+        self.begin_edit();
+        if let Some(other_chunk) = other.chunks.get(&chunk_coords) {
+            self.chunk_mut(chunk_coords).add(other_chunk);
+        }
+    }
+
+    pub fn subtract_chunk(&mut self, other: &Self, chunk_coords: ChunkCoords) {
+        self.begin_edit();
+        if let (Some(chunk), Some(other_chunk)) = (
+            self.chunks.get_mut(&chunk_coords),
+            other.chunks.get(&chunk_coords),
+        ) {
+            Arc::make_mut(chunk).subtract(other_chunk);
+            if chunk.is_empty() {
+                self.chunks.remove(&chunk_coords);
+            }
+            self.chunk_revisions.insert(chunk_coords, self.revision);
+        }
+    }
+
+    pub fn intersect_chunk(&mut self, other: &Self, chunk_coords: ChunkCoords) {
+        self.begin_edit();
+        if !self.chunks.contains_key(&chunk_coords) {
+            return;
+        }
+        match other.chunks.get(&chunk_coords) {
+            Some(other_chunk) => {
+                self.chunk_mut(chunk_coords).intersect(other_chunk);
+                self.remove_if_empty(chunk_coords);
+            }
+            None => {
+                self.chunks.remove(&chunk_coords);
+                self.chunk_revisions.insert(chunk_coords, self.revision);
+            }
+        }
+    }
+
+    pub fn xor_chunk(&mut self, other: &Self, chunk_coords: ChunkCoords) {
+        self.begin_edit();
+        if let Some(other_chunk) = other.chunks.get(&chunk_coords) {
+            self.chunk_mut(chunk_coords).xor(other_chunk);
+            self.remove_if_empty(chunk_coords);
+        }
+    }
+
+    pub fn replace_chunk(&mut self, other: &Self, chunk_coords: ChunkCoords) {
+        self.begin_edit();
+        if !self.chunks.contains_key(&chunk_coords) {
+            return;
+        }
+        if let Some(other_chunk) = other.chunks.get(&chunk_coords) {
+            self.chunk_mut(chunk_coords).replace(other_chunk);
+        }
+    }
+
+    // makes a chunk the same as in the other grid, without copying it
+    pub fn copy_chunk(&mut self, other: &Self, chunk_coords: ChunkCoords) {
+        self.begin_edit();
+        let changed = match other.chunks.get(&chunk_coords) {
+            Some(other_chunk) => {
+                self.chunks.insert(chunk_coords, other_chunk.clone());
+                true
+            }
+            None => self.chunks.remove(&chunk_coords).is_some(),
+        };
+        if changed {
+            self.chunk_revisions.insert(chunk_coords, self.revision);
+        }
+    }
+
+    // fills all voxels between min and max, inclusive
+    pub fn paint_cube(&mut self, min: Coords, max: Coords, material: Material) {
+        let min = clamp_to_world(min);
+        let max = clamp_to_world(max);
+        if min.0 > max.0 || min.1 > max.1 || min.2 > max.2 {
+            return;
+        }
+
+        self.begin_edit();
+
+        for chunk_x in min.0.div_euclid(CHUNK_SIZE)..=max.0.div_euclid(CHUNK_SIZE) {
+            let chunk_min_x = chunk_x * CHUNK_SIZE;
+            let lo = (min.0 - chunk_min_x).max(0) as usize;
+            let hi = (max.0 - chunk_min_x).min(CHUNK_SIZE - 1) as usize;
+
+            for z in min.2..=max.2 {
+                for y in min.1..=max.1 {
+                    let ((_, chunk_y, chunk_z), (_, local_y, local_z)) = split_coords((0, y, z));
+                    self.chunk_mut((chunk_x, chunk_y, chunk_z))
+                        .fill_row(local_y, local_z, lo, hi, material);
+                }
             }
         }
     }
 
-    pub fn paint_sphere(&mut self, pos: Coords, radius: f32) {
-        let bounds_radius = radius.ceil() as i32;
+    // writes the signed distance to the sphere rather than hard voxels, so
+    // that the mesh follows the sphere and not the voxel steps
+    pub fn paint_sphere(&mut self, pos: Coords, radius: f32, material: Material) {
+        self.begin_edit();
 
-        let x_min = -bounds_radius - (pos.0 as i32 - bounds_radius).min(0);
-        let y_min = -bounds_radius - (pos.1 as i32 - bounds_radius).min(0);
-        let z_min = -bounds_radius - (pos.2 as i32 - bounds_radius).min(0);
+        let bounds_radius = (radius + DISTANCE_BAND).ceil() as i32;
 
-        let x_max = bounds_radius + (63 - pos.0 as i32 - bounds_radius).min(0);
-        let y_max = bounds_radius + (63 - pos.1 as i32 - bounds_radius).min(0);
-        let z_max = bounds_radius + (63 - pos.2 as i32 - bounds_radius).min(0);
+        let min = clamp_to_world((
+            pos.0 - bounds_radius,
+            pos.1 - bounds_radius,
+            pos.2 - bounds_radius,
+        ));
+        let max = clamp_to_world((
+            pos.0 + bounds_radius,
+            pos.1 + bounds_radius,
+            pos.2 + bounds_radius,
+        ));
 
-        for z in z_min..=z_max {
-            for y in y_min..=y_max {
-                for x in x_min..=x_max {
-                    let point = (x as f32, y as f32, z as f32);
+        for z in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    let point = ((x - pos.0) as f32, (y - pos.1) as f32, (z - pos.2) as f32);
                     let distance =
                         (point.0 * point.0 + point.1 * point.1 + point.2 * point.2).sqrt();
-                    if distance <= radius {
-                        let voxel_pos = (
-                            (pos.0 as i32 + x) as usize,
-                            (pos.1 as i32 + y) as usize,
-                            (pos.2 as i32 + z) as usize,
-                        );
-                        self.data[voxel_pos.2 * 64 + voxel_pos.1] |= 1 << voxel_pos.0;
+                    if distance - radius <= DISTANCE_BAND {
+                        self.union_distance((x, y, z), distance - radius, material);
                     }
                 }
             }
         }
+
+        self.remove_empty_chunks(min, max);
+    }
+
+    fn begin_edit(&mut self) {
+        self.revision = next_revision();
     }
 
-    pub fn generate_mesh(&self) -> (Vec<VertexData>, Vec<u32>) {
-        let mut vertices: Vec<VertexData> = vec![];
-        let mut indices: Vec<u32> = vec![];
-
-        let mut index_grid = vec![0u32; 64 * 64 * 64];
-
-        for z in 0..63 {
-            for y in 0..63 {
-                for x in 0..63 {
-                    let mut count = 0;
-                    count += self.read((x, y, z));
-                    count += self.read((x + 1, y, z));
-                    count += self.read((x + 1, y + 1, z));
-                    count += self.read((x, y + 1, z));
-                    count += self.read((x, y, z + 1));
-                    count += self.read((x + 1, y, z + 1));
-                    count += self.read((x + 1, y + 1, z + 1));
-                    count += self.read((x, y + 1, z + 1));
-
-                    if count > 0 && count < 8 {
-                        // generate a vertex here
-                        let index = vertices.len();
-                        index_grid[z * 64 * 64 + y * 64 + x] = index as u32;
-                        vertices.push(VertexData {
-                            position: Vec3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5),
-                            normal: Vec3::ZERO,
-                        });
+    // marks the chunk as changed by the current edit
+    fn chunk_mut(&mut self, chunk_coords: ChunkCoords) -> &mut Chunk {
+        self.chunk_revisions.insert(chunk_coords, self.revision);
+        Arc::make_mut(
+            self.chunks
+                .entry(chunk_coords)
+                .or_insert_with(|| Arc::new(Chunk::new())),
+        )
+    }
+
+    // moves every voxel to where the map sends it; voxels sent outside of
+    // the world are dropped, and the solid ones among them are counted
+    fn remap(&mut self, map: impl Fn(Coords) -> [i64; 3]) -> usize {
+        let mut chunks: BTreeMap<ChunkCoords, Arc<Chunk>> = BTreeMap::new();
+        let mut clipped = 0;
+        let limit = WORLD_LIMIT as i64;
+
+        for (chunk_coords, chunk) in &self.chunks {
+            let origin = chunk_origin(*chunk_coords);
+            for local in chunk.stored_voxels() {
+                let voxel = chunk.get(local);
+                let target = map((
+                    origin.0 + local.0 as i32,
+                    origin.1 + local.1 as i32,
+                    origin.2 + local.2 as i32,
+                ));
+                if target.iter().any(|c| *c < -limit || *c >= limit) {
+                    if voxel.solid {
+                        clipped += 1;
                     }
+                    continue;
                 }
+
+                let (target_chunk, target_local) =
+                    split_coords((target[0] as i32, target[1] as i32, target[2] as i32));
+                let target_chunk = chunks
+                    .entry(target_chunk)
+                    .or_insert_with(|| Arc::new(Chunk::new()));
+                Arc::make_mut(target_chunk).set(target_local, voxel);
             }
         }
 
-        // scan all interior edges
-        for z in 1..63 {
-            for y in 1..63 {
-                for x in 0..63 {
-                    let v0 = self.read((x, y, z));
-                    let v1 = self.read((x + 1, y, z));
-
-                    if v0 != v1 {
-                        let i0 = index_grid[(z - 1) * 64 * 64 + (y - 1) * 64 + x];
-                        let i1 = index_grid[z * 64 * 64 + (y - 1) * 64 + x];
-                        let i2 = index_grid[z * 64 * 64 + y * 64 + x];
-                        let i3 = index_grid[(z - 1) * 64 * 64 + y * 64 + x];
-
-                        let mut normal = Vec3::new(1.0, 0.0, 0.0);
-                        if v0 < v1 {
-                            normal.x = -normal.x;
-                            indices.extend([i0, i1, i2, i2, i3, i0]);
-                        } else {
-                            indices.extend([i0, i3, i2, i2, i1, i0]);
-                        }
-
-                        vertices[i0 as usize].normal += normal;
-                        vertices[i1 as usize].normal += normal;
-                        vertices[i2 as usize].normal += normal;
-                        vertices[i3 as usize].normal += normal;
-                    }
+        self.replace_chunks(chunks);
+        clipped
+    }
+
+    // swaps all chunks at once, as a single edit
+    fn replace_chunks(&mut self, mut chunks: BTreeMap<ChunkCoords, Arc<Chunk>>) {
+        self.begin_edit();
+
+        // chunks may only have received the distances of empty voxels
+        chunks.retain(|_, chunk| !chunk.is_empty());
+
+        for chunk_coords in self.chunks.keys().chain(chunks.keys()) {
+            self.chunk_revisions.insert(*chunk_coords, self.revision);
+        }
+        self.chunks = chunks;
+    }
+
+    // writes back the voxels that differ between two grids of bits, as a
+    // single edit; new voxels take the given materials, and changed voxels
+    // lose their distance
+    fn write_grid(
+        &mut self,
+        original: &BitGrid,
+        grid: &BitGrid,
+        materials: &HashMap<Coords, Material>,
+    ) {
+        let changes: Vec<(Coords, Voxel)> = original
+            .differences(grid)
+            .into_iter()
+            .map(|coords| {
+                let voxel = if original.get(coords) {
+                    Voxel::hard(false, 0)
+                } else {
+                    Voxel::hard(true, materials.get(&coords).copied().unwrap_or(0))
+                };
+                (coords, voxel)
+            })
+            .collect();
+
+        self.begin_edit();
+        let mut touched = BTreeSet::new();
+        for (coords, voxel) in changes {
+            let (chunk_coords, local) = split_coords(coords);
+            self.chunk_mut(chunk_coords).set(local, voxel);
+            touched.insert(chunk_coords);
+        }
+
+        for chunk_coords in touched {
+            self.remove_if_empty(chunk_coords);
+        }
+    }
+
+    fn voxel(&self, coords: Coords) -> Voxel {
+        let (chunk_coords, local) = split_coords(coords);
+        match self.chunks.get(&chunk_coords) {
+            Some(chunk) => chunk.get(local),
+            None => Voxel::hard(false, 0),
+        }
+    }
+
+    fn remove_if_empty(&mut self, chunk_coords: ChunkCoords) {
+        if matches!(self.chunks.get(&chunk_coords), Some(chunk) if chunk.is_empty()) {
+            self.chunks.remove(&chunk_coords);
+        }
+    }
+
+    // smooth brushes write distances a bit past their surface, which may
+    // land in chunks without any solid voxel; these chunks are dropped
+    // rather than kept for their distances alone
+    fn remove_empty_chunks(&mut self, min: Coords, max: Coords) {
+        let (chunk_min, _) = split_coords(min);
+        let (chunk_max, _) = split_coords(max);
+        for z in chunk_min.2..=chunk_max.2 {
+            for y in chunk_min.1..=chunk_max.1 {
+                for x in chunk_min.0..=chunk_max.0 {
+                    self.remove_if_empty((x, y, z));
                 }
             }
         }
+    }
 
-        for z in 1..63 {
-            for x in 1..63 {
-                for y in 0..63 {
-                    let v0 = self.read((x, y, z));
-                    let v1 = self.read((x, y + 1, z));
-
-                    if v0 != v1 {
-                        let i0 = index_grid[(z - 1) * 64 * 64 + y * 64 + (x - 1)];
-                        let i1 = index_grid[(z - 1) * 64 * 64 + y * 64 + x];
-                        let i2 = index_grid[z * 64 * 64 + y * 64 + x];
-                        let i3 = index_grid[z * 64 * 64 + y * 64 + (x - 1)];
-
-                        let mut normal = Vec3::new(0.0, 1.0, 0.0);
-                        if v0 < v1 {
-                            normal.y = -normal.y;
-                            indices.extend([i0, i1, i2, i2, i3, i0]);
-                        } else {
-                            indices.extend([i0, i3, i2, i2, i1, i0]);
-                        }
-
-                        vertices[i0 as usize].normal += normal;
-                        vertices[i1 as usize].normal += normal;
-                        vertices[i2 as usize].normal += normal;
-                        vertices[i3 as usize].normal += normal;
-                    }
+    fn union_distance(&mut self, coords: Coords, distance: f32, material: Material) {
+        let (chunk_coords, local) = split_coords(coords);
+        self.chunk_mut(chunk_coords)
+            .union_distance(local, distance, material);
+    }
+}
+
+fn next_revision() -> u64 {
+    static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+fn chunk_origin(chunk_coords: ChunkCoords) -> Coords {
+    (
+        chunk_coords.0 * CHUNK_SIZE,
+        chunk_coords.1 * CHUNK_SIZE,
+        chunk_coords.2 * CHUNK_SIZE,
+    )
+}
+
+// positions in half voxels, so that both voxel centers and the planes
+// between voxels are exact
+fn doubled(position: Vec3) -> [i64; 3] {
+    let limit = 4.0 * WORLD_LIMIT as f32;
+    (position * 2.0)
+        .round()
+        .clamp(Vec3::splat(-limit), Vec3::splat(limit))
+        .to_array()
+        .map(|c| c as i64)
+}
+
+fn clamp_to_world(coords: Coords) -> Coords {
+    (
+        coords.0.clamp(-WORLD_LIMIT, WORLD_LIMIT - 1),
+        coords.1.clamp(-WORLD_LIMIT, WORLD_LIMIT - 1),
+        coords.2.clamp(-WORLD_LIMIT, WORLD_LIMIT - 1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // solid voxels of the grid and their materials, in order
+    pub(crate) fn solid(voxel_grid: &VoxelGrid) -> BTreeMap<Coords, Material> {
+        let mut voxels = BTreeMap::new();
+        for (chunk_coords, chunk) in &voxel_grid.chunks {
+            let origin = chunk_origin(*chunk_coords);
+            for (x, y, z) in chunk.stored_voxels() {
+                let voxel = chunk.get((x, y, z));
+                if voxel.solid {
+                    let coords = (
+                        origin.0 + x as i32,
+                        origin.1 + y as i32,
+                        origin.2 + z as i32,
+                    );
+                    voxels.insert(coords, voxel.material);
                 }
             }
         }
+        voxels
+    }
 
-        for y in 1..63 {
-            for x in 1..63 {
-                for z in 0..63 {
-                    let v0 = self.read((x, y, z));
-                    let v1 = self.read((x, y, z + 1));
-
-                    if v0 != v1 {
-                        let i0 = index_grid[z * 64 * 64 + (y - 1) * 64 + (x - 1)];
-                        let i1 = index_grid[z * 64 * 64 + y * 64 + (x - 1)];
-                        let i2 = index_grid[z * 64 * 64 + y * 64 + x];
-                        let i3 = index_grid[z * 64 * 64 + (y - 1) * 64 + x];
-
-                        let mut normal = Vec3::new(0.0, 0.0, 1.0);
-                        if v0 < v1 {
-                            normal.z = -normal.z;
-                            indices.extend([i0, i1, i2, i2, i3, i0]);
-                        } else {
-                            indices.extend([i0, i3, i2, i2, i1, i0]);
-                        }
-
-                        vertices[i0 as usize].normal += normal;
-                        vertices[i1 as usize].normal += normal;
-                        vertices[i2 as usize].normal += normal;
-                        vertices[i3 as usize].normal += normal;
-                    }
+    fn solid_voxels(voxel_grid: &VoxelGrid, min: Coords, max: Coords) -> usize {
+        let mut count = 0;
+        for z in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    count += voxel_grid.read((x, y, z)) as usize;
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn chunks_only_hold_painted_space() {
+        let mut voxel_grid = VoxelGrid::new();
+        assert_eq!(voxel_grid.chunk_coords().count(), 0);
+        assert_eq!(voxel_grid.bounding_box(), None);
+
+        // one voxel in each of the 8 chunks around the origin, and one far
+        // away at the bottom corner of the world
+        voxel_grid.paint_cube((-1, -1, -1), (0, 0, 0), 1);
+        let corner = (-WORLD_LIMIT, -WORLD_LIMIT, -WORLD_LIMIT);
+        voxel_grid.paint_cube(corner, corner, 2);
+        assert_eq!(voxel_grid.chunk_coords().count(), 9);
+        assert_eq!(voxel_grid.read((-1, -1, -1)), 1);
+        assert_eq!(voxel_grid.read((0, 0, 0)), 1);
+        assert_eq!(voxel_grid.read((-2, 0, 0)), 0);
+        assert_eq!(voxel_grid.read(corner), 1);
+        assert_eq!(voxel_grid.bounding_box(), Some((corner, (0, 0, 0))));
+
+        // erasing everything leaves no chunk behind
+        let other = voxel_grid.clone();
+        voxel_grid.subtract(&other);
+        assert_eq!(voxel_grid.chunk_coords().count(), 0);
+        assert_eq!(voxel_grid.bounding_box(), None);
+    }
+
+    #[test]
+    fn spheres_only_keep_chunks_with_solid_voxels() {
+        // the surface ends right before the next chunk along x, which only
+        // gets distances
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_sphere((32, 32, 32), 31.5, 1);
+        assert_eq!(voxel_grid.read((63, 32, 32)), 1);
+        assert_eq!(
+            voxel_grid.chunk_coords().collect::<Vec<_>>(),
+            vec![(0, 0, 0)]
+        );
+
+        // painted over, these chunks keep their distances
+        voxel_grid.paint_cube((64, 0, 0), (64, 0, 0), 1);
+        voxel_grid.paint_sphere((32, 32, 32), 31.5, 1);
+        assert_eq!(voxel_grid.chunk_coords().count(), 2);
+        let (chunk_coords, local) = split_coords((64, 32, 32));
+        let distance = voxel_grid.chunks[&chunk_coords].read_distance(local);
+        assert!(
+            (distance - 0.5).abs() < 1.0 / DISTANCE_SCALE,
+            "{}",
+            distance
+        );
+    }
+
+    #[test]
+    fn sphere_surfaces_follow_their_radius() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_sphere((-3, 5, -70), 9.3, 1);
+        let (vertices, indices) =
+            voxel_grid.generate_mesh(MeshingMode::SurfaceNets, &Palette::default());
+        assert!(!indices.is_empty());
+        for vertex in vertices {
+            let distance = vertex.position.distance(vec3(-3.0, 5.0, -70.0));
+            assert!((distance - 9.3).abs() < 0.1, "{}", distance);
+        }
+    }
+
+    // material of each solid voxel between min and max
+    fn solid_materials(voxel_grid: &VoxelGrid, min: Coords, max: Coords) -> Vec<Option<Material>> {
+        let mut materials = vec![];
+        for z in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    let voxel = voxel_grid.voxel((x, y, z));
+                    materials.push(voxel.solid.then_some(voxel.material));
                 }
             }
         }
+        materials
+    }
+
+    #[test]
+    fn boolean_operations_combine_voxels() {
+        let (min, max) = ((-80, -20, -20), (20, 20, 20));
+        let mut a = VoxelGrid::new();
+        a.paint_cube((-70, -10, -10), (0, 10, 10), 1);
+        let mut b = VoxelGrid::new();
+        b.paint_sphere((-3, 0, 0), 12.0, 2);
+        let (a_materials, b_materials) =
+            (solid_materials(&a, min, max), solid_materials(&b, min, max));
+
+        let check =
+            |op: fn(&mut VoxelGrid, &VoxelGrid),
+             expected: fn(Option<Material>, Option<Material>) -> Option<Material>| {
+                let mut result = a.clone();
+                op(&mut result, &b);
+                let materials = solid_materials(&result, min, max);
+                for i in 0..materials.len() {
+                    assert_eq!(materials[i], expected(a_materials[i], b_materials[i]));
+                }
+                for chunk in result.chunks.values() {
+                    assert!(!chunk.is_empty());
+                }
+            };
 
-        (vertices, indices)
+        check(VoxelGrid::add, |a, b| b.or(a));
+        check(VoxelGrid::subtract, |a, b| a.filter(|_| b.is_none()));
+        check(VoxelGrid::intersect, |a, b| a.and(b).and(a));
+        check(VoxelGrid::xor, |a, b| match (a, b) {
+            (Some(_), Some(_)) => None,
+            _ => a.or(b),
+        });
+        check(VoxelGrid::replace, |a, b| a.and(b).or(a));
+    }
+
+    #[test]
+    fn inverting_twice_restores_the_voxels() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-10, -10, -10), (10, 0, 10), 1);
+        let (min, max) = ((-70, -5, -5), (5, 5, 5));
+        let before = solid_materials(&voxel_grid, (-80, -20, -20), (20, 20, 20));
+
+        voxel_grid.invert(min, max, 3);
+        assert_eq!(voxel_grid.read((-70, -5, -5)), 1);
+        assert_eq!(voxel_grid.read((0, 0, 0)), 0);
+        assert_eq!(voxel_grid.read((0, 1, 0)), 1);
+        assert_eq!(voxel_grid.read((0, 6, 0)), 0);
+
+        voxel_grid.invert(min, max, 3);
+        let after = solid_materials(&voxel_grid, (-80, -20, -20), (20, 20, 20));
+        let painted = |materials: &[Option<Material>]| -> Vec<bool> {
+            materials.iter().map(Option::is_some).collect()
+        };
+        assert_eq!(painted(&after), painted(&before));
+        assert_eq!(voxel_grid.chunk_coords().count(), 8);
+    }
+
+    #[test]
+    fn rigid_transforms_round_trip() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, -5, 1), (20, 19, 40), 1);
+        voxel_grid.paint_sphere((0, 0, 20), 9.5, 2);
+        let (min, max) = ((-80, -80, -80), (80, 80, 80));
+        let before = solid_materials(&voxel_grid, min, max);
+
+        // pivots on voxel centers, between voxels, and mixed
+        for pivot in [
+            vec3(0.0, 0.0, 0.0),
+            vec3(5.5, 3.0, -2.5),
+            vec3(5.0, 3.5, 7.5),
+        ] {
+            for axis in Axis::ALL {
+                let mut rotated = voxel_grid.clone();
+                for _ in 0..4 {
+                    assert_eq!(rotated.rotate(axis, 1, pivot), 0);
+                }
+                assert_eq!(solid_materials(&rotated, min, max), before);
+
+                let mut rotated = voxel_grid.clone();
+                rotated.rotate(axis, 3, pivot);
+                rotated.rotate(axis, -3, pivot);
+                assert_eq!(solid_materials(&rotated, min, max), before);
+
+                let mut mirrored = voxel_grid.clone();
+                mirrored.mirror(axis, pivot);
+                assert_ne!(solid_materials(&mirrored, min, max), before);
+                mirrored.mirror(axis, pivot);
+                assert_eq!(solid_materials(&mirrored, min, max), before);
+            }
+        }
+
+        let mut translated = voxel_grid.clone();
+        translated.translate((100, -3, 64));
+        translated.translate((-100, 3, -64));
+        assert_eq!(solid_materials(&translated, min, max), before);
+    }
+
+    #[test]
+    fn quarter_turns_go_counterclockwise() {
+        for (axis, from, to) in [
+            (Axis::X, (0, 5, 0), (0, 0, 5)),
+            (Axis::Y, (0, 0, 5), (5, 0, 0)),
+            (Axis::Z, (5, 0, 0), (0, 5, 0)),
+        ] {
+            let mut voxel_grid = VoxelGrid::new();
+            voxel_grid.paint_cube(from, from, 1);
+            voxel_grid.rotate(axis, 1, Vec3::ZERO);
+            assert_eq!(voxel_grid.bounding_box(), Some((to, to)), "{:?}", axis);
+        }
+    }
+
+    #[test]
+    fn painting_stops_at_the_world_bounds() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((WORLD_LIMIT - 2, 0, 0), (WORLD_LIMIT + 2, 1, 0), 1);
+        assert_eq!(
+            solid_voxels(
+                &voxel_grid,
+                (WORLD_LIMIT - 4, 0, 0),
+                (WORLD_LIMIT - 1, 1, 0)
+            ),
+            4
+        );
+        assert_eq!(
+            voxel_grid.bounding_box(),
+            Some(((WORLD_LIMIT - 2, 0, 0), (WORLD_LIMIT - 1, 1, 0)))
+        );
+    }
+
+    // material of a voxel, when solid
+    fn material(voxel_grid: &VoxelGrid, coords: Coords) -> Option<Material> {
+        let (chunk_coords, local) = split_coords(coords);
+        let chunk = voxel_grid.chunks.get(&chunk_coords)?;
+        (chunk.read(local) == 1).then(|| chunk.read_material(local))
+    }
+
+    #[test]
+    fn voxels_keep_the_material_last_painted() {
+        // across the chunk seam at x = 0
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-4, 0, 0), (3, 3, 3), 3);
+        voxel_grid.paint_cube((2, 0, 0), (5, 3, 3), 7);
+        assert_eq!(material(&voxel_grid, (-4, 0, 0)), Some(3));
+        assert_eq!(material(&voxel_grid, (1, 3, 3)), Some(3));
+        assert_eq!(material(&voxel_grid, (2, 0, 0)), Some(7));
+        assert_eq!(material(&voxel_grid, (5, 3, 3)), Some(7));
+        assert_eq!(material(&voxel_grid, (6, 0, 0)), None);
+
+        // the mesh only takes colors of the painted materials
+        let palette = Palette::default();
+        let (vertices, _) = voxel_grid.generate_mesh(MeshingMode::SurfaceNets, &palette);
+        let colors = [palette.color(3), palette.color(7)];
+        for color in colors {
+            assert!(vertices.iter().any(|vertex| vertex.color == color));
+        }
+        assert!(vertices.iter().all(|vertex| colors.contains(&vertex.color)));
     }
 }
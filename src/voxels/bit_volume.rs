@@ -0,0 +1,662 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::voxels::chunk::{row_mask, split_coords, CHUNK_SIZE};
+use crate::voxels::{chunk_origin, ChunkCoords, Connectivity, Coords, VoxelGrid, WORLD_LIMIT};
+
+// dense box of voxels, one bit each, such as a chunk of a grid of bits and
+// the voxels around it; rows along x are packed into u64 words, so that
+// whole rows can be shifted and combined at once
+#[derive(Clone)]
+pub struct BitVolume {
+    pub min: Coords,
+    pub size: (usize, usize, usize),
+    words: usize,
+    bits: Vec<u64>,
+}
+
+impl BitVolume {
+    pub fn new(min: Coords, size: (usize, usize, usize)) -> Self {
+        let words = size.0.div_ceil(64);
+
+        Self {
+            min,
+            size,
+            words,
+            bits: vec![0; words * size.1 * size.2],
+        }
+    }
+
+    pub fn row(&self, y: usize, z: usize) -> &[u64] {
+        let start = (z * self.size.1 + y) * self.words;
+        &self.bits[start..start + self.words]
+    }
+
+    pub fn row_mut(&mut self, y: usize, z: usize) -> &mut [u64] {
+        let start = (z * self.size.1 + y) * self.words;
+        &mut self.bits[start..start + self.words]
+    }
+
+    // swaps solid and empty voxels within the box
+    pub fn invert(&mut self) {
+        for word in &mut self.bits {
+            *word = !*word;
+        }
+        self.clear_padding();
+    }
+
+    // each voxel becomes solid when any voxel of the given rows around it
+    // is; rows are given by their offset along y and z, and how far they
+    // reach on both sides along x
+    pub fn dilate(&self, rows: &[(i32, i32, usize)]) -> Self {
+        let mut dilated = Self::new(self.min, self.size);
+        let (words, size_y, size_z) = (self.words, self.size.1, self.size.2);
+        let last_mask = self.last_mask();
+
+        dilated
+            .bits
+            .par_chunks_exact_mut(words * size_y)
+            .enumerate()
+            .for_each(|(z, slice)| {
+                let mut smeared = vec![0; words];
+                let mut scratch = (vec![0; words], vec![0; words]);
+                for (y, row) in slice.chunks_exact_mut(words).enumerate() {
+                    for (dy, dz, reach) in rows {
+                        let (source_y, source_z) = (y as i32 + dy, z as i32 + dz);
+                        if source_y < 0
+                            || source_z < 0
+                            || source_y >= size_y as i32
+                            || source_z >= size_z as i32
+                        {
+                            continue;
+                        }
+
+                        smear(
+                            self.row(source_y as usize, source_z as usize),
+                            *reach,
+                            &mut smeared,
+                            &mut scratch,
+                        );
+                        for (word, smeared) in row.iter_mut().zip(&smeared) {
+                            *word |= smeared;
+                        }
+                    }
+                    row[words - 1] &= last_mask;
+                }
+            });
+
+        dilated
+    }
+
+    // each voxel stays solid only when all voxels of the given rows around
+    // it are, the outside of the box being empty
+    pub fn erode(&self, rows: &[(i32, i32, usize)]) -> Self {
+        let mut inverted = self.clone();
+        inverted.invert();
+        let mut eroded = inverted.dilate(rows);
+        eroded.invert();
+        eroded
+    }
+
+    // each voxel becomes solid when most voxels of the cube of the given
+    // reach around it are, the outside of the box being empty; counts are
+    // summed along x, then y, then z
+    pub fn majority(&self, reach: usize) -> Self {
+        let (size_x, size_y, size_z) = self.size;
+        let width = 2 * reach + 1;
+        let threshold = width * width * width / 2;
+
+        let mut along_x = vec![0u8; size_x * size_y * size_z];
+        along_x
+            .par_chunks_exact_mut(size_x)
+            .enumerate()
+            .for_each(|(index, counts)| {
+                let row = self.row(index % size_y, index / size_y);
+                let bit = |x: usize| ((row[x / 64] >> (x % 64)) & 1) as u8;
+
+                let mut count: u8 = (0..=reach.min(size_x - 1)).map(bit).sum();
+                for (x, counts) in counts.iter_mut().enumerate() {
+                    *counts = count;
+                    if x + reach + 1 < size_x {
+                        count += bit(x + reach + 1);
+                    }
+                    if x >= reach {
+                        count -= bit(x - reach);
+                    }
+                }
+            });
+
+        let mut along_y = vec![0u8; along_x.len()];
+        along_y
+            .par_chunks_exact_mut(size_x * size_y)
+            .enumerate()
+            .for_each(|(z, slice)| {
+                let source = &along_x[z * size_x * size_y..(z + 1) * size_x * size_y];
+                for (y, counts) in slice.chunks_exact_mut(size_x).enumerate() {
+                    let (lo, hi) = (y.saturating_sub(reach), (y + reach).min(size_y - 1));
+                    for source in source[lo * size_x..(hi + 1) * size_x].chunks_exact(size_x) {
+                        for (count, source) in counts.iter_mut().zip(source) {
+                            *count += source;
+                        }
+                    }
+                }
+            });
+
+        let mut filtered = Self::new(self.min, self.size);
+        let words = self.words;
+        filtered
+            .bits
+            .par_chunks_exact_mut(words * size_y)
+            .enumerate()
+            .for_each(|(z, slice)| {
+                let (lo, hi) = (z.saturating_sub(reach), (z + reach).min(size_z - 1));
+                let mut counts = vec![0usize; size_x];
+                for (y, row) in slice.chunks_exact_mut(words).enumerate() {
+                    counts.fill(0);
+                    for source_z in lo..=hi {
+                        let start = (source_z * size_y + y) * size_x;
+                        for (count, source) in
+                            counts.iter_mut().zip(&along_y[start..start + size_x])
+                        {
+                            *count += *source as usize;
+                        }
+                    }
+                    for (x, count) in counts.iter().enumerate() {
+                        if *count > threshold {
+                            row[x / 64] |= 1 << (x % 64);
+                        }
+                    }
+                }
+            });
+
+        filtered
+    }
+
+    // copies the voxels outside of the given box from another volume
+    pub fn restore_outside(
+        &mut self,
+        other: &Self,
+        lo: (usize, usize, usize),
+        hi: (usize, usize, usize),
+    ) {
+        let words = self.words;
+        let inside_x: Vec<u64> = (0..words)
+            .map(|i| {
+                (0..64)
+                    .filter(|bit| (lo.0..=hi.0).contains(&(i * 64 + bit)))
+                    .fold(0, |mask, bit| mask | 1 << bit)
+            })
+            .collect();
+
+        for z in 0..self.size.2 {
+            for y in 0..self.size.1 {
+                let inside = (lo.1..=hi.1).contains(&y) && (lo.2..=hi.2).contains(&z);
+                let source = other.row(y, z);
+                for (i, word) in self.row_mut(y, z).iter_mut().enumerate() {
+                    let mask = if inside { inside_x[i] } else { 0 };
+                    *word = (*word & mask) | (source[i] & !mask);
+                }
+            }
+        }
+    }
+
+    // 64 bits of a row from the given voxel on, the bits past the end of the
+    // row being empty
+    fn bits(&self, y: usize, z: usize, x: usize) -> u64 {
+        let row = self.row(y, z);
+        let (index, shift) = (x / 64, x % 64);
+        let low = row.get(index).copied().unwrap_or(0) >> shift;
+        match shift {
+            0 => low,
+            _ => low | row.get(index + 1).copied().unwrap_or(0) << (64 - shift),
+        }
+    }
+
+    // ors the rows of the chunk at the origin that overlap the box, whose
+    // last voxel is max
+    fn or_chunk(&mut self, origin: Coords, max: Coords, row: impl Fn(usize, usize) -> u64) {
+        let min = self.min;
+        let (lo, hi) = (
+            (min.1 - origin.1).max(0),
+            (max.1 - origin.1).min(CHUNK_SIZE - 1),
+        );
+        let (lo_z, hi_z) = (
+            (min.2 - origin.2).max(0),
+            (max.2 - origin.2).min(CHUNK_SIZE - 1),
+        );
+        if origin.0 + CHUNK_SIZE <= min.0 || origin.0 > max.0 || lo > hi || lo_z > hi_z {
+            return;
+        }
+
+        for z in lo_z..=hi_z {
+            for y in lo..=hi {
+                self.or_bits(
+                    (origin.1 + y - min.1) as usize,
+                    (origin.2 + z - min.2) as usize,
+                    (origin.0 - min.0) as i64,
+                    row(y as usize, z as usize),
+                );
+            }
+        }
+    }
+
+    // bits of the last word of each row past the end of the box
+    fn clear_padding(&mut self) {
+        let (words, last_mask) = (self.words, self.last_mask());
+        for row in self.bits.chunks_exact_mut(words) {
+            row[words - 1] &= last_mask;
+        }
+    }
+
+    fn last_mask(&self) -> u64 {
+        match self.size.0 % 64 {
+            0 => u64::MAX,
+            used => (1 << used) - 1,
+        }
+    }
+
+    fn or_bits(&mut self, y: usize, z: usize, x: i64, bits: u64) {
+        let row = self.row_mut(y, z);
+        let (index, shift) = (x.div_euclid(64), x.rem_euclid(64) as u32);
+        let mut or_word = |index: i64, bits: u64| {
+            if index >= 0 && (index as usize) < row.len() {
+                row[index as usize] |= bits;
+            }
+        };
+
+        if shift == 0 {
+            or_word(index, bits);
+        } else {
+            or_word(index, bits << shift);
+            or_word(index + 1, bits >> (64 - shift));
+        }
+    }
+}
+
+// sparse grid of bits, in chunks laid out like the chunks of voxel grids;
+// only chunks with bits set are stored. passes run over each chunk on its
+// own, through a volume holding the chunk and the voxels within reach
+// around it, so that their cost follows the chunks in use rather than the
+// space between them
+#[derive(Clone, Default)]
+pub struct BitGrid {
+    chunks: BTreeMap<ChunkCoords, Arc<Rows>>,
+}
+
+// one u64 per row of a chunk along x, at z * 64 + y
+type Rows = [u64; 64 * 64];
+
+// voxels along x, by their row along y and z, then their first and last x
+pub type Run = (i32, i32, i32, i32);
+
+impl BitGrid {
+    // solid voxels of the grid
+    pub fn from_grid(voxel_grid: &VoxelGrid) -> Self {
+        let chunks = voxel_grid
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| !chunk.is_empty())
+            .map(|(chunk_coords, chunk)| {
+                let mut rows = [0; 64 * 64];
+                for (index, row) in rows.iter_mut().enumerate() {
+                    *row = chunk.row(index % 64, index / 64);
+                }
+                (*chunk_coords, Arc::new(rows))
+            })
+            .collect();
+
+        Self { chunks }
+    }
+
+    pub fn chunk_coords(&self) -> impl Iterator<Item = ChunkCoords> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    pub fn get(&self, coords: Coords) -> bool {
+        let (chunk_coords, (x, y, z)) = split_coords(coords);
+        match self.chunks.get(&chunk_coords) {
+            Some(rows) => (rows[z * 64 + y] >> x) & 1 == 1,
+            None => false,
+        }
+    }
+
+    // all bits set, chunk by chunk
+    pub fn voxels(&self) -> Vec<Coords> {
+        self.differences(&Self::default())
+    }
+
+    pub fn union(&mut self, other: &Self) {
+        for (chunk_coords, other_rows) in &other.chunks {
+            for (index, row) in other_rows.iter().enumerate() {
+                self.or_row(*chunk_coords, index, *row);
+            }
+        }
+    }
+
+    // flips the bits of the stored chunks; they all stay stored, even the
+    // ones left without any bit set, so that the result still tells which
+    // chunks the original had
+    pub fn inverted(&self) -> Self {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|(chunk_coords, rows)| (*chunk_coords, Arc::new(rows.map(|row| !row))))
+            .collect();
+
+        Self { chunks }
+    }
+
+    // bits within the box
+    pub fn volume(&self, min: Coords, size: (usize, usize, usize)) -> BitVolume {
+        let mut volume = BitVolume::new(min, size);
+        let max = (
+            min.0 + size.0 as i32 - 1,
+            min.1 + size.1 as i32 - 1,
+            min.2 + size.2 as i32 - 1,
+        );
+
+        let (chunk_min, _) = split_coords(min);
+        let (chunk_max, _) = split_coords(max);
+        for z in chunk_min.2..=chunk_max.2 {
+            for y in chunk_min.1..=chunk_max.1 {
+                for x in chunk_min.0..=chunk_max.0 {
+                    if let Some(rows) = self.chunks.get(&(x, y, z)) {
+                        let origin = chunk_origin((x, y, z));
+                        volume.or_chunk(origin, max, |y, z| rows[z * 64 + y]);
+                    }
+                }
+            }
+        }
+
+        volume.clear_padding();
+        volume
+    }
+
+    // stored chunks, and the chunks of the world with voxels within reach of
+    // them
+    pub fn chunks_around(&self, reach: usize) -> BTreeSet<ChunkCoords> {
+        let (world_min, _) = split_coords((-WORLD_LIMIT, -WORLD_LIMIT, -WORLD_LIMIT));
+        let (world_max, _) = split_coords((WORLD_LIMIT - 1, WORLD_LIMIT - 1, WORLD_LIMIT - 1));
+        let reach = reach
+            .div_ceil(CHUNK_SIZE as usize)
+            .min(world_max.0 as usize) as i32;
+
+        let mut chunks = BTreeSet::new();
+        for chunk_coords in self.chunks.keys() {
+            let min = (
+                (chunk_coords.0 - reach).max(world_min.0),
+                (chunk_coords.1 - reach).max(world_min.1),
+                (chunk_coords.2 - reach).max(world_min.2),
+            );
+            let max = (
+                (chunk_coords.0 + reach).min(world_max.0),
+                (chunk_coords.1 + reach).min(world_max.1),
+                (chunk_coords.2 + reach).min(world_max.2),
+            );
+            for z in min.2..=max.2 {
+                for y in min.1..=max.1 {
+                    chunks.extend((min.0..=max.0).map(|x| (x, y, z)));
+                }
+            }
+        }
+        chunks
+    }
+
+    // runs the pass on each of the target chunks in parallel, through a
+    // volume holding the chunk and the given halo of voxels around it, and
+    // keeps the voxels of the chunk from the result; the other chunks stay
+    // as they are
+    pub fn map(
+        &self,
+        targets: &BTreeSet<ChunkCoords>,
+        halo: usize,
+        pass: impl Fn(&BitVolume) -> BitVolume + Sync,
+    ) -> Self {
+        let size = CHUNK_SIZE as usize + 2 * halo;
+        let results: Vec<(ChunkCoords, Option<Arc<Rows>>)> = targets
+            .par_iter()
+            .map(|chunk_coords| {
+                let origin = chunk_origin(*chunk_coords);
+                let min = (
+                    origin.0 - halo as i32,
+                    origin.1 - halo as i32,
+                    origin.2 - halo as i32,
+                );
+                let volume = pass(&self.volume(min, (size, size, size)));
+
+                let mut rows = [0; 64 * 64];
+                for (index, row) in rows.iter_mut().enumerate() {
+                    *row = volume.bits(index % 64 + halo, index / 64 + halo, halo);
+                }
+                let rows = rows.iter().any(|row| *row != 0).then(|| Arc::new(rows));
+                (*chunk_coords, rows)
+            })
+            .collect();
+
+        let mut chunks = self.chunks.clone();
+        for (chunk_coords, rows) in results {
+            match rows {
+                Some(rows) => chunks.insert(chunk_coords, rows),
+                None => chunks.remove(&chunk_coords),
+            };
+        }
+
+        Self { chunks }
+    }
+
+    // floods the bits connected to the seeds, skipping the ones already set
+    // in filled and setting the ones reached; each run filled along x is
+    // visited with its row and first and last voxels. runs stop at the end
+    // of chunks, and go on through the same row of the next one. returns
+    // whether the flood reached chunks that are not stored
+    pub fn flood_runs(
+        &self,
+        seeds: Vec<Coords>,
+        connectivity: Connectivity,
+        filled: &mut Self,
+        mut visit: impl FnMut(i32, i32, i32, i32),
+    ) -> bool {
+        // neighboring rows, and how far past the ends of a run its
+        // neighbors are in them
+        let rows: &[(i32, i32, i32)] = match connectivity {
+            Connectivity::Faces => &[(0, 0, 1), (-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0)],
+            Connectivity::Corners => &[
+                (0, 0, 1),
+                (-1, -1, 1),
+                (0, -1, 1),
+                (1, -1, 1),
+                (-1, 0, 1),
+                (1, 0, 1),
+                (-1, 1, 1),
+                (0, 1, 1),
+                (1, 1, 1),
+            ],
+        };
+
+        let mut escaped = false;
+        let mut stack = seeds;
+        while let Some(coords) = stack.pop() {
+            let (chunk_coords, (x, y, z)) = split_coords(coords);
+            let index = z * 64 + y;
+            let open = self.row(chunk_coords, index) & !filled.row(chunk_coords, index);
+            if (open >> x) & 1 == 0 {
+                continue;
+            }
+
+            // the run of open bits around the seed
+            let hi = x + (!(open >> x)).trailing_zeros() as usize - 1;
+            let lo = x + 1 - (!(open << (63 - x))).leading_zeros() as usize;
+            filled.or_row(chunk_coords, index, row_mask(lo, hi));
+            let origin = chunk_origin(chunk_coords);
+            let (lo, hi) = (origin.0 + lo as i32, origin.0 + hi as i32);
+            visit(coords.1, coords.2, lo, hi);
+
+            for (dy, dz, reach) in rows {
+                let (y, z) = (coords.1 + dy, coords.2 + dz);
+                let (mut x, last) = (lo - reach, hi + reach);
+                while x <= last {
+                    let (chunk_coords, (local_x, local_y, local_z)) = split_coords((x, y, z));
+                    let start = x - local_x as i32;
+                    let local_last = (last - start).min(CHUNK_SIZE - 1) as usize;
+                    x = start + CHUNK_SIZE;
+
+                    let rows = match self.chunks.get(&chunk_coords) {
+                        Some(rows) => rows,
+                        None => {
+                            escaped = true;
+                            continue;
+                        }
+                    };
+                    let index = local_z * 64 + local_y;
+                    let mut open = rows[index]
+                        & !filled.row(chunk_coords, index)
+                        & row_mask(local_x, local_last);
+
+                    // a seed for each run of open bits
+                    while open != 0 {
+                        let first = open.trailing_zeros() as usize;
+                        let length = (!(open >> first)).trailing_zeros() as usize;
+                        stack.push((start + first as i32, y, z));
+                        open &= !row_mask(first, first + length - 1);
+                    }
+                }
+            }
+        }
+
+        escaped
+    }
+
+    // floods each region of connected bits in turn, and visits the runs
+    // filling it, and whether it reached chunks that are not stored
+    pub fn regions(&self, connectivity: Connectivity, mut visit: impl FnMut(&[Run], bool)) {
+        let mut visited = Self::default();
+        let mut runs = vec![];
+        for (chunk_coords, rows) in &self.chunks {
+            let origin = chunk_origin(*chunk_coords);
+            for (index, row) in rows.iter().enumerate() {
+                loop {
+                    let open = row & !visited.row(*chunk_coords, index);
+                    if open == 0 {
+                        break;
+                    }
+
+                    let seed = (
+                        origin.0 + open.trailing_zeros() as i32,
+                        origin.1 + (index % 64) as i32,
+                        origin.2 + (index / 64) as i32,
+                    );
+                    runs.clear();
+                    let escaped =
+                        self.flood_runs(vec![seed], connectivity, &mut visited, |y, z, lo, hi| {
+                            runs.push((y, z, lo, hi))
+                        });
+                    visit(&runs, escaped);
+                }
+            }
+        }
+    }
+
+    // sets a run within a single chunk
+    pub fn set_run(&mut self, (y, z, lo, hi): Run) {
+        let (chunk_coords, (x, y, z)) = split_coords((lo, y, z));
+        self.or_row(
+            chunk_coords,
+            z * 64 + y,
+            row_mask(x, x + (hi - lo) as usize),
+        );
+    }
+
+    // sets every bit of a chunk
+    pub fn fill_chunk(&mut self, chunk_coords: ChunkCoords) {
+        self.chunks.insert(chunk_coords, Arc::new([!0; 64 * 64]));
+    }
+
+    // voxels set in one grid but not in the other, chunk by chunk
+    pub fn differences(&self, other: &Self) -> Vec<Coords> {
+        let empty = [0; 64 * 64];
+        let chunk_coords: BTreeSet<ChunkCoords> =
+            self.chunk_coords().chain(other.chunk_coords()).collect();
+
+        let mut differences = vec![];
+        for chunk_coords in chunk_coords {
+            let rows = self.chunks.get(&chunk_coords).map_or(&empty, |rows| rows);
+            let other_rows = other.chunks.get(&chunk_coords).map_or(&empty, |rows| rows);
+            let origin = chunk_origin(chunk_coords);
+            for (index, (row, other_row)) in rows.iter().zip(other_rows).enumerate() {
+                let mut bits = row ^ other_row;
+                while bits != 0 {
+                    differences.push((
+                        origin.0 + bits.trailing_zeros() as i32,
+                        origin.1 + (index % 64) as i32,
+                        origin.2 + (index / 64) as i32,
+                    ));
+                    bits &= bits - 1;
+                }
+            }
+        }
+        differences
+    }
+
+    fn row(&self, chunk_coords: ChunkCoords, index: usize) -> u64 {
+        self.chunks.get(&chunk_coords).map_or(0, |rows| rows[index])
+    }
+
+    fn or_row(&mut self, chunk_coords: ChunkCoords, index: usize, bits: u64) {
+        if bits != 0 {
+            let rows = self
+                .chunks
+                .entry(chunk_coords)
+                .or_insert_with(|| Arc::new([0; 64 * 64]));
+            Arc::make_mut(rows)[index] |= bits;
+        }
+    }
+}
+
+// or of a row shifted by all amounts within reach on both sides; each pass
+// doubles the covered span
+fn smear(row: &[u64], reach: usize, out: &mut [u64], (up, down): &mut (Vec<u64>, Vec<u64>)) {
+    out.copy_from_slice(row);
+
+    let mut span = 0;
+    while span < reach {
+        let step = (span + 1).min(reach - span);
+
+        shift_up(out, step, up);
+        shift_down(out, step, down);
+        for ((word, up), down) in out.iter_mut().zip(up.iter()).zip(down.iter()) {
+            *word |= up | down;
+        }
+
+        span += step;
+    }
+}
+
+// towards higher x
+fn shift_up(row: &[u64], amount: usize, out: &mut [u64]) {
+    let (words, bits) = (amount / 64, (amount % 64) as u32);
+    for (i, out) in out.iter_mut().enumerate() {
+        let low = if i >= words { row[i - words] } else { 0 };
+        let lower = if i > words { row[i - words - 1] } else { 0 };
+        *out = if bits == 0 {
+            low
+        } else {
+            (low << bits) | (lower >> (64 - bits))
+        };
+    }
+}
+
+// towards lower x
+fn shift_down(row: &[u64], amount: usize, out: &mut [u64]) {
+    let (words, bits) = (amount / 64, (amount % 64) as u32);
+    for (i, out) in out.iter_mut().enumerate() {
+        let high = row.get(i + words).copied().unwrap_or(0);
+        let higher = row.get(i + words + 1).copied().unwrap_or(0);
+        *out = if bits == 0 {
+            high
+        } else {
+            (high >> bits) | (higher << (64 - bits))
+        };
+    }
+}
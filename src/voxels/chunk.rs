@@ -0,0 +1,426 @@
+use crate::voxels::Material;
+
+pub const CHUNK_SIZE: i32 = 64;
+
+// signed distances are quantized to 1/32 of a voxel, negative inside
+pub const DISTANCE_SCALE: f32 = 32.0;
+
+// coordinates of a voxel within its chunk
+pub type LocalCoords = (usize, usize, usize);
+
+// marks a voxel without a distance value, which only knows whether it is solid
+const HARD: i8 = i8::MIN;
+
+// one bit per voxel, each u64 row spans the whole chunk along x; the
+// material of each voxel sits at the same position in a byte array, and is
+// only meaningful when the voxel is solid
+//
+// chunks touched by smooth brushes also store a signed distance per voxel.
+// the occupancy bits always decide which voxels are solid, distances only
+// refine where the surface crosses between two voxels
+#[derive(Clone)]
+pub struct Chunk {
+    data: [u64; 64 * 64],
+    materials: Box<[Material]>,
+    distances: Option<Box<[i8]>>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        let data = [0u64; 64 * 64];
+        let materials = vec![0; 64 * 64 * 64].into_boxed_slice();
+
+        Self {
+            data,
+            materials,
+            distances: None,
+        }
+    }
+
+    pub fn read(&self, (x, y, z): (usize, usize, usize)) -> u64 {
+        let line = self.data[z * 64 + y];
+        (line >> x) & 1
+    }
+
+    // all voxels along x, one per bit
+    pub fn row(&self, y: usize, z: usize) -> u64 {
+        self.data[z * 64 + y]
+    }
+
+    pub fn read_material(&self, (x, y, z): (usize, usize, usize)) -> Material {
+        self.materials[(z * 64 + y) * 64 + x]
+    }
+
+    // signed distance to the surface; hard voxels report the surface halfway
+    // to their neighbors, like a cube would
+    pub fn read_distance(&self, (x, y, z): (usize, usize, usize)) -> f32 {
+        let index = (z * 64 + y) * 64 + x;
+        let solid = (self.data[z * 64 + y] >> x) & 1 == 1;
+        match &self.distances {
+            Some(distances) if distances[index] != HARD => distances[index] as f32 / DISTANCE_SCALE,
+            _ => {
+                if solid {
+                    -0.5
+                } else {
+                    0.5
+                }
+            }
+        }
+    }
+
+    // the same, except for hard empty voxels, which do not know how far the
+    // surface is
+    pub fn read_known_distance(&self, (x, y, z): (usize, usize, usize)) -> Option<f32> {
+        let index = (z * 64 + y) * 64 + x;
+        let solid = (self.data[z * 64 + y] >> x) & 1 == 1;
+        let quantized = match &self.distances {
+            Some(distances) => distances[index],
+            None => HARD,
+        };
+        known_distance(quantized, solid)
+    }
+
+    // fills voxels lo..=hi of a row, as hard voxels
+    pub fn fill_row(&mut self, y: usize, z: usize, lo: usize, hi: usize, material: Material) {
+        let row = z * 64 + y;
+        self.data[row] |= row_mask(lo, hi);
+        self.materials[row * 64 + lo..=row * 64 + hi].fill(material);
+        if let Some(distances) = &mut self.distances {
+            distances[row * 64 + lo..=row * 64 + hi].fill(HARD);
+        }
+    }
+
+    // merges a signed distance into a voxel, keeping the closest surface
+    pub fn union_distance(
+        &mut self,
+        (x, y, z): (usize, usize, usize),
+        distance: f32,
+        material: Material,
+    ) {
+        let row = z * 64 + y;
+        let index = row * 64 + x;
+        let solid = (self.data[row] >> x) & 1 == 1;
+
+        let distances = self
+            .distances
+            .get_or_insert_with(|| vec![HARD; 64 * 64 * 64].into_boxed_slice());
+        let distance = match known_distance(distances[index], solid) {
+            Some(previous) => previous.min(distance),
+            None => distance,
+        };
+
+        if distance <= 0.0 {
+            self.data[row] |= 1 << x;
+            if !solid {
+                self.materials[index] = material;
+            }
+        }
+        distances[index] = quantize(distance, distance <= 0.0 || solid);
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        if self.distances.is_some() || other.distances.is_some() {
+            self.combine_distances(other, BooleanOp::Union);
+        }
+
+        for (row, other_row) in other.data.iter().enumerate() {
+            self.data[row] |= other_row;
+
+            // solid voxels of the other chunk bring their own material
+            let mut bits = *other_row;
+            while bits != 0 {
+                let index = row * 64 + bits.trailing_zeros() as usize;
+                self.materials[index] = other.materials[index];
+                bits &= bits - 1;
+            }
+        }
+    }
+
+    pub fn subtract(&mut self, other: &Self) {
+        if self.distances.is_some() || other.distances.is_some() {
+            self.combine_distances(other, BooleanOp::Difference);
+        }
+
+        for (row, other_row) in self.data.iter_mut().zip(other.data.iter()) {
+            *row &= !other_row;
+        }
+    }
+
+    pub fn intersect(&mut self, other: &Self) {
+        if self.distances.is_some() || other.distances.is_some() {
+            self.combine_distances(other, BooleanOp::Intersection);
+        }
+
+        for (row, other_row) in self.data.iter_mut().zip(other.data.iter()) {
+            *row &= other_row;
+        }
+    }
+
+    pub fn xor(&mut self, other: &Self) {
+        if self.distances.is_some() || other.distances.is_some() {
+            self.combine_distances(other, BooleanOp::SymmetricDifference);
+        }
+
+        for (row, other_row) in other.data.iter().enumerate() {
+            // voxels only solid in the other chunk bring their own material
+            let mut bits = other_row & !self.data[row];
+            while bits != 0 {
+                let index = row * 64 + bits.trailing_zeros() as usize;
+                self.materials[index] = other.materials[index];
+                bits &= bits - 1;
+            }
+
+            self.data[row] ^= other_row;
+        }
+    }
+
+    // repaints the solid voxels of this chunk that are also solid in the
+    // other one, without changing the shape
+    pub fn replace(&mut self, other: &Self) {
+        for (row, other_row) in other.data.iter().enumerate() {
+            let mut bits = other_row & self.data[row];
+            while bits != 0 {
+                let index = row * 64 + bits.trailing_zeros() as usize;
+                self.materials[index] = other.materials[index];
+                bits &= bits - 1;
+            }
+        }
+    }
+
+    // flips voxels lo..=hi of a row; voxels becoming solid get the material
+    pub fn invert_row(&mut self, y: usize, z: usize, lo: usize, hi: usize, material: Material) {
+        let row = z * 64 + y;
+        let solid = self.data[row];
+        self.data[row] ^= row_mask(lo, hi);
+
+        for x in lo..=hi {
+            let index = row * 64 + x;
+            let was_solid = (solid >> x) & 1 == 1;
+            if !was_solid {
+                self.materials[index] = material;
+            }
+
+            // the surface stays where it was, seen from the other side
+            if let Some(distances) = &mut self.distances {
+                if distances[index] != HARD {
+                    let distance = distances[index] as f32 / DISTANCE_SCALE;
+                    distances[index] = quantize(-distance, !was_solid);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|row| *row == 0)
+    }
+
+    // smallest box holding all solid voxels, inclusive
+    pub fn bounds(&self) -> Option<(LocalCoords, LocalCoords)> {
+        let mut bounds: Option<(LocalCoords, LocalCoords)> = None;
+        for (row, bits) in self.data.iter().enumerate() {
+            if *bits == 0 {
+                continue;
+            }
+            let (y, z) = (row % 64, row / 64);
+            let (lo, hi) = (
+                bits.trailing_zeros() as usize,
+                63 - bits.leading_zeros() as usize,
+            );
+            bounds = Some(match bounds {
+                Some((min, max)) => (
+                    (min.0.min(lo), min.1.min(y), min.2.min(z)),
+                    (max.0.max(hi), max.1.max(y), max.2.max(z)),
+                ),
+                None => ((lo, y, z), (hi, y, z)),
+            });
+        }
+        bounds
+    }
+
+    // voxels holding anything: solid ones, and empty ones with a distance
+    pub fn stored_voxels(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        (0..64 * 64).flat_map(move |row| {
+            let mut bits = self.data[row];
+            if let Some(distances) = &self.distances {
+                for x in 0..64 {
+                    if distances[row * 64 + x] != HARD {
+                        bits |= 1 << x;
+                    }
+                }
+            }
+
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let x = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some((x, row % 64, row / 64))
+            })
+        })
+    }
+
+    pub fn get(&self, (x, y, z): (usize, usize, usize)) -> Voxel {
+        let index = (z * 64 + y) * 64 + x;
+        Voxel {
+            solid: (self.data[z * 64 + y] >> x) & 1 == 1,
+            material: self.materials[index],
+            distance: match &self.distances {
+                Some(distances) => distances[index],
+                None => HARD,
+            },
+        }
+    }
+
+    pub fn set(&mut self, (x, y, z): (usize, usize, usize), voxel: Voxel) {
+        let row = z * 64 + y;
+        let index = row * 64 + x;
+        if voxel.solid {
+            self.data[row] |= 1 << x;
+        } else {
+            self.data[row] &= !(1 << x);
+        }
+        self.materials[index] = voxel.material;
+        if voxel.distance != HARD || self.distances.is_some() {
+            self.distances
+                .get_or_insert_with(|| vec![HARD; 64 * 64 * 64].into_boxed_slice())[index] =
+                voxel.distance;
+        }
+    }
+
+    // rewrites the distances of this chunk before a boolean operation with
+    // another chunk, while the occupancy bits are still untouched
+    fn combine_distances(&mut self, other: &Self, op: BooleanOp) {
+        let distances = self
+            .distances
+            .get_or_insert_with(|| vec![HARD; 64 * 64 * 64].into_boxed_slice());
+
+        for row in 0..64 * 64 {
+            let (bits, other_bits) = (self.data[row], other.data[row]);
+            for x in 0..64 {
+                let index = row * 64 + x;
+                let other_quantized = match &other.distances {
+                    Some(other_distances) => other_distances[index],
+                    None => HARD,
+                };
+                if distances[index] == HARD && other_quantized == HARD {
+                    continue;
+                }
+
+                let solid = (bits >> x) & 1 == 1;
+                let other_solid = (other_bits >> x) & 1 == 1;
+                let distance = known_distance(distances[index], solid);
+                let other_distance = known_distance(other_quantized, other_solid);
+
+                distances[index] = match op {
+                    BooleanOp::Union => match (distance, other_distance) {
+                        (Some(a), Some(b)) => quantize(a.min(b), solid || other_solid),
+                        (Some(a), None) => quantize(a, solid),
+                        (None, Some(b)) => quantize(b, other_solid),
+                        (None, None) => HARD,
+                    },
+                    BooleanOp::Difference => match (distance, other_distance) {
+                        (Some(a), Some(b)) => quantize(a.max(-b), solid && !other_solid),
+                        (_, None) => distances[index],
+                        (None, Some(_)) => HARD,
+                    },
+                    BooleanOp::Intersection => match (distance, other_distance) {
+                        (Some(a), Some(b)) => quantize(a.max(b), solid && other_solid),
+                        _ => HARD,
+                    },
+                    BooleanOp::SymmetricDifference => match (distance, other_distance) {
+                        (Some(a), Some(b)) => {
+                            quantize(a.min(b).max(-a.max(b)), solid != other_solid)
+                        }
+                        (_, None) => distances[index],
+                        (None, Some(b)) => quantize(b, other_solid),
+                    },
+                };
+            }
+        }
+    }
+}
+
+// everything a chunk stores about a voxel
+#[derive(Clone, Copy)]
+pub struct Voxel {
+    pub solid: bool,
+    pub material: Material,
+    distance: i8,
+}
+
+impl Voxel {
+    pub fn hard(solid: bool, material: Material) -> Self {
+        Self {
+            solid,
+            material,
+            distance: HARD,
+        }
+    }
+
+    // hard empty voxels are the same as no voxel at all
+    pub fn is_stored(&self) -> bool {
+        self.solid || self.distance != HARD
+    }
+
+    // the same voxel in a grid scaled by the factor, where distances measure
+    // that many more voxels
+    pub fn scaled(self, factor: f32) -> Self {
+        match self.distance {
+            HARD => self,
+            distance => Self {
+                distance: quantize(distance as f32 / DISTANCE_SCALE * factor, self.solid),
+                ..self
+            },
+        }
+    }
+}
+
+enum BooleanOp {
+    Union,
+    Difference,
+    Intersection,
+    SymmetricDifference,
+}
+
+// hard empty voxels do not know how far the surface is, as opposed to hard
+// solid voxels which are at least half a voxel deep
+fn known_distance(quantized: i8, solid: bool) -> Option<f32> {
+    match (quantized, solid) {
+        (HARD, true) => Some(-0.5),
+        (HARD, false) => None,
+        (quantized, _) => Some(quantized as f32 / DISTANCE_SCALE),
+    }
+}
+
+// solid voxels never get a positive distance and empty voxels never get a
+// negative or zero one, so that the surface always crosses between the two
+fn quantize(distance: f32, solid: bool) -> i8 {
+    let quantized = (distance * DISTANCE_SCALE).round().clamp(-127.0, 127.0) as i8;
+    if solid {
+        quantized.min(0)
+    } else {
+        quantized.max(1)
+    }
+}
+
+// splits world coordinates into chunk coordinates and local coordinates
+pub fn split_coords((x, y, z): (i32, i32, i32)) -> ((i32, i32, i32), (usize, usize, usize)) {
+    (
+        (
+            x.div_euclid(CHUNK_SIZE),
+            y.div_euclid(CHUNK_SIZE),
+            z.div_euclid(CHUNK_SIZE),
+        ),
+        (
+            x.rem_euclid(CHUNK_SIZE) as usize,
+            y.rem_euclid(CHUNK_SIZE) as usize,
+            z.rem_euclid(CHUNK_SIZE) as usize,
+        ),
+    )
+}
+
+// bits lo..=hi set
+pub fn row_mask(lo: usize, hi: usize) -> u64 {
+    (u64::MAX >> (63 - hi)) & (u64::MAX << lo)
+}
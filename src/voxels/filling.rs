@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use crate::voxels::bit_volume::{BitGrid, Run};
+use crate::voxels::chunk::{split_coords, CHUNK_SIZE};
+use crate::voxels::{ChunkCoords, Coords, Material, VoxelGrid};
+
+// which voxels around each voxel it connects to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connectivity {
+    // the 6 voxels sharing a face
+    Faces,
+
+    // the 26 voxels sharing a face, an edge or a corner
+    Corners,
+}
+
+impl VoxelGrid {
+    // paints the solid voxels connected to the seed with the material, or
+    // fills the empty voxels connected to it when it is empty; empty space
+    // reaching past the bounding box of the solid voxels counts as the
+    // outside of the grid, and is left alone. returns how many voxels were
+    // painted or filled
+    pub fn flood_fill(
+        &mut self,
+        seed: Coords,
+        connectivity: Connectivity,
+        material: Material,
+    ) -> usize {
+        let grid = BitGrid::from_grid(self);
+
+        if grid.get(seed) {
+            let mut region = BitGrid::default();
+            grid.flood_runs(vec![seed], connectivity, &mut region, |_, _, _, _| {});
+            let voxels = region.voxels();
+
+            self.begin_edit();
+            for coords in &voxels {
+                let (chunk_coords, local) = split_coords(*coords);
+                let chunk = self.chunk_mut(chunk_coords);
+                let mut voxel = chunk.get(local);
+                voxel.material = material;
+                chunk.set(local, voxel);
+            }
+            voxels.len()
+        } else {
+            let empty = match EmptySpace::new(self, &grid, connectivity) {
+                Some(empty) => empty,
+                None => return 0,
+            };
+            if !empty.voxels.get(seed) {
+                return 0;
+            }
+
+            let mut region = BitGrid::default();
+            let mut outside = false;
+            let escaped =
+                empty
+                    .voxels
+                    .flood_runs(vec![seed], connectivity, &mut region, |y, z, lo, hi| {
+                        outside |= empty.outside((y, z, lo, hi))
+                    });
+            if escaped || outside {
+                return 0;
+            }
+
+            let voxels = region.voxels();
+            let materials = voxels.iter().map(|coords| (*coords, material)).collect();
+            let mut filled = grid.clone();
+            filled.union(&region);
+            self.write_grid(&grid, &filled, &materials);
+            voxels.len()
+        }
+    }
+
+    // fills the empty space that does not reach past the bounding box of
+    // the solid voxels, which counts as the outside of the grid; each filled
+    // voxel takes the material of the closest wall. returns how many voxels
+    // were filled
+    pub fn fill_cavities(&mut self, connectivity: Connectivity) -> usize {
+        let grid = BitGrid::from_grid(self);
+        let empty = match EmptySpace::new(self, &grid, connectivity) {
+            Some(empty) => empty,
+            None => return 0,
+        };
+
+        let mut cavities = BitGrid::default();
+        empty.voxels.regions(connectivity, |runs, escaped| {
+            if !escaped && !runs.iter().any(|run| empty.outside(*run)) {
+                for run in runs {
+                    cavities.set_run(*run);
+                }
+            }
+        });
+
+        let voxels = cavities.voxels();
+        if voxels.is_empty() {
+            return 0;
+        }
+
+        // spreads the materials of the walls inwards, one voxel at a time
+        let mut materials: HashMap<Coords, Material> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for coords in &voxels {
+            let wall = FACES
+                .iter()
+                .map(|offset| neighbor(*coords, *offset))
+                .find(|neighbor| grid.get(*neighbor));
+            if let Some(wall) = wall {
+                materials.insert(*coords, self.voxel(wall).material);
+                queue.push_back(*coords);
+            }
+        }
+        let mut filled = grid.clone();
+        filled.union(&cavities);
+        while let Some(coords) = queue.pop_front() {
+            let material = materials[&coords];
+            for neighbor in FACES.iter().map(|offset| neighbor(coords, *offset)) {
+                if filled.get(neighbor) && !grid.get(neighbor) && !materials.contains_key(&neighbor)
+                {
+                    materials.insert(neighbor, material);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        self.write_grid(&grid, &filled, &materials);
+        voxels.len()
+    }
+}
+
+// the empty space that solid voxels may enclose: the empty voxels of the
+// chunks holding solid voxels, and the chunks without any that only reach
+// such chunks, as whole blocks. floods reaching the chunks left out, or
+// past the bounding box of the solid voxels, are outside
+struct EmptySpace {
+    voxels: BitGrid,
+    min: Coords,
+    max: Coords,
+}
+
+impl EmptySpace {
+    fn new(voxel_grid: &VoxelGrid, grid: &BitGrid, connectivity: Connectivity) -> Option<Self> {
+        let (min, max) = voxel_grid.bounding_box()?;
+        let mut voxels = grid.inverted();
+        for chunk_coords in enclosed_chunks(grid, min, max, connectivity) {
+            voxels.fill_chunk(chunk_coords);
+        }
+
+        Some(Self { voxels, min, max })
+    }
+
+    fn outside(&self, (y, z, lo, hi): Run) -> bool {
+        lo < self.min.0
+            || hi > self.max.0
+            || y < self.min.1
+            || y > self.max.1
+            || z < self.min.2
+            || z > self.max.2
+    }
+}
+
+// chunks without solid voxels, within the bounding box of the solid voxels,
+// that are not connected through other such chunks to one reaching past it
+fn enclosed_chunks(
+    grid: &BitGrid,
+    min: Coords,
+    max: Coords,
+    connectivity: Connectivity,
+) -> Vec<ChunkCoords> {
+    // chunks entirely within the bounding box
+    let first = [
+        (min.0 + CHUNK_SIZE - 1).div_euclid(CHUNK_SIZE),
+        (min.1 + CHUNK_SIZE - 1).div_euclid(CHUNK_SIZE),
+        (min.2 + CHUNK_SIZE - 1).div_euclid(CHUNK_SIZE),
+    ];
+    let last = [
+        (max.0 + 1).div_euclid(CHUNK_SIZE) - 1,
+        (max.1 + 1).div_euclid(CHUNK_SIZE) - 1,
+        (max.2 + 1).div_euclid(CHUNK_SIZE) - 1,
+    ];
+    if (0..3).any(|axis| first[axis] > last[axis]) {
+        return vec![];
+    }
+    let within =
+        |chunk: [i32; 3]| (0..3).all(|axis| (first[axis]..=last[axis]).contains(&chunk[axis]));
+
+    // stored chunks along each axis, by their coordinates along the others
+    let stored: BTreeSet<[i32; 3]> = grid.chunk_coords().map(|(x, y, z)| [x, y, z]).collect();
+    let mut lines: [BTreeMap<(i32, i32), BTreeSet<i32>>; 3] = Default::default();
+    for chunk in &stored {
+        for (axis, line) in lines.iter_mut().enumerate() {
+            let others = (chunk[(axis + 1) % 3], chunk[(axis + 2) % 3]);
+            line.entry(others).or_default().insert(chunk[axis]);
+        }
+    }
+
+    // a chunk past the box without solid voxels is reached in a straight
+    // line when no stored chunk is in the way
+    let reaches_out = |chunk: [i32; 3]| {
+        (0..3).any(|axis| {
+            let others = (chunk[(axis + 1) % 3], chunk[(axis + 2) % 3]);
+            match lines[axis].get(&others) {
+                Some(line) => {
+                    line.range(first[axis] - 1..chunk[axis]).next().is_none()
+                        || line
+                            .range(chunk[axis] + 1..=last[axis] + 1)
+                            .next()
+                            .is_none()
+                }
+                None => true,
+            }
+        })
+    };
+
+    let offsets: Vec<[i32; 3]> = match connectivity {
+        Connectivity::Faces => FACES.iter().map(|(x, y, z)| [*x, *y, *z]).collect(),
+        Connectivity::Corners => (0..27)
+            .map(|index| [index % 3 - 1, index / 3 % 3 - 1, index / 9 - 1])
+            .filter(|offset| *offset != [0, 0, 0])
+            .collect(),
+    };
+    let step = |chunk: [i32; 3], offset: [i32; 3]| {
+        [
+            chunk[0] + offset[0],
+            chunk[1] + offset[1],
+            chunk[2] + offset[2],
+        ]
+    };
+
+    // floods the chunks without solid voxels next to stored ones, and stops
+    // as soon as one reaches out
+    let mut outside = BTreeSet::new();
+    let mut enclosed = BTreeSet::new();
+    for chunk in &stored {
+        for offset in &offsets {
+            let seed = step(*chunk, *offset);
+            if !within(seed)
+                || stored.contains(&seed)
+                || outside.contains(&seed)
+                || enclosed.contains(&seed)
+            {
+                continue;
+            }
+
+            let mut visited = BTreeSet::from([seed]);
+            let mut stack = vec![seed];
+            let mut escaped = false;
+            while let Some(chunk) = stack.pop() {
+                if outside.contains(&chunk) || reaches_out(chunk) {
+                    escaped = true;
+                    break;
+                }
+                for offset in &offsets {
+                    let next = step(chunk, *offset);
+                    if stored.contains(&next) {
+                        continue;
+                    }
+                    if !within(next) {
+                        escaped = true;
+                        break;
+                    }
+                    if visited.insert(next) {
+                        stack.push(next);
+                    }
+                }
+                if escaped {
+                    break;
+                }
+            }
+
+            if escaped {
+                outside.extend(visited);
+            } else {
+                enclosed.extend(visited);
+            }
+        }
+    }
+
+    enclosed.into_iter().map(|[x, y, z]| (x, y, z)).collect()
+}
+
+const FACES: [Coords; 6] = [
+    (-1, 0, 0),
+    (1, 0, 0),
+    (0, -1, 0),
+    (0, 1, 0),
+    (0, 0, -1),
+    (0, 0, 1),
+];
+
+fn neighbor(coords: Coords, offset: Coords) -> Coords {
+    (
+        coords.0 + offset.0,
+        coords.1 + offset.1,
+        coords.2 + offset.2,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    // a box with walls 2 voxels thick, around an empty inside
+    fn hollow_box(voxel_grid: &mut VoxelGrid, min: Coords, max: Coords, material: Material) {
+        voxel_grid.paint_cube(min, max, material);
+        let mut inside = VoxelGrid::new();
+        inside.paint_cube(
+            (min.0 + 2, min.1 + 2, min.2 + 2),
+            (max.0 - 2, max.1 - 2, max.2 - 2),
+            0,
+        );
+        voxel_grid.subtract(&inside);
+    }
+
+    #[test]
+    fn far_apart_cavities_are_filled() {
+        let mut voxel_grid = VoxelGrid::new();
+        hollow_box(&mut voxel_grid, (-10, -10, -10), (9, 9, 9), 1);
+        hollow_box(
+            &mut voxel_grid,
+            (20000, 20000, 2000),
+            (20019, 20019, 2019),
+            2,
+        );
+
+        assert_eq!(
+            voxel_grid.fill_cavities(Connectivity::Faces),
+            2 * 16 * 16 * 16
+        );
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels.len(), 2 * 20 * 20 * 20);
+        assert_eq!(voxels[&(0, 0, 0)], 1);
+        assert_eq!(voxels[&(20010, 20010, 2010)], 2);
+    }
+
+    #[test]
+    fn cracked_walls_leave_cavities_open() {
+        let mut voxel_grid = VoxelGrid::new();
+        hollow_box(&mut voxel_grid, (-10, -10, -10), (9, 9, 9), 1);
+
+        // two empty voxels through the wall, touching along an edge only
+        let mut crack = VoxelGrid::new();
+        crack.paint_cube((8, 8, 0), (8, 8, 0), 0);
+        crack.paint_cube((9, 9, 0), (9, 9, 0), 0);
+        voxel_grid.subtract(&crack);
+
+        let mut corners = voxel_grid.clone();
+        assert_eq!(corners.fill_cavities(Connectivity::Corners), 0);
+        assert_eq!(corners.flood_fill((0, 0, 0), Connectivity::Corners, 3), 0);
+        assert_eq!(solid(&corners), solid(&voxel_grid));
+
+        // through faces, the crack touches neither the inside nor the outside
+        let mut faces = voxel_grid.clone();
+        assert_eq!(
+            faces.flood_fill((0, 0, 0), Connectivity::Faces, 3),
+            16 * 16 * 16
+        );
+        assert_eq!(solid(&faces)[&(0, 0, 0)], 3);
+    }
+
+    #[test]
+    fn empty_chunks_within_walls_are_filled() {
+        // the inside of this box covers a whole chunk without solid voxels
+        let mut voxel_grid = VoxelGrid::new();
+        hollow_box(&mut voxel_grid, (-3, -3, -3), (66, 66, 66), 1);
+        let mut flooded = voxel_grid.clone();
+        assert_eq!(voxel_grid.fill_cavities(Connectivity::Faces), 66 * 66 * 66);
+        assert_eq!(solid(&voxel_grid)[&(30, 30, 30)], 1);
+        assert_eq!(
+            flooded.flood_fill((30, 30, 30), Connectivity::Corners, 3),
+            66 * 66 * 66
+        );
+        assert_eq!(solid(&flooded)[&(-1, -1, -1)], 3);
+
+        // until a hole through the walls reaches the outside
+        let mut voxel_grid = VoxelGrid::new();
+        hollow_box(&mut voxel_grid, (-3, -3, -3), (66, 66, 66), 1);
+        let mut hole = VoxelGrid::new();
+        hole.paint_cube((30, 30, 65), (30, 30, 66), 0);
+        voxel_grid.subtract(&hole);
+        assert_eq!(voxel_grid.fill_cavities(Connectivity::Faces), 0);
+        assert_eq!(
+            voxel_grid.flood_fill((30, 30, 30), Connectivity::Faces, 3),
+            0
+        );
+    }
+
+    #[test]
+    fn hollowed_shapes_are_filled_back() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (139, 139, 139), 1);
+        voxel_grid.hollow(3, &[]);
+        let walls = solid(&voxel_grid).len();
+
+        let filled = voxel_grid.fill_cavities(Connectivity::Faces);
+        assert_eq!(walls + filled, 140 * 140 * 140);
+    }
+
+    #[test]
+    fn flood_fill_paints_connected_voxels() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, 0, 0), (-1, 0, 0), 1);
+        voxel_grid.paint_cube((0, 1, 0), (5, 1, 0), 1);
+        voxel_grid.paint_cube((10, 0, 0), (12, 0, 0), 1);
+
+        let mut faces = voxel_grid.clone();
+        assert_eq!(faces.flood_fill((-70, 0, 0), Connectivity::Faces, 2), 70);
+        assert_eq!(solid(&faces)[&(0, 1, 0)], 1);
+
+        let mut corners = voxel_grid.clone();
+        assert_eq!(
+            corners.flood_fill((-70, 0, 0), Connectivity::Corners, 2),
+            76
+        );
+        let voxels = solid(&corners);
+        assert_eq!((voxels[&(5, 1, 0)], voxels[&(10, 0, 0)]), (2, 1));
+
+        // empty seeds outside of any cavity fill nothing
+        assert_eq!(corners.flood_fill((0, 0, 0), Connectivity::Faces, 2), 0);
+        assert_eq!(corners.flood_fill((0, 500, 0), Connectivity::Faces, 2), 0);
+    }
+}
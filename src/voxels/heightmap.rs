@@ -0,0 +1,290 @@
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use glam::*;
+
+use crate::voxels::{Material, Shape, VoxelGrid};
+
+// heights between 0 and 1 decoded from the pixels of an image, with rows from
+// the top of the image down
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    values: Vec<f32>,
+}
+
+// how a heightmap becomes voxels
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightmapImport {
+    // voxels in the columns of the highest pixels
+    pub max_height: u32,
+
+    // makes dark pixels high and light ones low
+    pub invert: bool,
+
+    // min and max pixels, inclusive, of the part of the image imported
+    pub crop: ((usize, usize), (usize, usize)),
+}
+
+impl Heightmap {
+    pub fn load(path: &Path) -> Result<Self, png::DecodingError> {
+        Self::decode(BufReader::new(std::fs::File::open(path)?))
+    }
+
+    // grayscale images give their values directly, colors go by their
+    // luminance; alpha is ignored
+    pub fn decode(reader: impl Read) -> Result<Self, png::DecodingError> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let (color_type, bit_depth) = reader.output_color_type();
+
+        // samples are 8 or 16 bits once expanded, 16 bits in big endian
+        let samples: Vec<f32> = match bit_depth {
+            png::BitDepth::Sixteen => buffer
+                .chunks_exact(2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 65535.0)
+                .collect(),
+            _ => buffer.iter().map(|byte| *byte as f32 / 255.0).collect(),
+        };
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let samples_per_line = info.line_size * samples.len() / buffer.len();
+        let values = (0..height)
+            .flat_map(|y| {
+                samples[y * samples_per_line..]
+                    .chunks_exact(color_type.samples())
+                    .take(width)
+            })
+            .map(|pixel| match color_type {
+                png::ColorType::Rgb | png::ColorType::Rgba => {
+                    0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+                }
+                _ => pixel[0],
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            values,
+        })
+    }
+
+    pub fn value(&self, x: usize, y: usize) -> f32 {
+        self.values[y * self.width + x]
+    }
+}
+
+impl VoxelGrid {
+    // each pixel of the cropped image becomes a column of voxels from z = 0
+    // up, centered on the origin, with the top of the image towards +y
+    pub fn from_heightmap(
+        heightmap: &Heightmap,
+        import: &HeightmapImport,
+        material: Material,
+    ) -> Self {
+        let mut voxel_grid = VoxelGrid::new();
+        if heightmap.width == 0 || heightmap.height == 0 {
+            return voxel_grid;
+        }
+
+        let (min, max) = import.crop;
+        let max = (
+            max.0.min(heightmap.width - 1),
+            max.1.min(heightmap.height - 1),
+        );
+        if min.0 > max.0 || min.1 > max.1 {
+            return voxel_grid;
+        }
+
+        let size = ivec2((max.0 - min.0 + 1) as i32, (max.1 - min.1 + 1) as i32);
+        let shape = HeightmapShape {
+            heightmap,
+            import,
+            crop_min: min,
+            offset: -size / 2,
+            size,
+        };
+        voxel_grid.paint_shape(&shape, material);
+        voxel_grid
+    }
+}
+
+struct HeightmapShape<'a> {
+    heightmap: &'a Heightmap,
+    import: &'a HeightmapImport,
+    crop_min: (usize, usize),
+
+    // world x and y of the column of the bottom left pixel
+    offset: IVec2,
+
+    // of the cropped image, in pixels
+    size: IVec2,
+}
+
+impl<'a> Shape for HeightmapShape<'a> {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let min = self.offset.extend(0);
+        let max = (self.offset + self.size - 1).extend(self.import.max_height as i32 - 1);
+        (min.as_vec3(), max.as_vec3())
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let column = point.truncate().as_ivec2() - self.offset;
+        let x = self.crop_min.0 + column.x as usize;
+        let y = self.crop_min.1 + (self.size.y - 1 - column.y) as usize;
+
+        let mut value = self.heightmap.value(x, y);
+        if self.import.invert {
+            value = 1.0 - value;
+        }
+        point.z < (value * self.import.max_height as f32).round()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(
+        (width, height): (u32, u32),
+        color_type: png::ColorType,
+        bit_depth: png::BitDepth,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    // heights of the columns of voxels, in rows from the top of the image
+    // down like the pixels, over the whole bounding box
+    fn column_heights(voxel_grid: &VoxelGrid) -> Vec<Vec<u32>> {
+        let (min, max) = voxel_grid.bounding_box().unwrap();
+        (min.1..=max.1)
+            .rev()
+            .map(|y| {
+                (min.0..=max.0)
+                    .map(|x| {
+                        let height = (0..=max.2)
+                            .take_while(|z| voxel_grid.read((x, y, *z)) == 1)
+                            .count();
+                        assert!((height as i32..=max.2).all(|z| voxel_grid.read((x, y, z)) == 0));
+                        height as u32
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn import(max_height: u32) -> HeightmapImport {
+        HeightmapImport {
+            max_height,
+            invert: false,
+            crop: ((0, 0), (usize::MAX, usize::MAX)),
+        }
+    }
+
+    #[test]
+    fn grayscale_pixels_give_column_heights() {
+        let bytes = encode(
+            (3, 2),
+            png::ColorType::Grayscale,
+            png::BitDepth::Eight,
+            &[255, 51, 204, 102, 153, 255],
+        );
+        let heightmap = Heightmap::decode(bytes.as_slice()).unwrap();
+        assert_eq!((heightmap.width, heightmap.height), (3, 2));
+        assert_eq!(heightmap.value(1, 0), 0.2);
+
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &import(10), 1);
+        assert_eq!(
+            column_heights(&voxel_grid),
+            vec![vec![10, 2, 8], vec![4, 6, 10]]
+        );
+        assert_eq!(voxel_grid.bounding_box(), Some(((-1, -1, 0), (1, 0, 9))));
+    }
+
+    #[test]
+    fn sixteen_bit_pixels_keep_their_precision() {
+        let samples: [u16; 3] = [65535, 32768, 655];
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect();
+        let bytes = encode(
+            (3, 1),
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            &data,
+        );
+        let heightmap = Heightmap::decode(bytes.as_slice()).unwrap();
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &import(1000), 1);
+        assert_eq!(column_heights(&voxel_grid), vec![vec![1000, 500, 10]]);
+    }
+
+    #[test]
+    fn colors_go_by_their_luminance() {
+        let bytes = encode(
+            (3, 1),
+            png::ColorType::Rgb,
+            png::BitDepth::Eight,
+            &[255, 0, 0, 0, 255, 0, 0, 0, 255],
+        );
+        let heightmap = Heightmap::decode(bytes.as_slice()).unwrap();
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &import(100), 1);
+        assert_eq!(column_heights(&voxel_grid), vec![vec![21, 72, 7]]);
+    }
+
+    #[test]
+    fn inverted_and_cropped_images() {
+        let bytes = encode(
+            (3, 2),
+            png::ColorType::Grayscale,
+            png::BitDepth::Eight,
+            &[0, 51, 204, 102, 153, 255],
+        );
+        let heightmap = Heightmap::decode(bytes.as_slice()).unwrap();
+
+        let inverted = HeightmapImport {
+            invert: true,
+            ..import(10)
+        };
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &inverted, 1);
+        assert_eq!(
+            column_heights(&voxel_grid),
+            vec![vec![10, 8, 2], vec![6, 4, 0]]
+        );
+
+        // crops going past the image stop at its edges
+        let cropped = HeightmapImport {
+            crop: ((1, 0), (7, 9)),
+            ..import(10)
+        };
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &cropped, 1);
+        assert_eq!(column_heights(&voxel_grid), vec![vec![2, 8], vec![6, 10]]);
+        assert_eq!(voxel_grid.bounding_box(), Some(((-1, -1, 0), (0, 0, 9))));
+
+        let cropped = HeightmapImport {
+            crop: ((1, 1), (1, 1)),
+            ..import(10)
+        };
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &cropped, 1);
+        assert_eq!(column_heights(&voxel_grid), vec![vec![6]]);
+
+        let outside = HeightmapImport {
+            crop: ((3, 0), (9, 9)),
+            ..import(10)
+        };
+        let voxel_grid = VoxelGrid::from_heightmap(&heightmap, &outside, 1);
+        assert_eq!(voxel_grid.bounding_box(), None);
+    }
+}
@@ -0,0 +1,143 @@
+use glam::*;
+
+use crate::voxels::{clamp_to_world, Kernel, VoxelGrid, DISTANCE_BAND};
+
+// cylinder drilled from a point on the outside of a wall, along a direction
+// going through the wall, until it opens into the hollow
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrainHole {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub radius: f32,
+}
+
+impl VoxelGrid {
+    // empties the inside of shapes, keeping walls of the given thickness;
+    // shapes thinner than their walls stay solid
+    pub fn hollow(&mut self, thickness: u32, drain_holes: &[DrainHole]) {
+        let mut hollow = self.clone();
+        hollow.erode(Kernel::Sphere(thickness.max(1) as f32), 1);
+        if hollow.bounding_box().is_none() {
+            return;
+        }
+
+        let mut holes = VoxelGrid::new();
+        for drain_hole in drain_holes {
+            let direction = drain_hole.direction.normalize_or_zero();
+            if direction == Vec3::ZERO || drain_hole.radius <= 0.0 {
+                continue;
+            }
+
+            // far enough to cross the wall at a grazing angle, and to find
+            // the hollow on the other side
+            let max_depth = (thickness as f32 + drain_hole.radius) * 4.0 + 2.0;
+            let depth = (0..=max_depth.ceil() as i32)
+                .map(|step| step as f32)
+                .find(|depth| {
+                    let point = (drain_hole.position + direction * *depth).round();
+                    hollow.read((point.x as i32, point.y as i32, point.z as i32)) == 1
+                })
+                .unwrap_or(thickness as f32 + 1.0);
+
+            // starting a bit outside makes sure the hole opens on the wall
+            holes.drill(
+                drain_hole.position - direction * drain_hole.radius,
+                drain_hole.position + direction * (depth + drain_hole.radius),
+                drain_hole.radius,
+            );
+        }
+
+        hollow.add(&holes);
+        self.subtract(&hollow);
+    }
+
+    // writes the signed distance to the cylinder between two points
+    fn drill(&mut self, start: Vec3, end: Vec3, radius: f32) {
+        self.begin_edit();
+
+        let axis = end - start;
+        let length = axis.length();
+        let axis = axis / length;
+
+        let margin = Vec3::splat(radius + DISTANCE_BAND);
+        let min = (start.min(end) - margin).floor();
+        let max = (start.max(end) + margin).ceil();
+        let min = clamp_to_world((min.x as i32, min.y as i32, min.z as i32));
+        let max = clamp_to_world((max.x as i32, max.y as i32, max.z as i32));
+
+        for z in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    let point = vec3(x as f32, y as f32, z as f32) - start;
+                    let along = point.dot(axis);
+                    let across = (point - axis * along).length();
+
+                    // distances to the side and to the caps, combined
+                    // exactly outside of both
+                    let side = across - radius;
+                    let cap = (along - length * 0.5).abs() - length * 0.5;
+                    let distance =
+                        vec2(side.max(0.0), cap.max(0.0)).length() + side.max(cap).min(0.0);
+
+                    if distance <= DISTANCE_BAND {
+                        self.union_distance((x, y, z), distance, 0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+    use crate::voxels::Connectivity;
+
+    #[test]
+    fn walls_keep_their_thickness() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-10, -10, -10), (9, 9, 9), 1);
+        voxel_grid.paint_cube((20000, 20000, 2000), (20019, 20019, 2019), 2);
+
+        voxel_grid.hollow(3, &[]);
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels.len(), 2 * (20 * 20 * 20 - 14 * 14 * 14));
+        for (min, material) in [((-10, -10, -10), 1), ((20000, 20000, 2000), 2)] {
+            let at = |x: i32, y: i32, z: i32| (min.0 + x, min.1 + y, min.2 + z);
+            assert_eq!(voxels[&at(2, 10, 10)], material);
+            assert!(!voxels.contains_key(&at(3, 10, 10)));
+            assert!(!voxels.contains_key(&at(16, 10, 10)));
+            assert_eq!(voxels[&at(17, 10, 10)], material);
+        }
+    }
+
+    #[test]
+    fn thin_shapes_stay_solid() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-30, -30, 0), (30, 30, 4), 1);
+        let before = solid(&voxel_grid);
+
+        voxel_grid.hollow(3, &[]);
+        assert_eq!(solid(&voxel_grid), before);
+    }
+
+    #[test]
+    fn drain_holes_open_into_the_hollow() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (19, 19, 19), 1);
+
+        let drain_hole = DrainHole {
+            position: vec3(10.0, 10.0, -0.5),
+            direction: Vec3::Z,
+            radius: 1.5,
+        };
+        voxel_grid.hollow(3, &[drain_hole]);
+        for z in 0..3 {
+            assert_eq!(voxel_grid.read((10, 10, z)), 0);
+        }
+        assert_eq!(voxel_grid.read((10, 10, 17)), 1);
+        assert_eq!(voxel_grid.read((10, 14, 1)), 1);
+        assert_eq!(voxel_grid.fill_cavities(Connectivity::Faces), 0);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use crate::voxels::bit_volume::{BitGrid, Run};
+use crate::voxels::{Connectivity, Coords, VoxelGrid};
+
+// solid voxels all connected to each other, and to no other solid voxel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Component {
+    pub size: usize,
+    pub min: Coords,
+    pub max: Coords,
+}
+
+impl VoxelGrid {
+    // components in the order of their first voxel, the one with the lowest
+    // z, then y, then x
+    pub fn components(&self, connectivity: Connectivity) -> Vec<Component> {
+        let (_, components) = self.label(connectivity);
+        components
+            .into_iter()
+            .map(|(component, _)| component)
+            .collect()
+    }
+
+    // removes the components with fewer voxels than the given size; returns
+    // how many voxels were removed
+    pub fn remove_islands(&mut self, min_size: usize, connectivity: Connectivity) -> usize {
+        self.remove_components(connectivity, |components| {
+            components
+                .iter()
+                .map(|component| component.size < min_size)
+                .collect()
+        })
+    }
+
+    // removes all components but the one with the most voxels, the first one
+    // on ties; returns how many voxels were removed
+    pub fn keep_largest_component(&mut self, connectivity: Connectivity) -> usize {
+        self.remove_components(connectivity, |components| {
+            let largest = (0..components.len())
+                .rev()
+                .max_by_key(|index| components[*index].size);
+            (0..components.len())
+                .map(|index| Some(index) != largest)
+                .collect()
+        })
+    }
+
+    // floods each component in turn over whole runs, chunk by chunk, then
+    // sorts them by their first voxel
+    fn label(&self, connectivity: Connectivity) -> (BitGrid, Vec<(Component, Vec<Run>)>) {
+        let grid = BitGrid::from_grid(self);
+
+        let mut components = vec![];
+        grid.regions(connectivity, |runs, _| {
+            let mut component = Component {
+                size: 0,
+                min: (i32::MAX, i32::MAX, i32::MAX),
+                max: (i32::MIN, i32::MIN, i32::MIN),
+            };
+            for (y, z, lo, hi) in runs {
+                component.size += (hi - lo + 1) as usize;
+                component.min = (
+                    component.min.0.min(*lo),
+                    component.min.1.min(*y),
+                    component.min.2.min(*z),
+                );
+                component.max = (
+                    component.max.0.max(*hi),
+                    component.max.1.max(*y),
+                    component.max.2.max(*z),
+                );
+            }
+
+            let first = runs.iter().map(|(y, z, lo, _)| (*z, *y, *lo)).min();
+            components.push((first, component, runs.to_vec()));
+        });
+        components.sort_by_key(|(first, _, _)| *first);
+
+        let components = components
+            .into_iter()
+            .map(|(_, component, runs)| (component, runs))
+            .collect();
+        (grid, components)
+    }
+
+    fn remove_components(
+        &mut self,
+        connectivity: Connectivity,
+        removed: impl Fn(&[Component]) -> Vec<bool>,
+    ) -> usize {
+        let (grid, components) = self.label(connectivity);
+
+        let summaries: Vec<Component> =
+            components.iter().map(|(component, _)| *component).collect();
+        let mut kept = BitGrid::default();
+        let mut removed_voxels = 0;
+        for ((component, runs), removed) in components.iter().zip(removed(&summaries)) {
+            if removed {
+                removed_voxels += component.size;
+            } else {
+                for run in runs {
+                    kept.set_run(*run);
+                }
+            }
+        }
+
+        if removed_voxels > 0 {
+            self.write_grid(&grid, &kept, &HashMap::new());
+        }
+        removed_voxels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    // small cubes far apart, with a bigger one in between, and a voxel
+    // touching the bigger one along an edge only
+    fn scene() -> VoxelGrid {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (3, 3, 3), 1);
+        voxel_grid.paint_cube((20000, 20000, 2000), (20003, 20003, 2003), 2);
+        voxel_grid.paint_cube((-70, 10, 10), (-61, 19, 19), 3);
+        voxel_grid.paint_cube((-60, 20, 15), (-60, 20, 15), 4);
+        voxel_grid
+    }
+
+    #[test]
+    fn components_follow_their_first_voxel() {
+        let voxel_grid = scene();
+
+        let components = voxel_grid.components(Connectivity::Faces);
+        assert_eq!(
+            components,
+            vec![
+                Component {
+                    size: 64,
+                    min: (0, 0, 0),
+                    max: (3, 3, 3),
+                },
+                Component {
+                    size: 1000,
+                    min: (-70, 10, 10),
+                    max: (-61, 19, 19),
+                },
+                Component {
+                    size: 1,
+                    min: (-60, 20, 15),
+                    max: (-60, 20, 15),
+                },
+                Component {
+                    size: 64,
+                    min: (20000, 20000, 2000),
+                    max: (20003, 20003, 2003),
+                },
+            ]
+        );
+
+        let components = voxel_grid.components(Connectivity::Corners);
+        assert_eq!(components.len(), 3);
+        assert_eq!(components[1].size, 1001);
+        assert_eq!(components[1].max, (-60, 20, 19));
+    }
+
+    #[test]
+    fn far_apart_islands_are_removed() {
+        let mut voxel_grid = scene();
+        assert_eq!(
+            voxel_grid.remove_islands(100, Connectivity::Faces),
+            64 + 64 + 1
+        );
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels.len(), 1000);
+        assert!(voxels.values().all(|material| *material == 3));
+
+        let mut voxel_grid = scene();
+        assert_eq!(
+            voxel_grid.remove_islands(100, Connectivity::Corners),
+            64 + 64
+        );
+        assert_eq!(solid(&voxel_grid).len(), 1001);
+    }
+
+    #[test]
+    fn the_largest_component_stays() {
+        let mut voxel_grid = scene();
+        assert_eq!(voxel_grid.keep_largest_component(Connectivity::Faces), 129);
+        assert_eq!(
+            voxel_grid.bounding_box(),
+            Some(((-70, 10, 10), (-61, 19, 19)))
+        );
+
+        // the first one wins ties
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((5, 0, 0), (5, 0, 0), 1);
+        voxel_grid.paint_cube((0, 0, 1), (0, 0, 1), 2);
+        voxel_grid.paint_cube((0, 0, -1), (0, 0, -1), 3);
+        assert_eq!(voxel_grid.keep_largest_component(Connectivity::Corners), 2);
+        assert_eq!(
+            solid(&voxel_grid).into_iter().collect::<Vec<_>>(),
+            vec![((0, 0, -1), 3)]
+        );
+    }
+}
@@ -0,0 +1,441 @@
+mod cubes;
+mod decimation;
+mod dual_contouring;
+mod export;
+mod marching_cubes;
+mod surface_nets;
+mod validation;
+
+pub use decimation::*;
+pub use export::*;
+pub use validation::*;
+
+use std::collections::BTreeSet;
+
+use glam::*;
+use rayon::prelude::*;
+
+use crate::voxels::chunk::Chunk;
+use crate::voxels::{chunk_origin, ChunkCoords, Coords, Material, Palette, VertexData, VoxelGrid};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshingMode {
+    SurfaceNets,
+    Blocky,
+    Greedy,
+    MarchingCubes,
+    DualContouring,
+}
+
+// how the meshers look for the surface: rows go through 64 voxels at once
+// with bit operations, and skip right to the voxels where it crosses; voxels
+// visit each of them on its own, which is slower but simpler, and serves as
+// a reference since both must give the exact same mesh
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scan {
+    Voxels,
+    Rows,
+}
+
+// the mesh is built from independent bricks, one per chunk of cells; a
+// brick holds the surface crossing the cells whose min corner lies in its
+// chunk, and only depends on the voxels of the chunks around it, so that
+// bricks can be cached and only remeshed when these change. bricks are
+// meshed in parallel, and always put together in the same order
+impl VoxelGrid {
+    pub fn generate_mesh(
+        &self,
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        self.generate_mesh_with(mode, palette, Scan::Rows)
+    }
+
+    pub fn generate_reference_mesh(
+        &self,
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        self.generate_mesh_with(mode, palette, Scan::Voxels)
+    }
+
+    pub fn generate_brick_mesh(
+        &self,
+        brick: ChunkCoords,
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        self.generate_brick_mesh_with(brick, mode, palette, Scan::Rows)
+    }
+
+    pub fn generate_brick_meshes(
+        &self,
+        bricks: &[ChunkCoords],
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> Vec<(Vec<VertexData>, Vec<u32>)> {
+        bricks
+            .par_iter()
+            .map(|brick| self.generate_brick_mesh(*brick, mode, palette))
+            .collect()
+    }
+
+    fn generate_mesh_with(
+        &self,
+        mode: MeshingMode,
+        palette: &Palette,
+        scan: Scan,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        let mut vertices: Vec<VertexData> = vec![];
+        let mut indices: Vec<u32> = vec![];
+
+        let bricks: Vec<ChunkCoords> = self.bricks().into_iter().collect();
+        let meshes: Vec<(Vec<VertexData>, Vec<u32>)> = bricks
+            .par_iter()
+            .map(|brick| self.generate_brick_mesh_with(*brick, mode, palette, scan))
+            .collect();
+
+        for (brick_vertices, brick_indices) in meshes {
+            let base = vertices.len() as u32;
+            vertices.extend(brick_vertices);
+            indices.extend(brick_indices.into_iter().map(|index| base + index));
+        }
+
+        (vertices, indices)
+    }
+
+    fn generate_brick_mesh_with(
+        &self,
+        brick: ChunkCoords,
+        mode: MeshingMode,
+        palette: &Palette,
+        scan: Scan,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        let range = match self.brick_range(brick) {
+            Some(range) => range,
+            None => return (vec![], vec![]),
+        };
+        let neighborhood = self.neighborhood(brick);
+
+        match mode {
+            MeshingMode::SurfaceNets => {
+                surface_nets::generate_mesh(&neighborhood, range, palette, scan)
+            }
+            MeshingMode::Blocky => cubes::generate_mesh(&neighborhood, range, palette, false, scan),
+            MeshingMode::Greedy => cubes::generate_mesh(&neighborhood, range, palette, true, scan),
+            MeshingMode::MarchingCubes => {
+                marching_cubes::generate_mesh(&neighborhood, range, palette, scan)
+            }
+            MeshingMode::DualContouring => {
+                dual_contouring::generate_mesh(&neighborhood, range, palette, scan)
+            }
+        }
+    }
+
+    // a cell touches the voxels of its own chunk and of the next chunk on
+    // each axis, so only the bricks next to a chunk can hold a surface
+    pub fn bricks(&self) -> BTreeSet<ChunkCoords> {
+        let mut bricks = BTreeSet::new();
+        for chunk_coords in self.chunks.keys() {
+            for i in 0..8 {
+                let offset = (i & 1, (i >> 1) & 1, i >> 2);
+                bricks.insert((
+                    chunk_coords.0 - offset.0,
+                    chunk_coords.1 - offset.1,
+                    chunk_coords.2 - offset.2,
+                ));
+            }
+        }
+
+        bricks
+    }
+
+    fn neighborhood(&self, brick: ChunkCoords) -> Neighborhood<'_> {
+        let mut chunks = [None; 27];
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            let offset = ((i % 3) as i32, ((i / 3) % 3) as i32, (i / 9) as i32);
+            *chunk = self
+                .chunks
+                .get(&(
+                    brick.0 + offset.0 - 1,
+                    brick.1 + offset.1 - 1,
+                    brick.2 + offset.2 - 1,
+                ))
+                .map(|chunk| chunk.as_ref());
+        }
+
+        let origin = chunk_origin((brick.0 - 1, brick.1 - 1, brick.2 - 1));
+
+        Neighborhood { chunks, origin }
+    }
+
+    // range of cells (max exclusive) of a brick that needs a visit, in the
+    // local coordinates of its neighborhood; when the chunk of the brick is
+    // empty on an axis, only its last layer of cells reaches into the next
+    // chunk. this also covers every edge between two voxels of the brick
+    // that can cross the surface
+    fn brick_range(&self, brick: ChunkCoords) -> Option<([usize; 3], [usize; 3])> {
+        let mut lo: Option<[usize; 3]> = None;
+        for i in 0..8 {
+            let offset = [i & 1, (i >> 1) & 1, i >> 2];
+            let chunk_coords = (
+                brick.0 + offset[0],
+                brick.1 + offset[1],
+                brick.2 + offset[2],
+            );
+            if !self.chunks.contains_key(&chunk_coords) {
+                continue;
+            }
+
+            let chunk_lo = offset.map(|o| if o == 0 { 64 } else { 127 });
+            lo = Some(match lo {
+                Some(lo) => [0, 1, 2].map(|axis| lo[axis].min(chunk_lo[axis])),
+                None => chunk_lo,
+            });
+        }
+
+        lo.map(|lo| (lo, [128; 3]))
+    }
+}
+
+// bricks whose mesh depends on the voxels of a chunk
+pub fn bricks_around(chunk_coords: ChunkCoords) -> impl Iterator<Item = ChunkCoords> {
+    (0..27).map(move |i| {
+        (
+            chunk_coords.0 + (i % 3) - 1,
+            chunk_coords.1 + (i / 3) % 3 - 1,
+            chunk_coords.2 + i / 9 - 1,
+        )
+    })
+}
+
+// the 3x3x3 chunks around a brick, readable as a single 192x192x192 block
+// of local coordinates; the chunk of the brick starts at 64 on each axis
+struct Neighborhood<'a> {
+    chunks: [Option<&'a Chunk>; 27],
+
+    // world coordinates of the local origin
+    origin: Coords,
+}
+
+impl<'a> Neighborhood<'a> {
+    fn chunk(&self, x: usize, y: usize, z: usize) -> Option<&'a Chunk> {
+        self.chunks[(x >> 6) + (y >> 6) * 3 + (z >> 6) * 9]
+    }
+
+    fn read(&self, x: usize, y: usize, z: usize) -> u64 {
+        match self.chunk(x, y, z) {
+            Some(chunk) => chunk.read((x & 63, y & 63, z & 63)),
+            None => 0,
+        }
+    }
+
+    // voxels of a row from local x 63 on, one per bit; this spans the cells
+    // of the brick and the ones just around it, with their corners
+    fn row(&self, y: usize, z: usize) -> u128 {
+        let chunk_row = |x| match self.chunk(x, y, z) {
+            Some(chunk) => chunk.row(y & 63, z & 63),
+            None => 0,
+        };
+
+        (chunk_row(0) >> 63) as u128 | (chunk_row(64) as u128) << 1 | (chunk_row(128) as u128) << 65
+    }
+
+    fn read_material(&self, x: usize, y: usize, z: usize) -> Material {
+        match self.chunk(x, y, z) {
+            Some(chunk) => chunk.read_material((x & 63, y & 63, z & 63)),
+            None => 0,
+        }
+    }
+
+    fn read_distance(&self, x: usize, y: usize, z: usize) -> f32 {
+        match self.chunk(x, y, z) {
+            Some(chunk) => chunk.read_distance((x & 63, y & 63, z & 63)),
+            None => 0.5,
+        }
+    }
+
+    fn read_known_distance(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        self.chunk(x, y, z)
+            .and_then(|chunk| chunk.read_known_distance((x & 63, y & 63, z & 63)))
+    }
+
+    // world position of a point given in local coordinates
+    fn world_position(&self, (x, y, z): (usize, usize, usize)) -> Vec3 {
+        Vec3::new(
+            (self.origin.0 + x as i32) as f32,
+            (self.origin.1 + y as i32) as f32,
+            (self.origin.2 + z as i32) as f32,
+        )
+    }
+
+    // where the surface crosses the edge between two corners of a cell, as a
+    // position relative to the min corner of the cell; the corners must differ.
+    // crossings stay off the corners, so that triangles never collapse onto a
+    // voxel whose distance is exactly zero
+    fn crossing(&self, cell: (usize, usize, usize), a: usize, b: usize) -> Vec3 {
+        let ((x, y, z), axis, t) = self.edge_crossing(cell, a, b);
+
+        let mut position = Vec3::new(
+            (x - cell.0) as f32,
+            (y - cell.1) as f32,
+            (z - cell.2) as f32,
+        );
+        position[axis] += t;
+        position
+    }
+
+    // the same crossing as its edge, given by the local coordinates of its
+    // min corner, its axis, and how far along the axis the surface is; this
+    // does not depend on the cell the edge is seen from
+    fn edge_crossing(
+        &self,
+        (x, y, z): (usize, usize, usize),
+        a: usize,
+        b: usize,
+    ) -> ((usize, usize, usize), usize, f32) {
+        let (a, b) = (CELL_CORNERS[a], CELL_CORNERS[b]);
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        let axis = if a.0 != b.0 {
+            0
+        } else if a.1 != b.1 {
+            1
+        } else {
+            2
+        };
+
+        let distance_a = self.read_distance(x + a.0, y + a.1, z + a.2);
+        let distance_b = self.read_distance(x + b.0, y + b.1, z + b.2);
+        let t = (distance_a / (distance_a - distance_b)).clamp(0.01, 0.99);
+
+        ((x + a.0, y + a.1, z + a.2), axis, t)
+    }
+}
+
+// bits of Neighborhood::row holding the local x from lo to hi (exclusive)
+fn row_mask(lo: usize, hi: usize) -> u128 {
+    ((1 << (hi - lo)) - 1) << (lo - 63)
+}
+
+// offsets from the min corner of a cell to its 8 corners
+const CELL_CORNERS: [(usize, usize, usize); 8] = [
+    (0, 0, 0),
+    (1, 0, 0),
+    (1, 1, 0),
+    (0, 1, 0),
+    (0, 0, 1),
+    (1, 0, 1),
+    (1, 1, 1),
+    (0, 1, 1),
+];
+
+// pairs of CELL_CORNERS forming the 12 edges of a cell
+const CELL_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (1, 2),
+    (2, 3),
+    (3, 0),
+    (4, 5),
+    (5, 6),
+    (6, 7),
+    (7, 4),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn assemble(meshes: Vec<(Vec<VertexData>, Vec<u32>)>) -> (Vec<u8>, Vec<u32>) {
+        let mut vertices: Vec<VertexData> = vec![];
+        let mut indices: Vec<u32> = vec![];
+        for (brick_vertices, brick_indices) in meshes {
+            let base = vertices.len() as u32;
+            vertices.extend(brick_vertices);
+            indices.extend(brick_indices.into_iter().map(|index| base + index));
+        }
+        (bytemuck::cast_slice(&vertices).to_vec(), indices)
+    }
+
+    #[test]
+    fn remeshed_bricks_match_a_full_mesh() {
+        let palette = Palette::default();
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, -10, -10), (70, 10, 10), 1);
+        voxel_grid.paint_sphere((64, 64, 0), 12.5, 2);
+
+        let mode = MeshingMode::SurfaceNets;
+        let mut cache: BTreeMap<ChunkCoords, (Vec<VertexData>, Vec<u32>)> = voxel_grid
+            .bricks()
+            .into_iter()
+            .map(|brick| (brick, voxel_grid.generate_brick_mesh(brick, mode, &palette)))
+            .collect();
+
+        // carves one chunk away entirely, and edits across chunk seams
+        let revision = voxel_grid.revision();
+        let mut hole = VoxelGrid::new();
+        hole.paint_sphere((64, 64, 0), 13.0, 0);
+        hole.paint_cube((-5, -20, -20), (5, 20, 20), 0);
+        voxel_grid.subtract(&hole);
+        voxel_grid.paint_sphere((-64, 0, 10), 6.0, 3);
+
+        let dirty: BTreeSet<ChunkCoords> = voxel_grid
+            .changed_chunks(revision)
+            .flat_map(bricks_around)
+            .collect();
+        for brick in dirty {
+            cache.insert(brick, voxel_grid.generate_brick_mesh(brick, mode, &palette));
+        }
+
+        let bricks = voxel_grid.bricks();
+        let remeshed = cache
+            .into_iter()
+            .filter(|(brick, _)| bricks.contains(brick))
+            .map(|(_, mesh)| mesh)
+            .collect();
+        let (vertices, indices) = voxel_grid.generate_mesh(mode, &palette);
+        let full = (bytemuck::cast_slice(&vertices).to_vec(), indices);
+        assert!(assemble(remeshed) == full);
+    }
+
+    #[test]
+    fn row_scans_match_the_reference() {
+        // shapes crossing chunk seams at negative coordinates, with scattered
+        // voxels of various materials
+        let palette = Palette::default();
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, -70, -8), (10, 10, 0), 2);
+        voxel_grid.paint_sphere((-64, -64, 4), 12.5, 3);
+        voxel_grid.paint_sphere((0, 0, 0), 9.0, 1);
+        for i in 0..300 {
+            let hash = (i * 7919 + 13) % 10007;
+            let coords = (hash % 97 - 80, (hash / 97) % 97 - 80, 1 + hash % 13);
+            voxel_grid.paint_cube(coords, coords, (i % 16) as u8);
+        }
+
+        for mode in [
+            MeshingMode::SurfaceNets,
+            MeshingMode::Blocky,
+            MeshingMode::Greedy,
+            MeshingMode::MarchingCubes,
+            MeshingMode::DualContouring,
+        ] {
+            let (reference_vertices, reference_indices) =
+                voxel_grid.generate_reference_mesh(mode, &palette);
+            let (vertices, indices) = voxel_grid.generate_mesh(mode, &palette);
+            assert!(!indices.is_empty(), "{:?}", mode);
+            assert!(
+                bytemuck::cast_slice::<_, u8>(&vertices)
+                    == bytemuck::cast_slice::<_, u8>(&reference_vertices)
+                    && indices == reference_indices,
+                "{:?}: the row scan does not match the reference mesh",
+                mode
+            );
+        }
+    }
+}
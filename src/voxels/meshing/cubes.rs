@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+
+use glam::*;
+
+use crate::voxels::meshing::{row_mask, Neighborhood, Scan};
+use crate::voxels::{Material, Palette, VertexData};
+
+// one quad per face between a solid and an empty voxel, with voxels as unit
+// cubes centered on integer coordinates; greedy meshing merges adjacent
+// faces of the same material and orientation into larger rectangles
+pub fn generate_mesh(
+    neighborhood: &Neighborhood,
+    (lo, hi): ([usize; 3], [usize; 3]),
+    palette: &Palette,
+    greedy: bool,
+    scan: Scan,
+) -> (Vec<VertexData>, Vec<u32>) {
+    // corners, normal and material of each rectangle
+    let mut faces: Vec<([Vec3; 4], Vec3, Material)> = vec![];
+
+    // slices are indexed from the chunk of the brick
+    let (lo, hi) = (lo.map(|lo| lo - 64), hi.map(|hi| hi - 64));
+    let origin = neighborhood.origin;
+    let origin = [origin.0 + 64, origin.1 + 64, origin.2 + 64];
+
+    // rows of the brick and the next one on each axis, indexed by z then y
+    let rows: Vec<Vec<u128>> = match scan {
+        Scan::Voxels => vec![],
+        Scan::Rows => (64..129)
+            .map(|z| (64..129).map(|y| neighborhood.row(y, z)).collect())
+            .collect(),
+    };
+
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        for d in lo[axis]..hi[axis] {
+            // faces between this slice and the next one, with the material
+            // of their solid voxel and whether they face along the axis
+            let mut slice = [[None; 64]; 64];
+            let mut visit = |i: usize, j: usize| {
+                let mut p = [0; 3];
+                p[axis] = d + 64;
+                p[u] = i + 64;
+                p[v] = j + 64;
+                let mut q = p;
+                q[axis] += 1;
+
+                let solid_p = neighborhood.read(p[0], p[1], p[2]);
+                let solid_q = neighborhood.read(q[0], q[1], q[2]);
+                if solid_p != solid_q {
+                    let (s, facing_positive) = if solid_p == 1 { (p, true) } else { (q, false) };
+                    let material: Material = neighborhood.read_material(s[0], s[1], s[2]);
+                    slice[j][i] = Some((material, facing_positive));
+                }
+            };
+
+            match (scan, axis) {
+                (Scan::Voxels, _) => {
+                    for j in lo[v]..hi[v] {
+                        for i in lo[u]..hi[u] {
+                            visit(i, j);
+                        }
+                    }
+                }
+
+                // bit 1 of a row is x = 0 in the brick; here the slice is
+                // across rows, and i, j are y, z
+                (Scan::Rows, 0) => {
+                    for (j, rows) in rows.iter().enumerate().take(hi[v]).skip(lo[v]) {
+                        for (i, row) in rows.iter().enumerate().take(hi[u]).skip(lo[u]) {
+                            if ((row >> (d + 1)) ^ (row >> (d + 2))) & 1 == 1 {
+                                visit(i, j);
+                            }
+                        }
+                    }
+                }
+
+                // i, j are z, x
+                (Scan::Rows, 1) => {
+                    for (i, rows) in rows.iter().enumerate().take(hi[u]).skip(lo[u]) {
+                        let mut faces = (rows[d] ^ rows[d + 1]) & row_mask(lo[v] + 64, hi[v] + 64);
+                        while faces != 0 {
+                            let bit = faces.trailing_zeros() as usize;
+                            faces &= faces - 1;
+                            visit(i, bit - 1);
+                        }
+                    }
+                }
+
+                // i, j are x, y
+                (Scan::Rows, _) => {
+                    let slices = rows[d].iter().zip(&rows[d + 1]);
+                    for (j, (row, next_row)) in slices.enumerate().take(hi[v]).skip(lo[v]) {
+                        let mut faces = (row ^ next_row) & row_mask(lo[u] + 64, hi[u] + 64);
+                        while faces != 0 {
+                            let bit = faces.trailing_zeros() as usize;
+                            faces &= faces - 1;
+                            visit(bit - 1, j);
+                        }
+                    }
+                }
+            }
+
+            for j in lo[v]..hi[v] {
+                let mut i = lo[u];
+                while i < hi[u] {
+                    let face = match slice[j][i] {
+                        Some(face) => face,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+
+                    let mut width = 1;
+                    let mut height = 1;
+                    if greedy {
+                        while i + width < hi[u] && slice[j][i + width] == Some(face) {
+                            width += 1;
+                        }
+                        while j + height < hi[v]
+                            && slice[j + height][i..i + width]
+                                .iter()
+                                .all(|other| *other == Some(face))
+                        {
+                            height += 1;
+                        }
+                    }
+
+                    for row in &mut slice[j..j + height] {
+                        row[i..i + width].fill(None);
+                    }
+
+                    let (material, facing_positive) = face;
+                    let plane = (origin[axis] + d as i32) as f32 + 0.5;
+                    let min_u = (origin[u] + i as i32) as f32 - 0.5;
+                    let min_v = (origin[v] + j as i32) as f32 - 0.5;
+                    let (corners, normal) = face_corners(
+                        axis,
+                        plane,
+                        (min_u, min_u + width as f32),
+                        (min_v, min_v + height as f32),
+                        facing_positive,
+                    );
+                    faces.push((corners, normal, material));
+
+                    i += width;
+                }
+            }
+        }
+    }
+
+    // a merged rectangle can border several smaller ones, whose corners then
+    // lie in the middle of its edges and leave cracks; such corners are
+    // inserted into its outline, which is filled as a fan around its center.
+    // the rectangles of other bricks are not known here, so edges along the
+    // border of the brick get a corner on every voxel instead, which the
+    // brick on the other side does as well.
+    // all corners sit on half voxels, so doubling them gives exact keys
+    let key = |position: Vec3| (position * 2.0).round().as_ivec3();
+    let on_border = |a: Vec3, b: Vec3| {
+        let (a, b) = (key(a), key(b));
+        (0..3).any(|axis| a[axis] == b[axis] && (a[axis] + 1).rem_euclid(128) == 0)
+    };
+    let all_corners: HashSet<IVec3> = faces
+        .iter()
+        .flat_map(|(corners, _, _)| corners.map(key))
+        .collect();
+
+    let mut vertices: Vec<VertexData> = vec![];
+    let mut indices: Vec<u32> = vec![];
+
+    for (corners, normal, material) in faces {
+        let mut outline = vec![];
+        for k in 0..4 {
+            let (a, b) = (corners[k], corners[(k + 1) % 4]);
+            // stepping a voxel at a time keeps the positions exact, so that
+            // faces sharing a corner agree on it
+            let steps = (b - a).abs().max_element() as usize;
+            let direction = (b - a) / steps as f32;
+            outline.push(a);
+            outline.extend(
+                (1..steps)
+                    .map(|step| a + direction * step as f32)
+                    .filter(|position| on_border(a, b) || all_corners.contains(&key(*position))),
+            );
+        }
+
+        let vertex = |position| VertexData {
+            position,
+            normal,
+            color: palette.color(material),
+        };
+
+        let base = vertices.len() as u32;
+        if outline.len() == 4 {
+            vertices.extend(outline.into_iter().map(vertex));
+            indices.extend([base, base + 1, base + 2, base + 2, base + 3, base]);
+        } else {
+            let count = outline.len() as u32;
+            vertices.push(vertex((corners[0] + corners[2]) * 0.5));
+            vertices.extend(outline.into_iter().map(vertex));
+            for k in 0..count {
+                indices.extend([base, base + 1 + k, base + 1 + (k + 1) % count]);
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+// corners and normal of an axis-aligned rectangle on the plane at the given
+// position along an axis
+fn face_corners(
+    axis: usize,
+    plane: f32,
+    (u0, u1): (f32, f32),
+    (v0, v1): (f32, f32),
+    facing_positive: bool,
+) -> ([Vec3; 4], Vec3) {
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let corner = |a: f32, b: f32| {
+        let mut position = Vec3::ZERO;
+        position[axis] = plane;
+        position[u] = a;
+        position[v] = b;
+        position
+    };
+
+    // counter-clockwise when seen from the side the face points to
+    let mut corners = [
+        corner(u0, v0),
+        corner(u1, v0),
+        corner(u1, v1),
+        corner(u0, v1),
+    ];
+    let mut normal = Vec3::ZERO;
+    normal[axis] = 1.0;
+    if !facing_positive {
+        corners.reverse();
+        normal = -normal;
+    }
+
+    (corners, normal)
+}
@@ -0,0 +1,737 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Add;
+
+use glam::*;
+
+use crate::voxels::VertexData;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecimationTarget {
+    // stops once the mesh has no more triangles than this
+    Triangles(usize),
+
+    // stops before moving the surface further than this from where it was,
+    // as estimated by the quadric error
+    Error(f32),
+}
+
+// boundary vertices further than this from the line between their two
+// neighbors along the boundary, relative to its length, are corners of the
+// outline
+const COLLINEAR_TOLERANCE: f64 = 1e-6;
+
+// triangles meeting at more than 60 degrees form a crease
+const CREASE: f64 = 0.5;
+
+// simplifies a triangle list by collapsing edges, the ones moving the surface
+// the least first, following the quadric error metric of Garland and
+// Heckbert. vertices are welded by position first; collapses that would
+// tear or fold the surface are skipped, boundaries only lose the vertices
+// along their straight runs, and the vertices around the non-manifold parts
+// of the input stay as they are. normals are smoothed over the triangles
+// around each vertex, up to creases
+pub fn decimate_mesh(
+    vertices: &[VertexData],
+    indices: &[u32],
+    target: DecimationTarget,
+) -> (Vec<VertexData>, Vec<u32>) {
+    let mut mesh = Mesh::new(vertices, indices);
+    mesh.simplify(target);
+    mesh.output()
+}
+
+// sum of squared distances to a set of planes, as the upper half of a
+// symmetric 4x4 matrix
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: DVec3, point: DVec3) -> Self {
+        let [a, b, c] = normal.to_array();
+        let d = -normal.dot(point);
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn error(&self, point: DVec3) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = point.to_array();
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+
+    // point of least error, unless the planes leave it undetermined
+    fn minimum(&self) -> Option<DVec3> {
+        let q = &self.0;
+        let matrix = DMat3::from_cols(
+            dvec3(q[0], q[1], q[2]),
+            dvec3(q[1], q[4], q[5]),
+            dvec3(q[2], q[5], q[7]),
+        );
+        if matrix.determinant().abs() < 1e-9 {
+            return None;
+        }
+        Some(matrix.inverse() * -dvec3(q[3], q[6], q[8]))
+    }
+}
+
+impl Add for Quadric {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut sum = self;
+        for (value, other) in sum.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+        sum
+    }
+}
+
+// merges the removed vertex into the kept one, which moves to the position
+struct Collapse {
+    cost: f64,
+    keep: usize,
+    remove: usize,
+    position: DVec3,
+}
+
+// collapse of an edge waiting in the heap, kept small as the heap holds
+// many of them; versions are the ones of its two vertices when queued
+struct Queued {
+    cost: f64,
+    edge: (u32, u32),
+    versions: (u32, u32),
+}
+
+// the cheapest collapse comes first out of the heap
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+struct Mesh {
+    positions: Vec<DVec3>,
+    colors: Vec<Vec3>,
+    quadrics: Vec<Quadric>,
+
+    // triangles around each vertex, removed ones included until cleaned up
+    faces: Vec<Vec<usize>>,
+
+    triangles: Vec<[usize; 3]>,
+    removed: Vec<bool>,
+    live_triangles: usize,
+
+    // vertices merged into others, or around non-manifold parts
+    merged: Vec<bool>,
+    locked: Vec<bool>,
+
+    // vertices on an edge of a single triangle; collapses keep these edges
+    // on the boundary, so a vertex only joins it by merging with another
+    // vertex on it
+    boundary: Vec<bool>,
+
+    // bumped on each change to a vertex, so that queued collapses can tell
+    // when they are stale
+    versions: Vec<u32>,
+}
+
+impl Mesh {
+    fn new(vertices: &[VertexData], indices: &[u32]) -> Self {
+        let mut positions = vec![];
+        let mut colors = vec![];
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let ids: Vec<usize> = vertices
+            .iter()
+            .map(|vertex| {
+                let key = vertex.position.to_array().map(|x| (x + 0.0).to_bits());
+                *welded.entry(key).or_insert_with(|| {
+                    positions.push(vertex.position.as_dvec3());
+                    colors.push(vertex.color);
+                    positions.len() - 1
+                })
+            })
+            .collect();
+
+        let triangles: Vec<[usize; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]].map(|i| ids[i as usize]))
+            .filter(|[a, b, c]| a != b && b != c && c != a)
+            .collect();
+
+        let mut faces = vec![vec![]; positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edges: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+        for (face, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|i| positions[i]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for i in 0..3 {
+                let (from, to) = (triangle[i], triangle[(i + 1) % 3]);
+                faces[from].push(face);
+                quadrics[from] = quadrics[from] + Quadric::plane(normal, a);
+
+                let uses = edges.entry((from.min(to), from.max(to))).or_default();
+                if from < to {
+                    uses.0 += 1;
+                } else {
+                    uses.1 += 1;
+                }
+            }
+        }
+
+        // edges shared by more than two triangles, or by two triangles
+        // disagreeing on their orientation, and vertices joining separate
+        // fans of triangles, are left alone
+        let mut locked = vec![false; positions.len()];
+        let mut boundary = vec![false; positions.len()];
+        for ((a, b), (forward, backward)) in edges {
+            if forward + backward == 1 {
+                boundary[a] = true;
+                boundary[b] = true;
+            }
+            if forward + backward > 2 || (forward + backward == 2 && forward != backward) {
+                locked[a] = true;
+                locked[b] = true;
+            }
+        }
+        for (vertex, vertex_faces) in faces.iter().enumerate() {
+            if fans(vertex, vertex_faces, &triangles) > 1 {
+                locked[vertex] = true;
+            }
+        }
+
+        Self {
+            colors,
+            quadrics,
+            faces,
+            live_triangles: triangles.len(),
+            removed: vec![false; triangles.len()],
+            triangles,
+            merged: vec![false; positions.len()],
+            locked,
+            boundary,
+            versions: vec![0; positions.len()],
+            positions,
+        }
+    }
+
+    fn simplify(&mut self, target: DecimationTarget) {
+        let mut edges: Vec<(usize, usize)> = self
+            .triangles
+            .iter()
+            .flat_map(|triangle| {
+                (0..3).map(|i| {
+                    let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                    (a.min(b), a.max(b))
+                })
+            })
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        let mut heap: BinaryHeap<Queued> = edges
+            .into_iter()
+            .filter_map(|(a, b)| self.queued(a, b))
+            .collect();
+
+        while let Some(queued) = heap.pop() {
+            if let DecimationTarget::Triangles(triangles) = target {
+                if self.live_triangles <= triangles {
+                    break;
+                }
+            }
+
+            let (a, b) = (queued.edge.0 as usize, queued.edge.1 as usize);
+            if self.merged[a]
+                || self.merged[b]
+                || queued.versions != (self.versions[a], self.versions[b])
+            {
+                continue;
+            }
+
+            // the neighborhood may have changed since the collapse was queued
+            let collapse = match self.candidate(a, b) {
+                Some(current) if current.cost > queued.cost + 1e-12 => {
+                    heap.extend(self.queued(a, b));
+                    continue;
+                }
+                Some(current) => current,
+                None => continue,
+            };
+
+            if let DecimationTarget::Error(tolerance) = target {
+                if collapse.cost > tolerance as f64 * tolerance as f64 {
+                    break;
+                }
+            }
+
+            if self.collapse(&collapse) {
+                let keep = collapse.keep;
+                for neighbor in self.neighbors(keep) {
+                    heap.extend(self.queued(keep, neighbor));
+                }
+            }
+        }
+    }
+
+    // where the edge would best collapse to; a vertex on the boundary stays
+    // where it is, and two of them only merge along the boundary, when one
+    // of them lies on a straight run of it
+    fn candidate(&self, a: usize, b: usize) -> Option<Collapse> {
+        if self.locked[a] || self.locked[b] {
+            return None;
+        }
+
+        let (position_a, position_b) = (self.positions[a], self.positions[b]);
+        let quadric = self.quadrics[a] + self.quadrics[b];
+        let (keep, remove, position) = match (self.boundary[a], self.boundary[b]) {
+            (false, false) => {
+                // far away minimums come from nearly parallel planes
+                let midpoint = (position_a + position_b) * 0.5;
+                let position = quadric
+                    .minimum()
+                    .filter(|minimum| minimum.distance(midpoint) <= position_a.distance(position_b))
+                    .into_iter()
+                    .chain([position_a, position_b, midpoint])
+                    .min_by(|x, y| quadric.error(*x).total_cmp(&quadric.error(*y)))
+                    .unwrap();
+
+                // the closest vertex keeps its color
+                if position.distance_squared(position_a) <= position.distance_squared(position_b) {
+                    (a, b, position)
+                } else {
+                    (b, a, position)
+                }
+            }
+            (true, false) => (a, b, position_a),
+            (false, true) => (b, a, position_b),
+            (true, true) => {
+                if self.shared_faces(a, b).count() != 1 {
+                    return None;
+                }
+                if self.is_straight(b) {
+                    (a, b, position_a)
+                } else if self.is_straight(a) {
+                    (b, a, position_b)
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        Some(Collapse {
+            cost: quadric.error(position).max(0.0),
+            keep,
+            remove,
+            position,
+        })
+    }
+
+    fn queued(&self, a: usize, b: usize) -> Option<Queued> {
+        self.candidate(a, b).map(|collapse| Queued {
+            cost: collapse.cost,
+            edge: (a as u32, b as u32),
+            versions: (self.versions[a], self.versions[b]),
+        })
+    }
+
+    // merges the vertices unless this would make the surface non-manifold,
+    // or turn any of its triangles over
+    fn collapse(&mut self, collapse: &Collapse) -> bool {
+        let (keep, remove, position) = (collapse.keep, collapse.remove, collapse.position);
+        self.clean(keep);
+        self.clean(remove);
+
+        let shared: Vec<usize> = self.shared_faces(keep, remove).collect();
+        if shared.is_empty() {
+            return false;
+        }
+
+        // the two vertices may only have in common the vertices of the
+        // triangles on their edge
+        let mut opposite: Vec<usize> = shared
+            .iter()
+            .flat_map(|face| self.triangles[*face])
+            .filter(|vertex| *vertex != keep && *vertex != remove)
+            .collect();
+        let keep_neighbors = self.neighbors(keep);
+        let mut common: Vec<usize> = self
+            .neighbors(remove)
+            .into_iter()
+            .filter(|vertex| keep_neighbors.contains(vertex))
+            .collect();
+        opposite.sort_unstable();
+        opposite.dedup();
+        common.sort_unstable();
+        if common != opposite {
+            return false;
+        }
+
+        let moved = |vertex: usize| {
+            if vertex == keep || vertex == remove {
+                position
+            } else {
+                self.positions[vertex]
+            }
+        };
+        for vertex in [keep, remove] {
+            for face in &self.faces[vertex] {
+                if shared.contains(face) {
+                    continue;
+                }
+                let triangle = self.triangles[*face];
+                let [a, b, c] = triangle.map(|i| self.positions[i]);
+                let [d, e, f] = triangle.map(moved);
+                let before = (b - a).cross(c - a);
+                let after = (e - d).cross(f - d);
+                if after.length_squared() <= 1e-12 * before.length_squared()
+                    || before.dot(after) <= 0.0
+                {
+                    return false;
+                }
+
+                // a triangle of both vertices already joining the same two
+                // others would be repeated
+                if vertex == remove {
+                    let others: Vec<usize> = triangle
+                        .into_iter()
+                        .filter(|other| *other != remove)
+                        .collect();
+                    let repeated = self.faces[keep].iter().any(|face| {
+                        !shared.contains(face)
+                            && others
+                                .iter()
+                                .all(|other| self.triangles[*face].contains(other))
+                    });
+                    if repeated {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        for face in &shared {
+            self.removed[*face] = true;
+            self.live_triangles -= 1;
+        }
+        let faces = std::mem::take(&mut self.faces[remove]);
+        for face in faces {
+            if shared.contains(&face) {
+                continue;
+            }
+            for corner in &mut self.triangles[face] {
+                if *corner == remove {
+                    *corner = keep;
+                }
+            }
+            self.faces[keep].push(face);
+        }
+        self.clean(keep);
+
+        self.positions[keep] = position;
+        self.quadrics[keep] = self.quadrics[keep] + self.quadrics[remove];
+        self.merged[remove] = true;
+        self.boundary[keep] |= self.boundary[remove];
+        self.versions[keep] += 1;
+        self.versions[remove] += 1;
+        true
+    }
+
+    fn clean(&mut self, vertex: usize) {
+        let removed = &self.removed;
+        self.faces[vertex].retain(|face| !removed[*face]);
+    }
+
+    fn live_faces(&self, vertex: usize) -> impl Iterator<Item = usize> + '_ {
+        self.faces[vertex]
+            .iter()
+            .copied()
+            .filter(|face| !self.removed[*face])
+    }
+
+    fn shared_faces(&self, a: usize, b: usize) -> impl Iterator<Item = usize> + '_ {
+        self.live_faces(a)
+            .filter(move |face| self.triangles[*face].contains(&b))
+    }
+
+    // other vertices of the triangles around the vertex, with the number of
+    // these triangles each of them is in
+    fn neighbor_uses(&self, vertex: usize) -> Vec<(usize, usize)> {
+        let mut uses: Vec<(usize, usize)> = vec![];
+        for face in self.live_faces(vertex) {
+            for other in self.triangles[face] {
+                if other == vertex {
+                    continue;
+                }
+                match uses.iter_mut().find(|(neighbor, _)| *neighbor == other) {
+                    Some((_, count)) => *count += 1,
+                    None => uses.push((other, 1)),
+                }
+            }
+        }
+        uses
+    }
+
+    fn neighbors(&self, vertex: usize) -> Vec<usize> {
+        self.neighbor_uses(vertex)
+            .into_iter()
+            .map(|(neighbor, _)| neighbor)
+            .collect()
+    }
+
+    // whether the vertex lies on the line between its two neighbors along
+    // the boundary
+    fn is_straight(&self, vertex: usize) -> bool {
+        let ends: Vec<usize> = self
+            .neighbor_uses(vertex)
+            .into_iter()
+            .filter(|(_, uses)| *uses == 1)
+            .map(|(neighbor, _)| neighbor)
+            .collect();
+        let [a, b] = match ends[..] {
+            [a, b] => [a, b].map(|end| self.positions[end]),
+            _ => return false,
+        };
+
+        let point = self.positions[vertex];
+        let line = b - a;
+        let along = (point - a).dot(line);
+        along > 0.0
+            && along < line.length_squared()
+            && (point - a).cross(line).length() <= COLLINEAR_TOLERANCE * line.length_squared()
+    }
+
+    // vertices are repeated along creases, each side with its own normal
+    fn output(&self) -> (Vec<VertexData>, Vec<u32>) {
+        let normals: Vec<DVec3> = self
+            .triangles
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|i| self.positions[i]);
+                (b - a).cross(c - a)
+            })
+            .collect();
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut remap: HashMap<(usize, [u32; 3]), u32> = HashMap::new();
+        for (face, triangle) in self.triangles.iter().enumerate() {
+            if self.removed[face] {
+                continue;
+            }
+
+            let direction = normals[face].normalize_or_zero();
+            for vertex in *triangle {
+                let normal = self
+                    .live_faces(vertex)
+                    .filter(|other| normals[*other].normalize_or_zero().dot(direction) > CREASE)
+                    .map(|other| normals[other])
+                    .sum::<DVec3>()
+                    .normalize_or_zero()
+                    .as_vec3();
+                let index = *remap
+                    .entry((vertex, normal.to_array().map(f32::to_bits)))
+                    .or_insert_with(|| {
+                        vertices.push(VertexData {
+                            position: self.positions[vertex].as_vec3(),
+                            normal,
+                            color: self.colors[vertex],
+                        });
+                        vertices.len() as u32 - 1
+                    });
+                indices.push(index);
+            }
+        }
+
+        (vertices, indices)
+    }
+}
+
+// groups of triangles around a vertex joined through their edges
+fn fans(vertex: usize, faces: &[usize], triangles: &[[usize; 3]]) -> usize {
+    let mut groups: Vec<usize> = (0..faces.len()).collect();
+    let mut first_use: HashMap<usize, usize> = HashMap::new();
+    for (i, face) in faces.iter().enumerate() {
+        for other in triangles[*face] {
+            if other == vertex {
+                continue;
+            }
+            match first_use.get(&other) {
+                Some(j) => {
+                    let (a, b) = (root(&mut groups, i), root(&mut groups, *j));
+                    groups[a] = b;
+                }
+                None => {
+                    first_use.insert(other, i);
+                }
+            }
+        }
+    }
+
+    (0..faces.len())
+        .filter(|i| root(&mut groups, *i) == *i)
+        .count()
+}
+
+fn root(groups: &mut [usize], mut i: usize) -> usize {
+    while groups[i] != i {
+        groups[i] = groups[groups[i]];
+        i = groups[i];
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{validate_mesh, MeshReport, MeshingMode, Palette, VoxelGrid};
+
+    fn sphere() -> (Vec<VertexData>, Vec<u32>) {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_sphere((-20, 5, 40), 15.3, 1);
+        voxel_grid.generate_mesh(MeshingMode::SurfaceNets, &Palette::default())
+    }
+
+    // the lower half of the sphere, open along its rim
+    fn bowl() -> (Vec<VertexData>, Vec<u32>) {
+        let (vertices, indices) = sphere();
+        let indices = indices
+            .chunks_exact(3)
+            .filter(|triangle| {
+                triangle
+                    .iter()
+                    .all(|index| vertices[*index as usize].position.z <= 40.0)
+            })
+            .flatten()
+            .copied()
+            .collect();
+        (vertices, indices)
+    }
+
+    fn assert_not_worse(report: MeshReport, before: MeshReport) {
+        assert!(
+            report.boundary_edges <= before.boundary_edges,
+            "{:?}",
+            report
+        );
+        assert!(
+            report.non_manifold_edges <= before.non_manifold_edges,
+            "{:?}",
+            report
+        );
+        assert!(
+            report.degenerate_triangles <= before.degenerate_triangles,
+            "{:?}",
+            report
+        );
+    }
+
+    // edges used by a single triangle, between welded positions
+    fn boundary(vertices: &[VertexData], indices: &[u32]) -> Vec<(Vec3, Vec3)> {
+        let key = |index: u32| {
+            vertices[index as usize]
+                .position
+                .to_array()
+                .map(f32::to_bits)
+        };
+        let mut edges: HashMap<([u32; 3], [u32; 3]), usize> = HashMap::new();
+        for triangle in indices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (key(triangle[i]), key(triangle[(i + 1) % 3]));
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        edges
+            .into_iter()
+            .filter(|(_, count)| *count == 1)
+            .map(|((a, b), _)| {
+                (
+                    Vec3::from_array(a.map(f32::from_bits)),
+                    Vec3::from_array(b.map(f32::from_bits)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn closed_meshes_stay_closed() {
+        let (vertices, indices) = sphere();
+        let before = validate_mesh(&vertices, &indices);
+        assert!(before.is_watertight());
+
+        for target in [
+            DecimationTarget::Triangles(before.triangles / 4),
+            DecimationTarget::Error(0.5),
+        ] {
+            let (decimated_vertices, decimated) = decimate_mesh(&vertices, &indices, target);
+            let report = validate_mesh(&decimated_vertices, &decimated);
+            assert!(report.triangles < before.triangles / 2, "{:?}", report);
+            if let DecimationTarget::Triangles(triangles) = target {
+                assert!(report.triangles <= triangles, "{:?}", report);
+            }
+            assert_not_worse(report, before);
+        }
+    }
+
+    #[test]
+    fn boundaries_keep_their_outline() {
+        let (vertices, indices) = bowl();
+        let before = validate_mesh(&vertices, &indices);
+        assert!(before.boundary_edges > 0);
+        let outline = boundary(&vertices, &indices);
+
+        let (decimated_vertices, decimated) =
+            decimate_mesh(&vertices, &indices, DecimationTarget::Triangles(0));
+        let report = validate_mesh(&decimated_vertices, &decimated);
+        assert!(report.triangles < before.triangles / 2, "{:?}", report);
+        assert_not_worse(report, before);
+
+        // boundary vertices stay where they were, and the only ones to go are
+        // along straight runs, so the outline keeps its length
+        let decimated_outline = boundary(&decimated_vertices, &decimated);
+        for (a, b) in &decimated_outline {
+            for point in [a, b] {
+                assert!(
+                    outline.iter().any(|(c, d)| c == point || d == point),
+                    "{}",
+                    point
+                );
+            }
+        }
+        let length =
+            |outline: &[(Vec3, Vec3)]| -> f32 { outline.iter().map(|(a, b)| a.distance(*b)).sum() };
+        assert!((length(&decimated_outline) - length(&outline)).abs() < 1e-3);
+    }
+}
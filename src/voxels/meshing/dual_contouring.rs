@@ -0,0 +1,223 @@
+use glam::*;
+
+use crate::voxels::meshing::{surface_nets, Neighborhood, Scan, CELL_CORNERS, CELL_EDGES};
+use crate::voxels::{Palette, VertexData};
+
+// directions the crossings of a cell barely constrain, with less than a tenth
+// of the weight of the strongest one, are left to the mass point
+const SINGULAR_RATIO: f64 = 0.1;
+
+// the same quads as surface nets, with each vertex placed where the planes
+// through the crossings of its cell meet best, following the normals of the
+// distance field; this keeps the edges and corners of the surface sharp
+pub fn generate_mesh(
+    neighborhood: &Neighborhood,
+    range: ([usize; 3], [usize; 3]),
+    palette: &Palette,
+    scan: Scan,
+) -> (Vec<VertexData>, Vec<u32>) {
+    surface_nets::generate_dual_mesh(neighborhood, range, scan, |cell| {
+        cell_vertex(neighborhood, cell, palette)
+    })
+}
+
+// minimizes the squared distances to the planes of the crossings (the QEF),
+// relative to the mass point of the crossings so that unconstrained
+// directions stay there; the vertex is kept inside its cell
+fn cell_vertex(
+    neighborhood: &Neighborhood,
+    (x, y, z): (usize, usize, usize),
+    palette: &Palette,
+) -> VertexData {
+    let mut vertex = surface_nets::cell_vertex(neighborhood, (x, y, z), palette);
+    let origin = neighborhood.world_position((x, y, z));
+    let mass_point = (vertex.position - origin).as_dvec3();
+
+    let mut normal_matrix = DMat3::ZERO;
+    let mut target = DVec3::ZERO;
+    let mut normal_sum = DVec3::ZERO;
+    for (a, b) in CELL_EDGES {
+        let (corner_a, corner_b) = (CELL_CORNERS[a], CELL_CORNERS[b]);
+        let corner_a = (x + corner_a.0, y + corner_a.1, z + corner_a.2);
+        let corner_b = (x + corner_b.0, y + corner_b.1, z + corner_b.2);
+        let solid_a = neighborhood.read(corner_a.0, corner_a.1, corner_a.2);
+        let solid_b = neighborhood.read(corner_b.0, corner_b.1, corner_b.2);
+        if solid_a == solid_b {
+            continue;
+        }
+
+        let point = neighborhood.crossing((x, y, z), a, b).as_dvec3();
+        let normal = if solid_a == 1 {
+            crossing_normal(neighborhood, corner_a, corner_b)
+        } else {
+            crossing_normal(neighborhood, corner_b, corner_a)
+        };
+
+        normal_matrix += outer_product(normal, normal);
+        target += normal * normal.dot(point - mass_point);
+        normal_sum += normal;
+    }
+
+    let position = mass_point + solve(normal_matrix, target);
+    vertex.position = origin
+        + position
+            .clamp(DVec3::splat(0.01), DVec3::splat(0.99))
+            .as_vec3();
+    vertex.normal = normal_sum.as_vec3();
+    vertex
+}
+
+// normal of the surface where it crosses the edge between a solid voxel and
+// an empty one, from what the gradients of the distance field at both ends
+// agree on: each axis takes the smaller of their components, or none when
+// they disagree. along the sharp edge of a box, the voxel inside the edge is
+// next to two faces and its gradient points between them, while the one
+// outside only sees the face it is against; inside a groove it goes the
+// other way around, and where both meet, each end sees a different pair of
+// faces
+fn crossing_normal(
+    neighborhood: &Neighborhood,
+    solid: (usize, usize, usize),
+    empty: (usize, usize, usize),
+) -> DVec3 {
+    let direction = dvec3(
+        empty.0 as f64 - solid.0 as f64,
+        empty.1 as f64 - solid.1 as f64,
+        empty.2 as f64 - solid.2 as f64,
+    );
+
+    let [a, b] = [solid, empty].map(|voxel| gradient(neighborhood, voxel).normalize_or_zero());
+    let normal = DVec3::from_array([0, 1, 2].map(|axis| {
+        if a[axis] * b[axis] <= 0.0 {
+            0.0
+        } else if a[axis].abs() < b[axis].abs() {
+            a[axis]
+        } else {
+            b[axis]
+        }
+    }));
+
+    if normal.dot(direction) > 0.0 {
+        normal.normalize()
+    } else {
+        direction
+    }
+}
+
+// central differences of the distance field, pointing out of the surface.
+// hard empty voxels only know the surface is at least half a voxel away;
+// they are taken to be a voxel further than the one the gradient is for,
+// as they lie beyond the band where distances are stored
+fn gradient(neighborhood: &Neighborhood, (x, y, z): (usize, usize, usize)) -> DVec3 {
+    let unknown = (neighborhood.read_distance(x, y, z) as f64 + 1.0).max(0.5);
+    let distance = |x, y, z| {
+        neighborhood
+            .read_known_distance(x, y, z)
+            .map_or(unknown, |distance| distance as f64)
+    };
+    dvec3(
+        distance(x + 1, y, z) - distance(x - 1, y, z),
+        distance(x, y + 1, z) - distance(x, y - 1, z),
+        distance(x, y, z + 1) - distance(x, y, z - 1),
+    ) * 0.5
+}
+
+fn outer_product(a: DVec3, b: DVec3) -> DMat3 {
+    DMat3::from_cols(a * b.x, a * b.y, a * b.z)
+}
+
+// least squares solution of the symmetric system, through its eigenvectors;
+// the ones with too small an eigenvalue are dropped (a pseudo-inverse), so
+// that nearly parallel planes do not send the solution far away
+fn solve(matrix: DMat3, target: DVec3) -> DVec3 {
+    let (values, vectors) = eigen_decomposition(matrix);
+    let max = values.iter().fold(0.0f64, |max, value| max.max(*value));
+
+    let mut solution = DVec3::ZERO;
+    for (value, vector) in values.into_iter().zip(vectors) {
+        if value > SINGULAR_RATIO * max {
+            solution += vector * vector.dot(target) / value;
+        }
+    }
+    solution
+}
+
+// eigenvalues and eigenvectors of a symmetric matrix, by Jacobi rotations
+fn eigen_decomposition(matrix: DMat3) -> ([f64; 3], [DVec3; 3]) {
+    let mut a = matrix.to_cols_array_2d();
+    let mut v = DMat3::IDENTITY.to_cols_array_2d();
+
+    for _ in 0..16 {
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q].abs() < 1e-15 {
+                continue;
+            }
+
+            // rotation in the (p, q) plane zeroing a[p][q]
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            for row in a.iter_mut().chain(&mut v) {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+            let (row_p, row_q) = (a[p], a[q]);
+            a[p] = [0, 1, 2].map(|k| c * row_p[k] - s * row_q[k]);
+            a[q] = [0, 1, 2].map(|k| s * row_p[k] + c * row_q[k]);
+        }
+    }
+
+    // v holds the rows of the rotations, whose columns are the eigenvectors
+    let v = DMat3::from_cols_array_2d(&v).transpose();
+    ([a[0][0], a[1][1], a[2][2]], [v.x_axis, v.y_axis, v.z_axis])
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::*;
+
+    use crate::voxels::{MeshingMode, Palette, VoxelGrid};
+
+    #[test]
+    fn cubes_keep_their_corners_and_flat_faces() {
+        // the second cube lies across chunk seams
+        for (min, max) in [((0, 0, 0), (9, 9, 9)), ((-5, -70, 60), (4, -60, 70))] {
+            let mut voxel_grid = VoxelGrid::new();
+            voxel_grid.paint_cube(min, max, 1);
+            let (vertices, indices) =
+                voxel_grid.generate_mesh(MeshingMode::DualContouring, &Palette::default());
+            assert!(!indices.is_empty());
+
+            let lo = IVec3::from(min).as_vec3() - 0.5;
+            let hi = IVec3::from(max).as_vec3() + 0.5;
+            for i in 0..8 {
+                let corner = vec3(
+                    if i & 1 == 0 { lo.x } else { hi.x },
+                    if i & 2 == 0 { lo.y } else { hi.y },
+                    if i & 4 == 0 { lo.z } else { hi.z },
+                );
+                assert!(
+                    vertices
+                        .iter()
+                        .any(|vertex| vertex.position.distance(corner) < 1e-3),
+                    "no vertex at {}",
+                    corner
+                );
+            }
+
+            for vertex in &vertices {
+                let position = vertex.position;
+                let inside = position.cmpge(lo - 1e-3).all() && position.cmple(hi + 1e-3).all();
+                let on_a_face = (position - lo)
+                    .abs()
+                    .min((position - hi).abs())
+                    .min_element()
+                    < 1e-3;
+                assert!(inside && on_a_face, "{} is off the surface", position);
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+use crate::voxels::VertexData;
+
+// writes a triangle list as a Wavefront OBJ file, positions scaled by the
+// given factor; vertex colors follow the positions, as most tools read them
+pub fn write_obj(
+    mut writer: impl Write,
+    vertices: &[VertexData],
+    indices: &[u32],
+    scale: f32,
+) -> io::Result<()> {
+    for vertex in vertices {
+        let position = vertex.position * scale;
+        let color = vertex.color;
+        writeln!(
+            writer,
+            "v {} {} {} {} {} {}",
+            position.x, position.y, position.z, color.x, color.y, color.z
+        )?;
+    }
+    for vertex in vertices {
+        let normal = vertex.normal;
+        writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+    }
+
+    // obj indices start at 1
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0] + 1, triangle[1] + 1, triangle[2] + 1);
+        writeln!(writer, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{MeshingMode, Palette, VoxelGrid};
+
+    #[test]
+    fn obj_files_hold_every_vertex_and_triangle() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (0, 0, 0), 1);
+        let (vertices, indices) =
+            voxel_grid.generate_mesh(MeshingMode::Blocky, &Palette::default());
+
+        let mut obj = vec![];
+        write_obj(&mut obj, &vertices, &indices, 0.5).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+        assert_eq!(lines.len(), 2 * vertices.len() + indices.len() / 3);
+
+        // positions are scaled, and faces point at both their vertex and its
+        // normal
+        let position = vertices[0].position * 0.5;
+        let first: Vec<f32> = lines[0][2..]
+            .split(' ')
+            .map(|value| value.parse().unwrap())
+            .collect();
+        assert_eq!(&first[..3], &[position.x, position.y, position.z]);
+        let triangle = &indices[indices.len() - 3..];
+        let (a, b, c) = (triangle[0] + 1, triangle[1] + 1, triangle[2] + 1);
+        let face = format!("f {}//{} {}//{} {}//{}", a, a, b, b, c, c);
+        assert_eq!(*lines.last().unwrap(), face);
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use glam::*;
+
+use crate::voxels::meshing::{row_mask, Neighborhood, Scan, CELL_CORNERS, CELL_EDGES};
+use crate::voxels::{Material, Palette, VertexData};
+
+// corners of each face of a cell, counter-clockwise when seen from outside
+const CELL_FACES: [[usize; 4]; 6] = [
+    [0, 4, 7, 3],
+    [1, 2, 6, 5],
+    [0, 1, 5, 4],
+    [3, 7, 6, 2],
+    [0, 3, 2, 1],
+    [4, 5, 6, 7],
+];
+
+// edges are keyed by their min corner and axis
+type EdgeKey = ((usize, usize, usize), usize);
+
+// vertices sit on the edges between voxels, triangles come from a table
+// indexed by which corners of the cell are solid
+pub fn generate_mesh(
+    neighborhood: &Neighborhood,
+    (lo, hi): ([usize; 3], [usize; 3]),
+    palette: &Palette,
+    scan: Scan,
+) -> (Vec<VertexData>, Vec<u32>) {
+    let mut vertices: Vec<VertexData> = vec![];
+    let mut indices: Vec<u32> = vec![];
+
+    let mut index_map: HashMap<EdgeKey, u32> = HashMap::new();
+
+    for_each_cell(neighborhood, (lo, hi), scan, |cell| {
+        for triangle in cell_triangles(neighborhood, cell) {
+            for (key, position, material) in triangle {
+                let index = *index_map.entry(key).or_insert_with(|| {
+                    vertices.push(VertexData {
+                        position,
+                        normal: Vec3::ZERO,
+                        color: palette.color(material),
+                    });
+                    vertices.len() as u32 - 1
+                });
+                indices.push(index);
+            }
+        }
+    });
+
+    // the normal of a vertex adds up all triangles around it, which includes
+    // the cells just around the brick; they are visited in the same order in
+    // any brick, so that vertices shared by two bricks get the exact same one
+    let around = (lo.map(|lo| lo - 1), hi.map(|hi| hi + 1));
+    for_each_cell(neighborhood, around, scan, |cell| {
+        for [a, b, c] in cell_triangles(neighborhood, cell) {
+            let normal = (b.1 - a.1).cross(c.1 - a.1);
+            for (key, _, _) in [a, b, c] {
+                if let Some(index) = index_map.get(&key) {
+                    vertices[*index as usize].normal += normal;
+                }
+            }
+        }
+    });
+
+    (vertices, indices)
+}
+
+// visits the cells of a range in order; going through rows skips the cells
+// whose corners are all solid or all empty, as they hold no triangle
+fn for_each_cell(
+    neighborhood: &Neighborhood,
+    (lo, hi): ([usize; 3], [usize; 3]),
+    scan: Scan,
+    mut visit: impl FnMut((usize, usize, usize)),
+) {
+    for z in lo[2]..hi[2] {
+        for y in lo[1]..hi[1] {
+            match scan {
+                Scan::Voxels => {
+                    for x in lo[0]..hi[0] {
+                        visit((x, y, z));
+                    }
+                }
+                Scan::Rows => {
+                    let rows = [
+                        neighborhood.row(y, z),
+                        neighborhood.row(y + 1, z),
+                        neighborhood.row(y, z + 1),
+                        neighborhood.row(y + 1, z + 1),
+                    ];
+
+                    let (mut any, mut all) = (0, !0);
+                    for row in rows {
+                        any |= row | (row >> 1);
+                        all &= row & (row >> 1);
+                    }
+
+                    let mut cells = any & !all & row_mask(lo[0], hi[0]);
+                    while cells != 0 {
+                        let bit = cells.trailing_zeros();
+                        cells &= cells - 1;
+
+                        visit((bit as usize + 63, y, z));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// corners of the triangles of a cell, with the position and the material of
+// the solid end of their edge
+fn cell_triangles<'a>(
+    neighborhood: &'a Neighborhood<'a>,
+    (x, y, z): (usize, usize, usize),
+) -> impl Iterator<Item = [(EdgeKey, Vec3, Material); 3]> + 'a {
+    let mut case = 0;
+    for (i, (dx, dy, dz)) in CELL_CORNERS.iter().enumerate() {
+        case |= neighborhood.read(x + dx, y + dy, z + dz) << i;
+    }
+
+    triangle_table()[case as usize].iter().map(move |triangle| {
+        triangle.map(|edge| {
+            let (a, b) = CELL_EDGES[edge];
+            let (corner_a, corner_b) = (CELL_CORNERS[a], CELL_CORNERS[b]);
+            let (min_corner, axis, t) = neighborhood.edge_crossing((x, y, z), a, b);
+
+            let solid = if (case >> a) & 1 == 1 {
+                corner_a
+            } else {
+                corner_b
+            };
+            let material = neighborhood.read_material(x + solid.0, y + solid.1, z + solid.2);
+
+            let mut position = neighborhood.world_position(min_corner);
+            position[axis] += t;
+
+            ((min_corner, axis), position, material)
+        })
+    })
+}
+
+// triangles of the surface for each of the 256 cases, as triples of CELL_EDGES
+fn triangle_table() -> &'static Vec<Vec<[usize; 3]>> {
+    static TABLE: OnceLock<Vec<Vec<[usize; 3]>>> = OnceLock::new();
+    TABLE.get_or_init(|| (0..256).map(case_triangles).collect())
+}
+
+// rather than spelling out the classic table, the triangles of each case are
+// derived by tracing the outline of the surface on the faces of the cell.
+// on each face, a segment runs along each group of adjacent solid corners;
+// solid corners only touching by a diagonal stay apart, which both cells
+// sharing the face agree on, so the surface is always closed
+fn case_triangles(case: usize) -> Vec<[usize; 3]> {
+    let solid = |corner: usize| (case >> corner) & 1 == 1;
+    let edge = |a: usize, b: usize| {
+        CELL_EDGES
+            .iter()
+            .position(|&(c, d)| (c, d) == (a, b) || (c, d) == (b, a))
+            .unwrap()
+    };
+
+    // each crossed edge leads to the next one along the outline, with the
+    // empty side on the left when seen from outside the cell
+    let mut next = [None; 12];
+    for face in CELL_FACES {
+        for i in 0..4 {
+            let previous = face[(i + 3) % 4];
+            if !solid(face[i]) || solid(previous) {
+                continue;
+            }
+
+            let mut last = i;
+            while solid(face[(last + 1) % 4]) {
+                last = (last + 1) % 4;
+            }
+
+            next[edge(previous, face[i])] = Some(edge(face[last], face[(last + 1) % 4]));
+        }
+    }
+
+    // close each outline and fill it with a fan of triangles
+    let mut triangles = vec![];
+    let mut visited = [false; 12];
+    for start in 0..12 {
+        if visited[start] || next[start].is_none() {
+            continue;
+        }
+
+        let mut outline = vec![];
+        let mut current = start;
+        while !visited[current] {
+            visited[current] = true;
+            outline.push(current);
+            current = next[current].unwrap();
+        }
+
+        for i in 1..outline.len() - 1 {
+            triangles.push([outline[0], outline[i], outline[i + 1]]);
+        }
+    }
+
+    triangles
+}
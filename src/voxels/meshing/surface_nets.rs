@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use glam::*;
+
+use crate::voxels::meshing::{row_mask, Neighborhood, Scan, CELL_CORNERS, CELL_EDGES};
+use crate::voxels::{Palette, VertexData};
+
+// vertices sit at the average of the crossings around them
+pub fn generate_mesh(
+    neighborhood: &Neighborhood,
+    range: ([usize; 3], [usize; 3]),
+    palette: &Palette,
+    scan: Scan,
+) -> (Vec<VertexData>, Vec<u32>) {
+    generate_dual_mesh(neighborhood, range, scan, |cell| {
+        cell_vertex(neighborhood, cell, palette)
+    })
+}
+
+// one vertex per cell crossing the surface, one quad per edge crossing it;
+// where the vertex of a cell goes is up to the caller
+pub fn generate_dual_mesh(
+    neighborhood: &Neighborhood,
+    (lo, hi): ([usize; 3], [usize; 3]),
+    scan: Scan,
+    cell_vertex: impl Fn((usize, usize, usize)) -> VertexData,
+) -> (Vec<VertexData>, Vec<u32>) {
+    let mut vertices: Vec<VertexData> = vec![];
+    let mut indices: Vec<u32> = vec![];
+
+    // cells are keyed by their min corner; the quads of the brick also use
+    // the vertices of the cells just before it, which are repeated here
+    let mut index_map: HashMap<(usize, usize, usize), u32> = HashMap::new();
+    let mut vertex = |cell: (usize, usize, usize), vertices: &mut Vec<VertexData>| {
+        *index_map.entry(cell).or_insert_with(|| {
+            vertices.push(cell_vertex(cell));
+            vertices.len() as u32 - 1
+        })
+    };
+
+    // a voxel of the brick, and its neighbors along each axis
+    let mut visit = |(x, y, z), v0, v1_x, v1_y, v1_z| {
+        if v0 != v1_x {
+            let cells = [(x, y - 1, z - 1), (x, y - 1, z), (x, y, z), (x, y, z - 1)]
+                .map(|cell| vertex(cell, &mut vertices));
+            emit_quad(&mut indices, cells, v0 < v1_x);
+        }
+
+        if v0 != v1_y {
+            let cells = [(x - 1, y, z - 1), (x, y, z - 1), (x, y, z), (x - 1, y, z)]
+                .map(|cell| vertex(cell, &mut vertices));
+            emit_quad(&mut indices, cells, v0 < v1_y);
+        }
+
+        if v0 != v1_z {
+            let cells = [(x - 1, y - 1, z), (x - 1, y, z), (x, y, z), (x, y - 1, z)]
+                .map(|cell| vertex(cell, &mut vertices));
+            emit_quad(&mut indices, cells, v0 < v1_z);
+        }
+    };
+
+    // scan all edges leaving the voxels of the brick
+    for z in lo[2]..hi[2] {
+        for y in lo[1]..hi[1] {
+            match scan {
+                Scan::Voxels => {
+                    for x in lo[0]..hi[0] {
+                        visit(
+                            (x, y, z),
+                            neighborhood.read(x, y, z),
+                            neighborhood.read(x + 1, y, z),
+                            neighborhood.read(x, y + 1, z),
+                            neighborhood.read(x, y, z + 1),
+                        );
+                    }
+                }
+                Scan::Rows => {
+                    let row = neighborhood.row(y, z);
+                    let row_x = row >> 1;
+                    let row_y = neighborhood.row(y + 1, z);
+                    let row_z = neighborhood.row(y, z + 1);
+
+                    // only the voxels with an edge crossing the surface
+                    let mut crossings =
+                        ((row ^ row_x) | (row ^ row_y) | (row ^ row_z)) & row_mask(lo[0], hi[0]);
+                    while crossings != 0 {
+                        let bit = crossings.trailing_zeros();
+                        crossings &= crossings - 1;
+
+                        visit(
+                            (bit as usize + 63, y, z),
+                            (row >> bit) as u64 & 1,
+                            (row_x >> bit) as u64 & 1,
+                            (row_y >> bit) as u64 & 1,
+                            (row_z >> bit) as u64 & 1,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+// the vertex of a cell only depends on its corners, so that cells repeated
+// in several bricks get the exact same vertex
+pub fn cell_vertex(
+    neighborhood: &Neighborhood,
+    (x, y, z): (usize, usize, usize),
+    palette: &Palette,
+) -> VertexData {
+    let mut sum = Vec3::ZERO;
+    let mut count = 0;
+
+    // the normal adds up the quads of all edges of the cell crossing the
+    // surface, each pointing from the solid side to the empty one
+    let mut normal = Vec3::ZERO;
+
+    for (a, b) in CELL_EDGES {
+        let (corner_a, corner_b) = (CELL_CORNERS[a], CELL_CORNERS[b]);
+        let solid_a = neighborhood.read(x + corner_a.0, y + corner_a.1, z + corner_a.2);
+        let solid_b = neighborhood.read(x + corner_b.0, y + corner_b.1, z + corner_b.2);
+
+        if solid_a != solid_b {
+            sum += neighborhood.crossing((x, y, z), a, b);
+            count += 1;
+
+            let direction = Vec3::new(
+                corner_b.0 as f32 - corner_a.0 as f32,
+                corner_b.1 as f32 - corner_a.1 as f32,
+                corner_b.2 as f32 - corner_a.2 as f32,
+            );
+            normal += if solid_a == 1 { direction } else { -direction };
+        }
+    }
+
+    // colored after its first solid corner
+    let (dx, dy, dz) = *CELL_CORNERS
+        .iter()
+        .find(|(dx, dy, dz)| neighborhood.read(x + dx, y + dy, z + dz) == 1)
+        .unwrap();
+    let material = neighborhood.read_material(x + dx, y + dy, z + dz);
+
+    VertexData {
+        position: neighborhood.world_position((x, y, z)) + sum / count as f32,
+        normal,
+        color: palette.color(material),
+    }
+}
+
+fn emit_quad(indices: &mut Vec<u32>, [i0, i1, i2, i3]: [u32; 4], entering: bool) {
+    if entering {
+        indices.extend([i0, i1, i2, i2, i3, i0]);
+    } else {
+        indices.extend([i0, i3, i2, i2, i1, i0]);
+    }
+}
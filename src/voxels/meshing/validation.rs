@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use glam::*;
+
+use crate::voxels::VertexData;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    pub triangles: usize,
+
+    // edges with a triangle on one side only, or between two triangles
+    // disagreeing on their orientation
+    pub boundary_edges: usize,
+
+    // edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+
+    // triangles with two corners at the same position, or without any area
+    pub degenerate_triangles: usize,
+}
+
+impl MeshReport {
+    pub fn is_closed(&self) -> bool {
+        self.boundary_edges == 0
+    }
+
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges == 0
+    }
+
+    pub fn is_watertight(&self) -> bool {
+        self.is_closed() && self.is_manifold()
+    }
+}
+
+// checks the topology of a triangle list; vertices are welded by position
+// first, as flat shaded meshes duplicate them for each face
+pub fn validate_mesh(vertices: &[VertexData], indices: &[u32]) -> MeshReport {
+    let mut report = MeshReport {
+        triangles: indices.len() / 3,
+        ..Default::default()
+    };
+
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    let ids: Vec<u32> = vertices
+        .iter()
+        .map(|vertex| {
+            let key = vertex.position.to_array().map(|x| (x + 0.0).to_bits());
+            let next = welded.len() as u32;
+            *welded.entry(key).or_insert(next)
+        })
+        .collect();
+
+    // uses of each edge by its lowest vertex first, then the other way around
+    let mut edges: HashMap<(u32, u32), (usize, usize)> = HashMap::new();
+
+    for triangle in indices.chunks_exact(3) {
+        let corners = [triangle[0], triangle[1], triangle[2]].map(|i| ids[i as usize]);
+        if corners[0] == corners[1] || corners[1] == corners[2] || corners[2] == corners[0] {
+            report.degenerate_triangles += 1;
+            continue;
+        }
+
+        let [a, b, c] =
+            [triangle[0], triangle[1], triangle[2]].map(|i| vertices[i as usize].position);
+        let longest = (b - a)
+            .length_squared()
+            .max((c - b).length_squared())
+            .max((a - c).length_squared());
+        if (b - a).cross(c - a).length() <= longest * 1e-6 {
+            report.degenerate_triangles += 1;
+        }
+
+        for i in 0..3 {
+            let (from, to) = (corners[i], corners[(i + 1) % 3]);
+            let uses = edges.entry((from.min(to), from.max(to))).or_default();
+            if from < to {
+                uses.0 += 1;
+            } else {
+                uses.1 += 1;
+            }
+        }
+    }
+
+    for (forward, backward) in edges.into_values() {
+        if forward + backward > 2 {
+            report.non_manifold_edges += 1;
+        } else if forward != backward {
+            report.boundary_edges += 1;
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{MeshingMode, Palette, VoxelGrid};
+
+    const MODES: [MeshingMode; 5] = [
+        MeshingMode::SurfaceNets,
+        MeshingMode::Blocky,
+        MeshingMode::Greedy,
+        MeshingMode::MarchingCubes,
+        MeshingMode::DualContouring,
+    ];
+
+    // a box and a sphere across several chunk seams, at negative coordinates
+    fn scene() -> VoxelGrid {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, -70, -70), (-50, -60, 10), 1);
+        voxel_grid.paint_sphere((-64, 0, -64), 20.3, 2);
+        voxel_grid
+    }
+
+    #[test]
+    fn meshes_are_watertight() {
+        let voxel_grid = scene();
+        for mode in MODES {
+            let (vertices, indices) = voxel_grid.generate_mesh(mode, &Palette::default());
+            let report = validate_mesh(&vertices, &indices);
+            assert!(report.triangles > 0, "{:?}", mode);
+            assert_eq!(report.boundary_edges, 0, "{:?}", mode);
+            assert_eq!(report.non_manifold_edges, 0, "{:?}", mode);
+            assert_eq!(report.degenerate_triangles, 0, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn voxels_sharing_an_edge_stay_closed() {
+        // two voxels touching along an edge only: the surface pinches there,
+        // which the meshers keep as a single edge used by four triangles,
+        // except marching cubes which separates both sides of the cell
+        let mut voxel_grid = scene();
+        voxel_grid.paint_cube((0, 0, 0), (0, 0, 0), 3);
+        voxel_grid.paint_cube((1, 1, 0), (1, 1, 0), 3);
+        for mode in MODES {
+            let (vertices, indices) = voxel_grid.generate_mesh(mode, &Palette::default());
+            let report = validate_mesh(&vertices, &indices);
+            let pinched = usize::from(mode != MeshingMode::MarchingCubes);
+            assert_eq!(report.boundary_edges, 0, "{:?}", mode);
+            assert_eq!(report.non_manifold_edges, pinched, "{:?}", mode);
+            assert_eq!(report.degenerate_triangles, 0, "{:?}", mode);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use crate::voxels::bit_volume::BitGrid;
+use crate::voxels::{Coords, Material, VoxelGrid};
+
+// voxels around each voxel that it grows into, or that it needs to survive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    // the 6 voxels sharing a face with the center one
+    Faces,
+
+    // the 26 voxels touching the center one
+    Cube,
+
+    // all voxels within the radius of the center one
+    Sphere(f32),
+}
+
+impl Kernel {
+    // rows of the kernel, by their offset along y and z and how far they
+    // reach on both sides along x
+    fn rows(&self) -> Vec<(i32, i32, usize)> {
+        match self {
+            Kernel::Faces => vec![(0, 0, 1), (-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0)],
+            Kernel::Cube => (-1..=1)
+                .flat_map(|dz| (-1..=1).map(move |dy| (dy, dz, 1)))
+                .collect(),
+            Kernel::Sphere(radius) => {
+                let radius = radius.max(0.0);
+                let reach = radius.floor() as i32;
+                let mut rows = vec![];
+                for dz in -reach..=reach {
+                    for dy in -reach..=reach {
+                        let remaining = radius * radius - (dy * dy + dz * dz) as f32;
+                        if remaining >= 0.0 {
+                            rows.push((dy, dz, remaining.sqrt().floor() as usize));
+                        }
+                    }
+                }
+                rows
+            }
+        }
+    }
+
+    // all voxels of the kernel but the center one, closest first
+    fn offsets(&self) -> Vec<Coords> {
+        let mut offsets: Vec<Coords> = self
+            .rows()
+            .into_iter()
+            .flat_map(|(dy, dz, reach)| {
+                let reach = reach as i32;
+                (-reach..=reach).map(move |dx| (dx, dy, dz))
+            })
+            .filter(|offset| *offset != (0, 0, 0))
+            .collect();
+        offsets.sort_by_key(|(x, y, z)| x * x + y * y + z * z);
+        offsets
+    }
+
+    // how far the kernel reaches along any axis
+    fn reach(&self) -> i32 {
+        match self {
+            Kernel::Faces | Kernel::Cube => 1,
+            Kernel::Sphere(radius) => radius.max(0.0).floor() as i32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pass {
+    Dilate,
+    Erode,
+}
+
+impl VoxelGrid {
+    // grows shapes by the kernel, once per step
+    pub fn dilate(&mut self, kernel: Kernel, steps: u32) {
+        self.morph(kernel, &[(Pass::Dilate, steps)]);
+    }
+
+    // shrinks shapes by the kernel, once per step
+    pub fn erode(&mut self, kernel: Kernel, steps: u32) {
+        self.morph(kernel, &[(Pass::Erode, steps)]);
+    }
+
+    // removes the parts of shapes thinner than the kernel, leaving the
+    // rest as it was
+    pub fn open(&mut self, kernel: Kernel, steps: u32) {
+        self.morph(kernel, &[(Pass::Erode, steps), (Pass::Dilate, steps)]);
+    }
+
+    // fills the gaps and holes narrower than the kernel, leaving the rest
+    // as it was
+    pub fn close(&mut self, kernel: Kernel, steps: u32) {
+        self.morph(kernel, &[(Pass::Dilate, steps), (Pass::Erode, steps)]);
+    }
+
+    // runs the passes on whole rows of bits, chunk by chunk, then writes
+    // back the voxels that changed; new voxels take the material of the
+    // closest original one, and changed voxels lose their distance
+    fn morph(&mut self, kernel: Kernel, passes: &[(Pass, u32)]) {
+        let original = BitGrid::from_grid(self);
+
+        let rows = kernel.rows();
+        let offsets = kernel.offsets();
+        let reach = kernel.reach().max(0) as usize;
+
+        // materials of the voxels grown so far, taken from the closest
+        // voxel they grew from
+        let mut materials: HashMap<Coords, Material> = HashMap::new();
+
+        let mut grid = original.clone();
+        for (pass, steps) in passes {
+            for _ in 0..*steps {
+                // voxels only grow into the chunks within reach of solid
+                // ones, and only shrink in chunks holding some
+                let next = match pass {
+                    Pass::Dilate => grid.map(&grid.chunks_around(reach), reach, |volume| {
+                        volume.dilate(&rows)
+                    }),
+                    Pass::Erode => grid.map(&grid.chunk_coords().collect(), reach, |volume| {
+                        volume.erode(&rows)
+                    }),
+                };
+
+                if *pass == Pass::Dilate {
+                    for coords in grid.differences(&next) {
+                        if original.get(coords) || materials.contains_key(&coords) {
+                            continue;
+                        }
+                        let source = offsets
+                            .iter()
+                            .map(|offset| {
+                                (
+                                    coords.0.saturating_add(offset.0),
+                                    coords.1.saturating_add(offset.1),
+                                    coords.2.saturating_add(offset.2),
+                                )
+                            })
+                            .find(|source| grid.get(*source));
+                        let material = match source {
+                            Some(source) => match materials.get(&source) {
+                                Some(material) => *material,
+                                None => self.voxel(source).material,
+                            },
+                            None => 0,
+                        };
+                        materials.insert(coords, material);
+                    }
+                }
+
+                grid = next;
+            }
+        }
+
+        self.write_grid(&original, &grid, &materials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+    use crate::voxels::WORLD_LIMIT;
+
+    #[test]
+    fn far_apart_shapes_grow_and_shrink_on_their_own() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (3, 3, 3), 1);
+        voxel_grid.paint_cube((20000, 20000, 2000), (20003, 20003, 2003), 2);
+        let before = solid(&voxel_grid);
+
+        // each cube grows a slab on each of its faces
+        voxel_grid.dilate(Kernel::Faces, 1);
+        let dilated = solid(&voxel_grid);
+        assert_eq!(dilated.len(), 2 * (64 + 6 * 16));
+        assert_eq!(dilated[&(-1, 1, 2)], 1);
+        assert_eq!(dilated[&(20004, 20001, 2002)], 2);
+        assert!(!dilated.contains_key(&(-1, -1, 0)));
+
+        voxel_grid.erode(Kernel::Faces, 1);
+        assert_eq!(solid(&voxel_grid), before);
+    }
+
+    #[test]
+    fn kernels_reach_across_chunk_seams() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-1, -1, -1), (-1, -1, -1), 3);
+        voxel_grid.dilate(Kernel::Sphere(2.0), 1);
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels.len(), 1 + 6 + 12 + 8 + 6);
+        assert!(voxels.contains_key(&(1, -1, -1)));
+        assert!(voxels.values().all(|material| *material == 3));
+
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((63, 63, 63), (63, 63, 63), 3);
+        voxel_grid.dilate(Kernel::Cube, 2);
+        assert_eq!(solid(&voxel_grid).len(), 125);
+        assert_eq!(
+            voxel_grid.bounding_box(),
+            Some(((61, 61, 61), (65, 65, 65)))
+        );
+    }
+
+    #[test]
+    fn growth_stops_at_the_world_bounds() {
+        let corner = (WORLD_LIMIT - 1, WORLD_LIMIT - 1, -WORLD_LIMIT);
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube(corner, corner, 1);
+        voxel_grid.dilate(Kernel::Cube, 3);
+        assert_eq!(solid(&voxel_grid).len(), 64);
+        assert_eq!(
+            voxel_grid.bounding_box(),
+            Some((
+                (WORLD_LIMIT - 4, WORLD_LIMIT - 4, -WORLD_LIMIT),
+                (WORLD_LIMIT - 1, WORLD_LIMIT - 1, 3 - WORLD_LIMIT)
+            ))
+        );
+    }
+
+    #[test]
+    fn opening_removes_thin_parts() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-5, -5, -5), (4, 4, 4), 1);
+        let cube = solid(&voxel_grid);
+        voxel_grid.paint_cube((5, -20, 0), (5, 20, 0), 2);
+
+        voxel_grid.open(Kernel::Cube, 1);
+        assert_eq!(solid(&voxel_grid), cube);
+    }
+
+    #[test]
+    fn closing_fills_narrow_gaps() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-10, -5, -5), (-1, 4, 4), 1);
+        voxel_grid.paint_cube((1, -5, -5), (10, 4, 4), 2);
+
+        voxel_grid.close(Kernel::Cube, 1);
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels.len(), 21 * 10 * 10);
+        assert!(voxels.contains_key(&(0, 0, 0)));
+        assert_eq!(voxels[&(-1, 0, 0)], 1);
+        assert_eq!(voxels[&(1, 0, 0)], 2);
+    }
+}
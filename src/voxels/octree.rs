@@ -0,0 +1,625 @@
+use glam::*;
+use rayon::prelude::*;
+
+use crate::voxels::chunk::{split_coords, Chunk, Voxel};
+use crate::voxels::{
+    chunk_origin, ChunkCoords, Coords, Material, MeshingMode, Palette, RayHit, VertexData,
+    VoxelGrid, CHUNK_SIZE,
+};
+
+// coarser levels would mix voxels of several chunks
+pub const MAX_LOD: u32 = 6;
+
+// sparse voxel octree over a cube of voxels, whose side is a power of two;
+// regions that are all empty, or all solid with a single material, are
+// single nodes however large they are. voxels only keep whether they are
+// solid and their material, not their distance.
+//
+// the octree is a side index built from a VoxelGrid and kept in step with
+// it, not another storage for grids: edits still go to the grid, raycasts
+// walk the nodes, and coarse levels are written back into a small grid,
+// brick by brick, to be meshed by the meshers of VoxelGrid
+pub struct Octree {
+    min: Coords,
+    size: i32,
+    root: Node,
+}
+
+enum Node {
+    Empty,
+    Solid(Material),
+    Branch(Box<Branch>),
+}
+
+// children are ordered by x first, then y, then z, low half first
+struct Branch {
+    children: [Node; 8],
+
+    // number of solid voxels below the branch, and their most common
+    // material, or close to it: the one of the child with the most solid
+    // voxels, going down the tree
+    solid: u64,
+    material: Material,
+}
+
+impl VoxelGrid {
+    // chunks are turned into nodes in parallel, then put together
+    pub fn octree(&self) -> Octree {
+        let nodes: Vec<(ChunkCoords, Node)> = self
+            .chunks
+            .par_iter()
+            .map(|(chunk_coords, chunk)| (*chunk_coords, chunk_node(chunk, (0, 0, 0), 64)))
+            .collect();
+
+        let (min, max) = match nodes.first() {
+            Some((coords, _)) => {
+                nodes
+                    .iter()
+                    .fold((*coords, *coords), |(min, max), (coords, _)| {
+                        (
+                            (
+                                min.0.min(coords.0),
+                                min.1.min(coords.1),
+                                min.2.min(coords.2),
+                            ),
+                            (
+                                max.0.max(coords.0),
+                                max.1.max(coords.1),
+                                max.2.max(coords.2),
+                            ),
+                        )
+                    })
+            }
+            None => {
+                return Octree {
+                    min: (0, 0, 0),
+                    size: CHUNK_SIZE,
+                    root: Node::Empty,
+                }
+            }
+        };
+
+        // the smallest cube of chunks around them all
+        let side = (max.0 - min.0).max(max.1 - min.1).max(max.2 - min.2) + 1;
+        let side = (side as u32).next_power_of_two() as i32;
+
+        Octree {
+            min: chunk_origin(min),
+            size: side * CHUNK_SIZE,
+            root: assemble(Node::Empty, nodes, min, side),
+        }
+    }
+}
+
+impl Octree {
+    // follows the edits made to the grid the octree was built from: only the
+    // changed chunks and the nodes above them are built again, unless the
+    // grid grew out of the cube of the octree
+    pub fn update(&mut self, voxel_grid: &VoxelGrid, changed: &[ChunkCoords]) {
+        let (min, _) = split_coords(self.min);
+        let side = self.size / CHUNK_SIZE;
+        let inside = |coords: &ChunkCoords| {
+            let offset = IVec3::from(*coords) - IVec3::from(min);
+            offset.cmpge(IVec3::ZERO).all() && offset.cmplt(IVec3::splat(side)).all()
+        };
+        if changed
+            .iter()
+            .any(|coords| !inside(coords) && voxel_grid.chunks.contains_key(coords))
+        {
+            *self = voxel_grid.octree();
+            return;
+        }
+
+        let nodes: Vec<(ChunkCoords, Node)> = changed
+            .par_iter()
+            .filter(|coords| inside(coords))
+            .map(|coords| {
+                let node = match voxel_grid.chunks.get(coords) {
+                    Some(chunk) => chunk_node(chunk, (0, 0, 0), 64),
+                    None => Node::Empty,
+                };
+                (*coords, node)
+            })
+            .collect();
+
+        let root = std::mem::replace(&mut self.root, Node::Empty);
+        self.root = assemble(root, nodes, min, side);
+    }
+
+    // min corner of the cube the octree covers, and its side
+    pub fn bounds(&self) -> (Coords, i32) {
+        (self.min, self.size)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.root.count()
+    }
+
+    pub fn read(&self, coords: Coords) -> Option<Material> {
+        let mut local = IVec3::from(coords) - IVec3::from(self.min);
+        if local.cmplt(IVec3::ZERO).any() || local.cmpge(IVec3::splat(self.size)).any() {
+            return None;
+        }
+
+        let mut node = &self.root;
+        let mut size = self.size;
+        loop {
+            match node {
+                Node::Empty => return None,
+                Node::Solid(material) => return Some(*material),
+                Node::Branch(branch) => {
+                    size /= 2;
+                    let octant = local.cmpge(IVec3::splat(size)).bitmask() as usize;
+                    local %= size;
+                    node = &branch.children[octant];
+                }
+            }
+        }
+    }
+
+    // voxels of the given level of detail, each standing for a cube of 2^lod
+    // voxels of the octree on a side, and solid when at least half of them
+    // are; coarse voxel coordinates are the ones of the first voxel of their
+    // cube, divided by its side
+    pub fn level(&self, lod: u32) -> VoxelGrid {
+        let mut voxel_grid = VoxelGrid::new();
+        self.write_level(lod.min(MAX_LOD), None, &mut voxel_grid);
+        voxel_grid
+    }
+
+    // the mesh of a level, scaled back to the voxels of the octree
+    pub fn generate_mesh(
+        &self,
+        lod: u32,
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        let lod = lod.min(MAX_LOD);
+        let (vertices, indices) = self.level(lod).generate_mesh(mode, palette);
+        (scale_vertices(vertices, lod), indices)
+    }
+
+    // brick of a level, given in coarse chunk coordinates; only the chunks of
+    // the level the brick depends on are copied out of the octree
+    pub fn generate_brick_mesh(
+        &self,
+        brick: ChunkCoords,
+        lod: u32,
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> (Vec<VertexData>, Vec<u32>) {
+        let lod = lod.min(MAX_LOD);
+        let min = chunk_origin((brick.0 - 1, brick.1 - 1, brick.2 - 1));
+        let max = chunk_origin((brick.0 + 2, brick.1 + 2, brick.2 + 2));
+        let region = (min, (max.0 - 1, max.1 - 1, max.2 - 1));
+
+        let mut voxel_grid = VoxelGrid::new();
+        self.write_level(lod, Some(region), &mut voxel_grid);
+        let (vertices, indices) = voxel_grid.generate_brick_mesh(brick, mode, palette);
+        (scale_vertices(vertices, lod), indices)
+    }
+
+    pub fn generate_brick_meshes(
+        &self,
+        bricks: &[ChunkCoords],
+        lod: u32,
+        mode: MeshingMode,
+        palette: &Palette,
+    ) -> Vec<(Vec<VertexData>, Vec<u32>)> {
+        bricks
+            .par_iter()
+            .map(|brick| self.generate_brick_mesh(*brick, lod, mode, palette))
+            .collect()
+    }
+
+    // first solid voxel along the ray, like VoxelGrid::raycast; nodes are
+    // visited front to back, skipping whole empty and solid regions at once
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO || !origin.is_finite() {
+            return None;
+        }
+
+        let ray = Ray {
+            origin: origin.as_dvec3(),
+            direction: direction.as_dvec3(),
+            max_distance: max_distance as f64,
+        };
+        ray.cast(&self.root, IVec3::from(self.min), self.size)
+    }
+
+    // coarse voxels of the level, only within the region when given, in
+    // coarse coordinates, inclusive
+    fn write_level(&self, lod: u32, region: Option<(Coords, Coords)>, voxel_grid: &mut VoxelGrid) {
+        voxel_grid.begin_edit();
+
+        let region = region.map(|(min, max)| (IVec3::from(min), IVec3::from(max)));
+        let mut stack = vec![(&self.root, IVec3::from(self.min), self.size)];
+        while let Some((node, min, size)) = stack.pop() {
+            let (mut lo, mut hi) = (min >> lod as i32, (min + (size - 1)) >> lod as i32);
+            if let Some((region_min, region_max)) = region {
+                lo = lo.max(region_min);
+                hi = hi.min(region_max);
+                if lo.cmpgt(hi).any() {
+                    continue;
+                }
+            }
+
+            match node {
+                Node::Empty => {}
+                Node::Solid(material) => {
+                    for z in lo.z..=hi.z {
+                        for y in lo.y..=hi.y {
+                            for chunk_x in lo.x.div_euclid(CHUNK_SIZE)..=hi.x.div_euclid(CHUNK_SIZE)
+                            {
+                                let (chunk_coords, (_, local_y, local_z)) =
+                                    split_coords((chunk_x * CHUNK_SIZE, y, z));
+                                let chunk_min_x = chunk_x * CHUNK_SIZE;
+                                voxel_grid.chunk_mut(chunk_coords).fill_row(
+                                    local_y,
+                                    local_z,
+                                    (lo.x - chunk_min_x).max(0) as usize,
+                                    (hi.x - chunk_min_x).min(CHUNK_SIZE - 1) as usize,
+                                    *material,
+                                );
+                            }
+                        }
+                    }
+                }
+                Node::Branch(branch) if size == 1 << lod => {
+                    let volume = (size as u64).pow(3);
+                    if branch.solid * 2 >= volume {
+                        let (chunk_coords, local) = split_coords(lo.into());
+                        voxel_grid
+                            .chunk_mut(chunk_coords)
+                            .set(local, Voxel::hard(true, branch.material));
+                    }
+                }
+                Node::Branch(branch) => {
+                    let half = size / 2;
+                    for (octant, child) in branch.children.iter().enumerate() {
+                        stack.push((child, min + octant_offset(octant) * half, half));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Node {
+    // collapses uniform children into a single node
+    fn branch(children: [Node; 8], child_size: i32) -> Self {
+        if children.iter().all(|child| matches!(child, Node::Empty)) {
+            return Node::Empty;
+        }
+        if let Node::Solid(material) = children[0] {
+            if children
+                .iter()
+                .all(|child| matches!(child, Node::Solid(other) if *other == material))
+            {
+                return Node::Solid(material);
+            }
+        }
+
+        let counts = children.each_ref().map(|child| child.solid(child_size));
+        let largest = (0..8).rev().max_by_key(|octant| counts[*octant]).unwrap();
+        let material = match &children[largest] {
+            Node::Solid(material) => *material,
+            Node::Branch(branch) => branch.material,
+            Node::Empty => 0,
+        };
+
+        Node::Branch(Box::new(Branch {
+            children,
+            solid: counts.iter().sum(),
+            material,
+        }))
+    }
+
+    fn solid(&self, size: i32) -> u64 {
+        match self {
+            Node::Empty => 0,
+            Node::Solid(_) => (size as u64).pow(3),
+            Node::Branch(branch) => branch.solid,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Node::Branch(branch) => 1 + branch.children.iter().map(Node::count).sum::<usize>(),
+            _ => 1,
+        }
+    }
+}
+
+// node of the cube of a chunk starting at lo, checking whole rows at once
+// to find uniform cubes early
+fn chunk_node(chunk: &Chunk, lo: (usize, usize, usize), size: usize) -> Node {
+    let mask = if size == 64 {
+        !0
+    } else {
+        ((1u64 << size) - 1) << lo.0
+    };
+    let (mut any, mut all) = (false, true);
+    for z in lo.2..lo.2 + size {
+        for y in lo.1..lo.1 + size {
+            let bits = chunk.row(y, z) & mask;
+            any |= bits != 0;
+            all &= bits == mask;
+        }
+    }
+    if !any {
+        return Node::Empty;
+    }
+    if all {
+        let material = chunk.read_material(lo);
+        let uniform = (lo.2..lo.2 + size).all(|z| {
+            (lo.1..lo.1 + size)
+                .all(|y| (lo.0..lo.0 + size).all(|x| chunk.read_material((x, y, z)) == material))
+        });
+        if uniform {
+            return Node::Solid(material);
+        }
+    }
+
+    let half = size / 2;
+    let children = std::array::from_fn(|octant| {
+        let offset = octant_offset(octant).as_uvec3() * half as u32;
+        chunk_node(
+            chunk,
+            (
+                lo.0 + offset.x as usize,
+                lo.1 + offset.y as usize,
+                lo.2 + offset.z as usize,
+            ),
+            half,
+        )
+    });
+    Node::branch(children, half as i32)
+}
+
+// puts chunk nodes in place of the ones below a node covering side chunks on
+// each axis from min, in chunk coordinates; only the nodes above them are
+// built again
+fn assemble(node: Node, nodes: Vec<(ChunkCoords, Node)>, min: ChunkCoords, side: i32) -> Node {
+    if nodes.is_empty() {
+        return node;
+    }
+    if side == 1 {
+        return nodes.into_iter().next().unwrap().1;
+    }
+
+    let mut previous = match node {
+        Node::Empty => std::array::from_fn(|_| Node::Empty),
+        Node::Solid(material) => std::array::from_fn(|_| Node::Solid(material)),
+        Node::Branch(branch) => branch.children,
+    };
+    let half = side / 2;
+    let mut octants: [Vec<(ChunkCoords, Node)>; 8] = Default::default();
+    for (coords, node) in nodes {
+        let octant = (coords.0 - min.0 >= half) as usize
+            | ((coords.1 - min.1 >= half) as usize) << 1
+            | ((coords.2 - min.2 >= half) as usize) << 2;
+        octants[octant].push((coords, node));
+    }
+
+    let children = std::array::from_fn(|octant| {
+        let offset = octant_offset(octant) * half;
+        assemble(
+            std::mem::replace(&mut previous[octant], Node::Empty),
+            std::mem::take(&mut octants[octant]),
+            (min.0 + offset.x, min.1 + offset.y, min.2 + offset.z),
+            half,
+        )
+    });
+    Node::branch(children, half * CHUNK_SIZE)
+}
+
+fn octant_offset(octant: usize) -> IVec3 {
+    ivec3(
+        (octant & 1) as i32,
+        ((octant >> 1) & 1) as i32,
+        (octant >> 2) as i32,
+    )
+}
+
+// from coarse voxels back to the ones they stand for; the center of a coarse
+// voxel is the middle of its cube
+fn scale_vertices(mut vertices: Vec<VertexData>, lod: u32) -> Vec<VertexData> {
+    let side = (1 << lod) as f32;
+    for vertex in &mut vertices {
+        vertex.position = vertex.position * side + (side - 1.0) * 0.5;
+    }
+    vertices
+}
+
+struct Ray {
+    origin: DVec3,
+    direction: DVec3,
+    max_distance: f64,
+}
+
+impl Ray {
+    fn cast(&self, node: &Node, min: IVec3, size: i32) -> Option<RayHit> {
+        let (start, entry_axis) = self.clip(min, size)?;
+
+        match node {
+            Node::Empty => None,
+            Node::Solid(_) => {
+                // voxel of the node the ray enters it through
+                let entry = self.origin + self.direction * start;
+                let lo = min.as_dvec3();
+                let hi = (min + (size - 1)).as_dvec3();
+                let mut voxel = (entry + 0.5).floor().clamp(lo, hi).as_ivec3();
+                let mut normal = IVec3::ZERO;
+                if let Some(axis) = entry_axis {
+                    let step = self.direction[axis] > 0.0;
+                    voxel[axis] = if step {
+                        min[axis]
+                    } else {
+                        min[axis] + size - 1
+                    };
+                    normal[axis] = if step { -1 } else { 1 };
+                }
+
+                Some(RayHit {
+                    voxel: voxel.into(),
+                    normal: normal.into(),
+                    distance: start as f32,
+                })
+            }
+            Node::Branch(branch) => {
+                let half = size / 2;
+                let mut children: Vec<(f64, usize)> = (0..8)
+                    .filter_map(|octant| {
+                        let child_min = min + octant_offset(octant) * half;
+                        let (start, _) = self.clip(child_min, half)?;
+                        Some((start, octant))
+                    })
+                    .collect();
+                children.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                children.into_iter().find_map(|(_, octant)| {
+                    self.cast(
+                        &branch.children[octant],
+                        min + octant_offset(octant) * half,
+                        half,
+                    )
+                })
+            }
+        }
+    }
+
+    // where the ray enters the cube of voxels, if it does so before its max
+    // distance, with the axis it enters through when it starts outside
+    fn clip(&self, min: IVec3, size: i32) -> Option<(f64, Option<usize>)> {
+        let lo = min.as_dvec3() - 0.5;
+        let hi = lo + size as f64;
+        let mut start = 0.0;
+        let mut end = self.max_distance;
+        let mut entry_axis = None;
+        for axis in 0..3 {
+            if self.direction[axis] == 0.0 {
+                if self.origin[axis] < lo[axis] || self.origin[axis] > hi[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let near = (lo[axis] - self.origin[axis]) / self.direction[axis];
+            let far = (hi[axis] - self.origin[axis]) / self.direction[axis];
+            let (near, far) = (near.min(far), near.max(far));
+            if near > start {
+                start = near;
+                entry_axis = Some(axis);
+            }
+            end = end.min(far);
+        }
+
+        (start <= end).then_some((start, entry_axis))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    fn scene() -> VoxelGrid {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_sphere((10, 3, -2), 17.5, 1);
+        voxel_grid.paint_cube((50, -40, -6), (75, -2, 4), 2);
+        voxel_grid.paint_cube((-150, 0, 200), (-130, 9, 210), 3);
+        voxel_grid.paint_cube((-64, -64, -64), (-1, -1, -1), 4);
+        voxel_grid
+    }
+
+    // solid voxels of the octree, and their materials
+    fn octree_voxels(octree: &Octree, voxel_grid: &VoxelGrid) -> BTreeMap<Coords, Material> {
+        let (min, max) = voxel_grid.bounding_box().unwrap();
+        let mut voxels = BTreeMap::new();
+        for z in min.2 - 2..=max.2 + 2 {
+            for y in min.1 - 2..=max.1 + 2 {
+                for x in min.0 - 2..=max.0 + 2 {
+                    if let Some(material) = octree.read((x, y, z)) {
+                        voxels.insert((x, y, z), material);
+                    }
+                }
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn octrees_hold_the_voxels_of_their_grid() {
+        let voxel_grid = scene();
+        let octree = voxel_grid.octree();
+        assert_eq!(octree_voxels(&octree, &voxel_grid), solid(&voxel_grid));
+        assert_eq!(octree.level(0).bounding_box(), voxel_grid.bounding_box());
+
+        // a whole chunk of a single material is a single node
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (127, 127, 127), 3);
+        let octree = voxel_grid.octree();
+        assert_eq!(octree.bounds(), ((0, 0, 0), 128));
+        assert_eq!(octree.node_count(), 1);
+    }
+
+    #[test]
+    fn coarse_voxels_are_solid_when_half_of_their_cube_is() {
+        let voxel_grid = scene();
+        let octree = voxel_grid.octree();
+        let (min, max) = voxel_grid.bounding_box().unwrap();
+        for lod in 1..=2 {
+            let side = 1 << lod;
+            let level = octree.level(lod);
+            for z in (min.2 >> lod) - 1..=(max.2 >> lod) + 1 {
+                for y in (min.1 >> lod) - 1..=(max.1 >> lod) + 1 {
+                    for x in (min.0 >> lod) - 1..=(max.0 >> lod) + 1 {
+                        let mut count = 0;
+                        for dz in 0..side {
+                            for dy in 0..side {
+                                for dx in 0..side {
+                                    let coords = (x * side + dx, y * side + dy, z * side + dz);
+                                    count += voxel_grid.read(coords);
+                                }
+                            }
+                        }
+                        let expected = (count * 2 >= (side * side * side) as u64) as u64;
+                        assert_eq!(level.read((x, y, z)), expected, "{} {:?}", lod, (x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn updates_match_a_fresh_build() {
+        let mut voxel_grid = scene();
+        let mut octree = voxel_grid.octree();
+
+        let edits: [&dyn Fn(&mut VoxelGrid); 4] = [
+            &|voxel_grid| voxel_grid.paint_sphere((60, -10, 0), 9.0, 5),
+            &|voxel_grid| voxel_grid.invert((-64, -64, -64), (-1, -1, -1), 0),
+            &|voxel_grid| voxel_grid.paint_cube((-200, -10, 0), (-190, 10, 5), 6),
+            &|voxel_grid| voxel_grid.paint_cube((-64, -64, -64), (-1, -1, -1), 4),
+        ];
+        for edit in edits {
+            let revision = voxel_grid.revision();
+            edit(&mut voxel_grid);
+            let changed: Vec<ChunkCoords> = voxel_grid.changed_chunks(revision).collect();
+            octree.update(&voxel_grid, &changed);
+
+            let fresh = voxel_grid.octree();
+            assert_eq!(octree_voxels(&octree, &voxel_grid), solid(&voxel_grid));
+            assert_eq!(
+                octree.level(2).bounding_box(),
+                fresh.level(2).bounding_box()
+            );
+            if octree.bounds() == fresh.bounds() {
+                assert_eq!(octree.node_count(), fresh.node_count());
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use glam::*;
+
+use crate::voxels::Material;
+
+#[derive(Clone)]
+pub struct Palette {
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let colors = vec![
+            [179, 179, 179],
+            [242, 242, 242],
+            [77, 77, 77],
+            [204, 64, 64],
+            [230, 140, 51],
+            [235, 204, 77],
+            [115, 179, 64],
+            [51, 128, 77],
+            [64, 166, 191],
+            [64, 102, 204],
+            [128, 77, 179],
+            [204, 102, 166],
+            [140, 97, 64],
+            [191, 153, 115],
+            [97, 115, 64],
+            [38, 38, 51],
+        ];
+
+        Self { colors }
+    }
+}
+
+impl Palette {
+    // the surface format is not srgb, so colors reach the shaders unconverted
+    pub fn color(&self, material: Material) -> Vec3 {
+        let [r, g, b] = self
+            .colors
+            .get(material as usize)
+            .copied()
+            .unwrap_or([255, 0, 255]);
+
+        Vec3::new(r as f32, g as f32, b as f32) / 255.0
+    }
+}
@@ -0,0 +1,222 @@
+use glam::*;
+
+use crate::voxels::chunk::split_coords;
+use crate::voxels::{Coords, VoxelGrid};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub voxel: Coords,
+
+    // points out of the face the ray entered the voxel through, so that the
+    // voxel on the other side is voxel + normal; zero when the ray starts
+    // inside the voxel
+    pub normal: Coords,
+
+    // along the ray, in units of its direction once normalized
+    pub distance: f32,
+}
+
+impl VoxelGrid {
+    // first solid voxel along the ray, visiting the voxels it crosses one
+    // after the other; voxels are unit cubes centered on their coordinates
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO || !origin.is_finite() {
+            return None;
+        }
+        let (origin, direction) = (origin.as_dvec3(), direction.as_dvec3());
+
+        // only the part of the ray within the grid is traversed
+        let (min, max) = self.bounding_box()?;
+        let min = IVec3::from(min).as_dvec3() - 0.5;
+        let max = IVec3::from(max).as_dvec3() + 0.5;
+        let mut start = 0.0;
+        let mut end = max_distance as f64;
+        let mut entry_axis = None;
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let near = (min[axis] - origin[axis]) / direction[axis];
+            let far = (max[axis] - origin[axis]) / direction[axis];
+            let (near, far) = (near.min(far), near.max(far));
+            if near > start {
+                start = near;
+                entry_axis = Some(axis);
+            }
+            end = end.min(far);
+        }
+        if start > end {
+            return None;
+        }
+
+        // voxel the ray enters the grid through, kept within the grid when
+        // the entry point lies on its boundary
+        let step = IVec3::from_array(direction.to_array().map(|d| {
+            if d > 0.0 {
+                1
+            } else if d < 0.0 {
+                -1
+            } else {
+                0
+            }
+        }));
+        let entry = origin + direction * start;
+        let mut voxel = (entry + 0.5).floor().clamp(min + 0.5, max - 0.5).as_ivec3();
+        let mut normal = IVec3::ZERO;
+        if let Some(axis) = entry_axis {
+            voxel[axis] = if step[axis] > 0 {
+                (min[axis] + 0.5) as i32
+            } else {
+                (max[axis] - 0.5) as i32
+            };
+            normal[axis] = -step[axis];
+        }
+
+        // distances along the ray to the next boundary on each axis, and
+        // between boundaries
+        let mut next = DVec3::splat(f64::INFINITY);
+        let mut delta = DVec3::splat(f64::INFINITY);
+        for axis in 0..3 {
+            if step[axis] != 0 {
+                let boundary = voxel[axis] as f64 + 0.5 * step[axis] as f64;
+                next[axis] = (boundary - origin[axis]) / direction[axis];
+                delta[axis] = 1.0 / direction[axis].abs();
+            }
+        }
+
+        let mut distance = start;
+        let mut chunk = None;
+        loop {
+            let (chunk_coords, local) = split_coords(voxel.into());
+            let current = match chunk {
+                Some((coords, current)) if coords == chunk_coords => current,
+                _ => {
+                    let current = self.chunks.get(&chunk_coords);
+                    chunk = Some((chunk_coords, current));
+                    current
+                }
+            };
+            if matches!(current, Some(current) if current.read(local) == 1) {
+                return Some(RayHit {
+                    voxel: voxel.into(),
+                    normal: normal.into(),
+                    distance: distance as f32,
+                });
+            }
+
+            let axis = if next.x <= next.y && next.x <= next.z {
+                0
+            } else if next.y <= next.z {
+                1
+            } else {
+                2
+            };
+            distance = next[axis];
+            if distance > end {
+                return None;
+            }
+            voxel[axis] += step[axis];
+            next[axis] += delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_hit_the_face_they_enter_through() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, 10, 0), (-60, 20, 5), 1);
+
+        let hit = voxel_grid.raycast(vec3(-100.0, 15.0, 2.0), Vec3::X, f32::INFINITY);
+        assert_eq!(
+            hit,
+            Some(RayHit {
+                voxel: (-70, 15, 2),
+                normal: (-1, 0, 0),
+                distance: 29.5,
+            })
+        );
+
+        let hit = voxel_grid.raycast(vec3(-65.0, 15.0, 50.0), -Vec3::Z, f32::INFINITY);
+        assert_eq!(
+            hit,
+            Some(RayHit {
+                voxel: (-65, 15, 5),
+                normal: (0, 0, 1),
+                distance: 44.5,
+            })
+        );
+
+        // from within a voxel, nothing is crossed
+        let hit = voxel_grid.raycast(vec3(-65.0, 15.0, 2.0), Vec3::Y, f32::INFINITY);
+        assert_eq!(
+            hit,
+            Some(RayHit {
+                voxel: (-65, 15, 2),
+                normal: (0, 0, 0),
+                distance: 0.0,
+            })
+        );
+    }
+
+    #[test]
+    fn rays_stop_at_their_max_distance() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (3, 3, 3), 1);
+
+        let origin = vec3(-10.0, 1.0, 1.0);
+        assert_eq!(voxel_grid.raycast(origin, Vec3::X, 9.0), None);
+        assert!(voxel_grid.raycast(origin, Vec3::X, 9.6).is_some());
+        assert_eq!(voxel_grid.raycast(origin, -Vec3::X, f32::INFINITY), None);
+        assert_eq!(voxel_grid.raycast(origin, Vec3::Y, f32::INFINITY), None);
+        assert_eq!(voxel_grid.raycast(origin, Vec3::ZERO, f32::INFINITY), None);
+        assert_eq!(
+            VoxelGrid::new().raycast(origin, Vec3::X, f32::INFINITY),
+            None
+        );
+    }
+
+    #[test]
+    fn rays_find_the_first_voxel_they_cross() {
+        let mut voxel_grid = VoxelGrid::new();
+        for i in 0..40 {
+            let coords = ((i * 37) % 90 - 45, (i * 53) % 70 - 35, (i * 29) % 80 - 40);
+            voxel_grid.paint_cube(coords, coords, 1);
+        }
+        voxel_grid.paint_sphere((5, -3, 8), 12.0, 1);
+
+        for i in 0..200 {
+            let angle = i as f32 * 0.61;
+            let direction = vec3(angle.cos(), (angle * 1.7).sin(), (angle * 0.3).cos() - 0.4);
+            let origin = vec3(0.8, -0.5, 3.9) - direction.normalize() * 30.0;
+            let hit = voxel_grid.raycast(origin, direction, 100.0);
+
+            // closest solid voxel whose cube the ray goes through, found by
+            // marching in small steps
+            let direction = direction.normalize();
+            let expected = (0..100_000)
+                .map(|step| origin + direction * (step as f32 * 0.001))
+                .map(|point| (point + 0.5).floor().as_ivec3())
+                .find(|voxel| voxel_grid.read((*voxel).into()) == 1)
+                .map(Coords::from);
+            assert_eq!(hit.map(|hit| hit.voxel), expected, "{}", direction);
+
+            if let Some(hit) = hit {
+                let entry = origin + direction * hit.distance;
+                let center = IVec3::from(hit.voxel).as_vec3();
+                assert!((entry - center).abs().max_element() <= 0.5 + 1e-3);
+                let outside = IVec3::from(hit.voxel) + IVec3::from(hit.normal);
+                assert_eq!(voxel_grid.read(outside.into()), 0);
+            }
+        }
+    }
+}
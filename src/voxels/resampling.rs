@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use glam::*;
+use rayon::prelude::*;
+
+use crate::voxels::chunk::{split_coords, Chunk, Voxel, CHUNK_SIZE};
+use crate::voxels::{chunk_origin, ChunkCoords, Coords, Material, VoxelGrid, WORLD_LIMIT};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resampling {
+    // each voxel copies the one it comes from
+    Nearest,
+
+    // each voxel takes the state of most of the space it comes from, which
+    // keeps thin parts from breaking up when shrinking
+    Majority,
+}
+
+// scales smaller than this would collapse the whole grid into a few voxels
+const MIN_SCALE: f32 = 0.01;
+
+impl VoxelGrid {
+    // rotates and scales the grid around the pivot, by looking up where each
+    // voxel of the result comes from; returns how many solid voxels were
+    // dropped for leaving the world
+    pub fn resample(
+        &mut self,
+        rotation: Quat,
+        scale: Vec3,
+        pivot: Vec3,
+        resampling: Resampling,
+    ) -> usize {
+        let (min, max) = match self.bounding_box() {
+            Some(bounding_box) => bounding_box,
+            None => return 0,
+        };
+
+        let scale = scale.signum() * scale.abs().max(Vec3::splat(MIN_SCALE));
+        let transform = Affine3A::from_translation(pivot)
+            * Affine3A::from_quat(rotation)
+            * Affine3A::from_scale(scale)
+            * Affine3A::from_translation(-pivot);
+        let inverse = transform.inverse();
+
+        // distances shrink and grow with the grid, by its smallest scale
+        // when it is not the same on all axes
+        let distance_factor = scale.abs().min_element();
+
+        let world_min = Vec3::splat(-WORLD_LIMIT as f32);
+        let world_max = Vec3::splat((WORLD_LIMIT - 1) as f32);
+
+        let mut clipped = 0;
+        for (chunk_coords, chunk) in &self.chunks {
+            let origin = to_vec3(chunk_origin(*chunk_coords));
+            for local in chunk.stored_voxels() {
+                if chunk.get(local).solid {
+                    let position = transform.transform_point3(
+                        origin + vec3(local.0 as f32, local.1 as f32, local.2 as f32),
+                    );
+                    let position = position.round();
+                    if position.cmplt(world_min).any() || position.cmpgt(world_max).any() {
+                        clipped += 1;
+                    }
+                }
+            }
+        }
+
+        // the box of the result, from the outer corners of the voxels
+        let (source_min, source_max) = (to_vec3(min) - 0.5, to_vec3(max) + 0.5);
+        let (target_min, target_max) = transformed_box(&transform, source_min, source_max);
+        let target_min = target_min.floor().max(world_min).as_ivec3();
+        let target_max = target_max.ceil().min(world_max).as_ivec3();
+        if target_min.cmpgt(target_max).any() {
+            self.replace_chunks(BTreeMap::new());
+            return clipped;
+        }
+
+        // only the chunks of the result around where the stored chunks land;
+        // majority votes reach a third of a voxel further on each axis
+        let mut target_chunks: BTreeSet<ChunkCoords> = BTreeSet::new();
+        for chunk_coords in self.chunks.keys() {
+            let origin = to_vec3(chunk_origin(*chunk_coords));
+            let (lo, hi) =
+                transformed_box(&transform, origin - 0.5, origin + (CHUNK_SIZE as f32 - 0.5));
+            let lo = (lo - 1.0).floor().as_ivec3().max(target_min);
+            let hi = (hi + 1.0).ceil().as_ivec3().min(target_max);
+            if lo.cmpgt(hi).any() {
+                continue;
+            }
+            let (lo, _) = split_coords(lo.into());
+            let (hi, _) = split_coords(hi.into());
+            for z in lo.2..=hi.2 {
+                for y in lo.1..=hi.1 {
+                    for x in lo.0..=hi.0 {
+                        target_chunks.insert((x, y, z));
+                    }
+                }
+            }
+        }
+        let target_chunks: Vec<ChunkCoords> = target_chunks.into_iter().collect();
+
+        let chunks: BTreeMap<ChunkCoords, Arc<Chunk>> = target_chunks
+            .par_iter()
+            .filter_map(|chunk_coords| {
+                let origin = IVec3::from(chunk_origin(*chunk_coords));
+                let lo = (target_min - origin).max(IVec3::ZERO);
+                let hi = (target_max - origin).min(IVec3::splat(CHUNK_SIZE - 1));
+
+                let mut chunk = Chunk::new();
+                for z in lo.z..=hi.z {
+                    for y in lo.y..=hi.y {
+                        for x in lo.x..=hi.x {
+                            let position = (origin + ivec3(x, y, z)).as_vec3();
+                            let voxel = match resampling {
+                                Resampling::Nearest => {
+                                    self.sample(inverse.transform_point3(position))
+                                }
+                                Resampling::Majority => self.sample_majority(&inverse, position),
+                            };
+                            if voxel.is_stored() {
+                                chunk.set(
+                                    (x as usize, y as usize, z as usize),
+                                    voxel.scaled(distance_factor),
+                                );
+                            }
+                        }
+                    }
+                }
+
+                (!chunk.is_empty()).then(|| (*chunk_coords, Arc::new(chunk)))
+            })
+            .collect();
+
+        self.replace_chunks(chunks);
+        clipped
+    }
+
+    // halfway positions always go up, the same on both sides of the origin
+    fn sample(&self, position: Vec3) -> Voxel {
+        self.voxel((position + 0.5).floor().as_ivec3().into())
+    }
+
+    // votes over 3x3x3 points spread across the voxel, which never ties
+    fn sample_majority(&self, inverse: &Affine3A, position: Vec3) -> Voxel {
+        let mut solid_count = 0;
+        let mut materials: Vec<(Material, usize)> = vec![];
+        for k in -1..=1 {
+            for j in -1..=1 {
+                for i in -1..=1 {
+                    let offset = vec3(i as f32, j as f32, k as f32) / 3.0;
+                    let voxel = self.sample(inverse.transform_point3(position + offset));
+                    if voxel.solid {
+                        solid_count += 1;
+                        match materials.iter_mut().find(|(m, _)| *m == voxel.material) {
+                            Some((_, count)) => *count += 1,
+                            None => materials.push((voxel.material, 1)),
+                        }
+                    }
+                }
+            }
+        }
+
+        let solid = solid_count > 13;
+        let material = materials
+            .iter()
+            .max_by_key(|(material, count)| (*count, std::cmp::Reverse(*material)))
+            .map_or(0, |(material, _)| *material);
+
+        // the distance of the voxel at the center is only kept if it agrees
+        let center = self.sample(inverse.transform_point3(position));
+        if center.solid == solid {
+            let mut voxel = center;
+            voxel.material = material;
+            voxel
+        } else {
+            Voxel::hard(solid, material)
+        }
+    }
+}
+
+fn to_vec3(coords: Coords) -> Vec3 {
+    vec3(coords.0 as f32, coords.1 as f32, coords.2 as f32)
+}
+
+// bounds of a transformed axis-aligned box
+fn transformed_box(transform: &Affine3A, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    (0..8)
+        .map(|corner| {
+            transform.transform_point3(vec3(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            ))
+        })
+        .fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(lo, hi), corner| (lo.min(corner), hi.max(corner)),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+    use crate::voxels::Axis;
+
+    fn scene() -> VoxelGrid {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-70, -5, 1), (20, 19, 40), 1);
+        voxel_grid.paint_sphere((0, 0, 20), 9.5, 2);
+        voxel_grid.paint_cube((3, 3, 60), (3, 3, 60), 3);
+        voxel_grid
+    }
+
+    #[test]
+    fn identity_keeps_every_voxel() {
+        let voxel_grid = scene();
+        for resampling in [Resampling::Nearest, Resampling::Majority] {
+            let mut resampled = voxel_grid.clone();
+            let pivot = vec3(1.5, -2.0, 7.25);
+            let clipped = resampled.resample(Quat::IDENTITY, Vec3::ONE, pivot, resampling);
+            assert_eq!(clipped, 0);
+            assert_eq!(solid(&resampled), solid(&voxel_grid), "{:?}", resampling);
+        }
+    }
+
+    #[test]
+    fn quarter_turns_match_rotations() {
+        let voxel_grid = scene();
+        let pivot = vec3(1.0, 2.0, 3.0);
+        let mut rotated = voxel_grid.clone();
+        rotated.rotate(Axis::Z, 1, pivot);
+        for resampling in [Resampling::Nearest, Resampling::Majority] {
+            let mut resampled = voxel_grid.clone();
+            let rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+            resampled.resample(rotation, Vec3::ONE, pivot, resampling);
+            assert_eq!(solid(&resampled), solid(&rotated), "{:?}", resampling);
+        }
+    }
+
+    #[test]
+    fn scaling_up_grows_the_volume() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (9, 9, 9), 1);
+        voxel_grid.resample(
+            Quat::IDENTITY,
+            Vec3::splat(2.0),
+            vec3(-0.5, -0.5, -0.5),
+            Resampling::Nearest,
+        );
+        assert_eq!(solid(&voxel_grid).len(), 8000);
+        assert_eq!(voxel_grid.bounding_box(), Some(((0, 0, 0), (19, 19, 19))));
+    }
+
+    #[test]
+    fn far_apart_voxels_only_resample_their_own_chunks() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((0, 0, 0), (3, 3, 3), 1);
+        voxel_grid.paint_cube((20000, 0, 0), (20003, 3, 3), 2);
+        let pivot = vec3(10000.0, 0.0, 0.0);
+        let mut rotated = voxel_grid.clone();
+        rotated.rotate(Axis::Z, 1, pivot);
+        let rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        voxel_grid.resample(rotation, Vec3::ONE, pivot, Resampling::Majority);
+        assert_eq!(solid(&voxel_grid), solid(&rotated));
+
+        // opposite corners of the world
+        let mut voxel_grid = VoxelGrid::new();
+        let (lo, hi) = (-WORLD_LIMIT, WORLD_LIMIT - 1);
+        voxel_grid.paint_cube((lo, lo, lo), (lo + 2, lo + 2, lo + 2), 1);
+        voxel_grid.paint_cube((hi - 2, hi - 2, hi - 2), (hi, hi, hi), 2);
+        let expected = solid(&voxel_grid);
+        let clipped =
+            voxel_grid.resample(Quat::IDENTITY, Vec3::ONE, Vec3::ZERO, Resampling::Nearest);
+        assert_eq!(clipped, 0);
+        assert_eq!(solid(&voxel_grid), expected);
+        assert_eq!(voxel_grid.chunk_coords().count(), 2);
+    }
+}
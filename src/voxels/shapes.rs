@@ -0,0 +1,324 @@
+use std::sync::Arc;
+
+use glam::*;
+use rayon::prelude::*;
+
+use crate::voxels::chunk::{split_coords, Chunk, Voxel};
+use crate::voxels::{chunk_origin, clamp_to_world, ChunkCoords, Material, VoxelGrid, CHUNK_SIZE};
+
+// solid that voxels are painted or erased with; voxels are inside when their
+// center is
+pub trait Shape: Sync {
+    // corners of a box around the whole shape
+    fn bounding_box(&self) -> (Vec3, Vec3);
+
+    fn contains(&self, point: Vec3) -> bool;
+}
+
+// centered on the middle of its axis; like the other oriented shapes, it is
+// set up along its own z axis, then turned by its rotation around its center
+pub struct Cylinder {
+    pub center: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub rotation: Quat,
+}
+
+// centered on the middle of its base, with its tip up the z axis
+pub struct Cone {
+    pub center: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub rotation: Quat,
+}
+
+// ring around its z axis
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub rotation: Quat,
+}
+
+// all points within the radius of the segment between start and end
+pub struct Capsule {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+}
+
+pub struct Ellipsoid {
+    pub center: Vec3,
+    pub radii: Vec3,
+    pub rotation: Quat,
+}
+
+pub struct OrientedBox {
+    pub center: Vec3,
+    pub half_size: Vec3,
+    pub rotation: Quat,
+}
+
+impl Shape for Cylinder {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let half_size = vec3(self.radius, self.radius, self.height * 0.5);
+        oriented_bounds(self.center, self.rotation, half_size)
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let local = to_local(point, self.center, self.rotation);
+        local.truncate().length_squared() <= self.radius * self.radius
+            && local.z.abs() <= self.height * 0.5
+    }
+}
+
+impl Shape for Cone {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let middle = self.center + self.rotation * vec3(0.0, 0.0, self.height * 0.5);
+        let half_size = vec3(self.radius, self.radius, self.height * 0.5);
+        oriented_bounds(middle, self.rotation, half_size)
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let local = to_local(point, self.center, self.rotation);
+        if local.z < 0.0 || local.z > self.height {
+            return false;
+        }
+        let radius = self.radius * (1.0 - local.z / self.height);
+        local.truncate().length_squared() <= radius * radius
+    }
+}
+
+impl Shape for Torus {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let outer = self.major_radius + self.minor_radius;
+        let half_size = vec3(outer, outer, self.minor_radius);
+        oriented_bounds(self.center, self.rotation, half_size)
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let local = to_local(point, self.center, self.rotation);
+        let ring = vec2(local.truncate().length() - self.major_radius, local.z);
+        ring.length_squared() <= self.minor_radius * self.minor_radius
+    }
+}
+
+impl Shape for Capsule {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        (
+            self.start.min(self.end) - self.radius,
+            self.start.max(self.end) + self.radius,
+        )
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let axis = self.end - self.start;
+        let along = match axis.length_squared() {
+            length_squared if length_squared > 0.0 => {
+                ((point - self.start).dot(axis) / length_squared).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        };
+        let closest = self.start + axis * along;
+        point.distance_squared(closest) <= self.radius * self.radius
+    }
+}
+
+impl Shape for Ellipsoid {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        oriented_bounds(self.center, self.rotation, self.radii)
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let local = to_local(point, self.center, self.rotation);
+        if self.radii.cmple(Vec3::ZERO).any() {
+            return false;
+        }
+        (local / self.radii).length_squared() <= 1.0
+    }
+}
+
+impl Shape for OrientedBox {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        oriented_bounds(self.center, self.rotation, self.half_size)
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let local = to_local(point, self.center, self.rotation);
+        local.abs().cmple(self.half_size).all()
+    }
+}
+
+impl VoxelGrid {
+    pub fn paint_shape(&mut self, shape: &dyn Shape, material: Material) {
+        self.rasterize(shape, Voxel::hard(true, material));
+    }
+
+    pub fn erase_shape(&mut self, shape: &dyn Shape) {
+        self.rasterize(shape, Voxel::hard(false, 0));
+    }
+
+    // sets the voxels inside the shape, filling the chunks it covers in
+    // parallel
+    fn rasterize(&mut self, shape: &dyn Shape, voxel: Voxel) {
+        let (min, max) = shape.bounding_box();
+        if !min.is_finite() || !max.is_finite() {
+            return;
+        }
+        let min = min.ceil().max(Vec3::splat(i32::MIN as f32)).as_ivec3();
+        let max = max.floor().min(Vec3::splat(i32::MAX as f32)).as_ivec3();
+        let min = IVec3::from(clamp_to_world(min.into()));
+        let max = IVec3::from(clamp_to_world(max.into()));
+        if min.cmpgt(max).any() {
+            return;
+        }
+
+        let (chunk_min, _) = split_coords(min.into());
+        let (chunk_max, _) = split_coords(max.into());
+        let mut chunk_coords: Vec<ChunkCoords> = vec![];
+        for z in chunk_min.2..=chunk_max.2 {
+            for y in chunk_min.1..=chunk_max.1 {
+                for x in chunk_min.0..=chunk_max.0 {
+                    // erasing never needs to create chunks
+                    if voxel.solid || self.chunks.contains_key(&(x, y, z)) {
+                        chunk_coords.push((x, y, z));
+                    }
+                }
+            }
+        }
+
+        let chunks: Vec<(ChunkCoords, Chunk)> = chunk_coords
+            .par_iter()
+            .filter_map(|chunk_coords| {
+                let origin = IVec3::from(chunk_origin(*chunk_coords));
+                let lo = (min - origin).max(IVec3::ZERO);
+                let hi = (max - origin).min(IVec3::splat(CHUNK_SIZE - 1));
+
+                let mut chunk = match self.chunks.get(chunk_coords) {
+                    Some(chunk) => Chunk::clone(chunk),
+                    None => Chunk::new(),
+                };
+                let mut changed = false;
+                for z in lo.z..=hi.z {
+                    for y in lo.y..=hi.y {
+                        for x in lo.x..=hi.x {
+                            if shape.contains((origin + ivec3(x, y, z)).as_vec3()) {
+                                chunk.set((x as usize, y as usize, z as usize), voxel);
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+
+                changed.then_some((*chunk_coords, chunk))
+            })
+            .collect();
+
+        self.begin_edit();
+        for (chunk_coords, chunk) in chunks {
+            self.chunk_revisions.insert(chunk_coords, self.revision);
+            if chunk.is_empty() {
+                self.chunks.remove(&chunk_coords);
+            } else {
+                self.chunks.insert(chunk_coords, Arc::new(chunk));
+            }
+        }
+    }
+}
+
+fn to_local(point: Vec3, center: Vec3, rotation: Quat) -> Vec3 {
+    rotation.inverse() * (point - center)
+}
+
+// bounds of a box turned around its center
+fn oriented_bounds(center: Vec3, rotation: Quat, half_size: Vec3) -> (Vec3, Vec3) {
+    let axes = Mat3::from_quat(rotation);
+    let extent = axes.x_axis.abs() * half_size.x
+        + axes.y_axis.abs() * half_size.y
+        + axes.z_axis.abs() * half_size.z;
+    (center - extent, center + extent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    fn shapes() -> Vec<Box<dyn Shape>> {
+        let rotation = Quat::from_euler(EulerRot::ZYX, 0.4, -0.7, 1.1);
+        let center = vec3(-2.5, 0.0, 1.3);
+        vec![
+            Box::new(Cylinder {
+                center,
+                radius: 7.5,
+                height: 12.0,
+                rotation,
+            }),
+            Box::new(Cone {
+                center,
+                radius: 6.0,
+                height: 14.0,
+                rotation,
+            }),
+            Box::new(Torus {
+                center,
+                major_radius: 8.0,
+                minor_radius: 3.2,
+                rotation,
+            }),
+            Box::new(Capsule {
+                start: vec3(-9.0, 4.0, -3.0),
+                end: vec3(6.0, -5.5, 8.0),
+                radius: 3.5,
+            }),
+            Box::new(Ellipsoid {
+                center,
+                radii: vec3(9.0, 4.0, 6.5),
+                rotation,
+            }),
+            Box::new(OrientedBox {
+                center,
+                half_size: vec3(8.0, 3.0, 5.5),
+                rotation,
+            }),
+        ]
+    }
+
+    #[test]
+    fn voxels_are_painted_where_their_center_is_inside() {
+        for shape in shapes() {
+            let mut voxel_grid = VoxelGrid::new();
+            voxel_grid.paint_shape(shape.as_ref(), 2);
+            let voxels = solid(&voxel_grid);
+            assert!(!voxels.is_empty());
+
+            // also checks that the bounding box holds the whole shape, as
+            // nothing is painted outside of it
+            for z in -20..=20 {
+                for y in -20..=20 {
+                    for x in -20..=20 {
+                        let inside = shape.contains(vec3(x as f32, y as f32, z as f32));
+                        assert_eq!(voxels.contains_key(&(x, y, z)), inside);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn erasing_never_creates_chunks() {
+        for shape in shapes() {
+            let mut voxel_grid = VoxelGrid::new();
+            voxel_grid.paint_cube((-30, -30, -30), (-1, -1, -1), 1);
+            let mut expected = solid(&voxel_grid);
+            expected.retain(|(x, y, z), _| !shape.contains(vec3(*x as f32, *y as f32, *z as f32)));
+
+            voxel_grid.erase_shape(shape.as_ref());
+            assert_eq!(solid(&voxel_grid), expected);
+            assert_eq!(
+                voxel_grid.chunk_coords().collect::<Vec<_>>(),
+                vec![(-1, -1, -1)]
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::voxels::bit_volume::BitGrid;
+use crate::voxels::{
+    chunk_origin, clamp_to_world, ChunkCoords, Coords, Material, VoxelGrid, CHUNK_SIZE, WORLD_LIMIT,
+};
+
+// cube of voxels around each voxel that votes on its state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterSize {
+    Three,
+    Five,
+}
+
+impl FilterSize {
+    fn reach(&self) -> usize {
+        match self {
+            FilterSize::Three => 1,
+            FilterSize::Five => 2,
+        }
+    }
+}
+
+impl VoxelGrid {
+    // each voxel takes the state of most voxels around it, once per
+    // iteration, which removes spikes and pits; only voxels inside the
+    // region change when one is given. the filter runs chunk by chunk, on
+    // the chunks within reach of solid voxels
+    pub fn smooth(&mut self, size: FilterSize, iterations: u32, region: Option<(Coords, Coords)>) {
+        let reach = size.reach();
+        let (min, max) = match region {
+            Some((min, max)) => (clamp_to_world(min), clamp_to_world(max)),
+            None => (
+                (-WORLD_LIMIT, -WORLD_LIMIT, -WORLD_LIMIT),
+                (WORLD_LIMIT - 1, WORLD_LIMIT - 1, WORLD_LIMIT - 1),
+            ),
+        };
+        if min.0 > max.0 || min.1 > max.1 || min.2 > max.2 {
+            return;
+        }
+
+        // chunks with voxels inside the region
+        let overlaps = |chunk_coords: &ChunkCoords| {
+            let origin = chunk_origin(*chunk_coords);
+            let last = CHUNK_SIZE - 1;
+            origin.0 <= max.0
+                && origin.1 <= max.1
+                && origin.2 <= max.2
+                && origin.0 + last >= min.0
+                && origin.1 + last >= min.1
+                && origin.2 + last >= min.2
+        };
+
+        // the region within a volume, in its local coordinates
+        let local_region = |volume_min: Coords, size: (usize, usize, usize)| {
+            let lo = |min: i32, volume_min: i32| (min as i64 - volume_min as i64).max(0) as usize;
+            let hi = |max: i32, volume_min: i32, size: usize| {
+                (max as i64 - volume_min as i64).min(size as i64 - 1) as usize
+            };
+            (
+                (
+                    lo(min.0, volume_min.0),
+                    lo(min.1, volume_min.1),
+                    lo(min.2, volume_min.2),
+                ),
+                (
+                    hi(max.0, volume_min.0, size.0),
+                    hi(max.1, volume_min.1, size.1),
+                    hi(max.2, volume_min.2, size.2),
+                ),
+            )
+        };
+
+        let offsets: Vec<Coords> = {
+            let reach = reach as i32;
+            (-reach..=reach)
+                .flat_map(|z| {
+                    (-reach..=reach).flat_map(move |y| (-reach..=reach).map(move |x| (x, y, z)))
+                })
+                .collect()
+        };
+
+        // materials of the voxels filled so far, by vote of the solid voxels
+        // around them
+        let mut materials: HashMap<Coords, Material> = HashMap::new();
+
+        let original = BitGrid::from_grid(self);
+        let mut grid = original.clone();
+        for _ in 0..iterations {
+            let targets: BTreeSet<ChunkCoords> = grid
+                .chunks_around(reach)
+                .into_iter()
+                .filter(overlaps)
+                .collect();
+            let next = grid.map(&targets, reach, |volume| {
+                let mut next = volume.majority(reach);
+                let (lo, hi) = local_region(volume.min, volume.size);
+                next.restore_outside(volume, lo, hi);
+                next
+            });
+
+            let differences = grid.differences(&next);
+            if differences.is_empty() {
+                break;
+            }
+
+            let mut votes: Vec<(Material, usize)> = vec![];
+            for coords in differences {
+                if grid.get(coords) {
+                    continue;
+                }
+
+                votes.clear();
+                for source in offsets
+                    .iter()
+                    .map(|offset| {
+                        (
+                            coords.0 + offset.0,
+                            coords.1 + offset.1,
+                            coords.2 + offset.2,
+                        )
+                    })
+                    .filter(|source| grid.get(*source))
+                {
+                    let material = match materials.get(&source) {
+                        Some(material) => *material,
+                        None => self.voxel(source).material,
+                    };
+                    match votes.iter_mut().find(|(m, _)| *m == material) {
+                        Some((_, count)) => *count += 1,
+                        None => votes.push((material, 1)),
+                    }
+                }
+                let material = votes
+                    .iter()
+                    .max_by_key(|(material, count)| (*count, std::cmp::Reverse(*material)))
+                    .map_or(0, |(material, _)| *material);
+                materials.insert(coords, material);
+            }
+
+            grid = next;
+        }
+
+        self.write_grid(&original, &grid, &materials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    // a box with a spike on top and a pit in its side
+    fn scene(voxel_grid: &mut VoxelGrid, min: Coords) {
+        let at = |x: i32, y: i32, z: i32| (min.0 + x, min.1 + y, min.2 + z);
+        voxel_grid.paint_cube(at(0, 0, 0), at(9, 9, 9), 1);
+        voxel_grid.paint_cube(at(5, 5, 10), at(5, 5, 12), 2);
+
+        let mut pit = VoxelGrid::new();
+        pit.paint_cube(at(0, 5, 5), at(0, 5, 5), 0);
+        voxel_grid.subtract(&pit);
+    }
+
+    #[test]
+    fn spikes_and_pits_are_smoothed_away() {
+        let mut voxel_grid = VoxelGrid::new();
+        scene(&mut voxel_grid, (-5, -5, -5));
+        scene(&mut voxel_grid, (20000, 20000, 2000));
+
+        voxel_grid.smooth(FilterSize::Three, 1, None);
+        let voxels = solid(&voxel_grid);
+        for min in [(-5, -5, -5), (20000, 20000, 2000)] {
+            let at = |x: i32, y: i32, z: i32| (min.0 + x, min.1 + y, min.2 + z);
+            assert!(!voxels.contains_key(&at(5, 5, 10)));
+            assert_eq!(voxels[&at(0, 5, 5)], 1);
+
+            // edges of the box lose their voxels, the center stays
+            assert!(!voxels.contains_key(&at(0, 0, 0)));
+            assert!(voxels.contains_key(&at(5, 5, 5)));
+        }
+    }
+
+    #[test]
+    fn only_the_region_changes() {
+        let mut voxel_grid = VoxelGrid::new();
+        scene(&mut voxel_grid, (60, 60, 60));
+        let before = solid(&voxel_grid);
+
+        // covers the spike only
+        voxel_grid.smooth(FilterSize::Five, 2, Some(((60, 60, 68), (75, 75, 80))));
+        let after = solid(&voxel_grid);
+        assert!(!after.contains_key(&(65, 65, 70)));
+        for (coords, material) in &before {
+            if coords.2 < 68 {
+                assert_eq!(after.get(coords), Some(material));
+            }
+        }
+    }
+
+    #[test]
+    fn filled_voxels_take_the_material_around_them() {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-64, 0, 0), (-55, 9, 9), 1);
+        voxel_grid.paint_cube((-54, 0, 0), (-45, 9, 9), 2);
+        let mut pit = VoxelGrid::new();
+        pit.paint_cube((-59, 5, 0), (-59, 5, 0), 0);
+        pit.paint_cube((-50, 5, 0), (-50, 5, 0), 0);
+        voxel_grid.subtract(&pit);
+
+        voxel_grid.smooth(FilterSize::Three, 1, None);
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels[&(-59, 5, 0)], 1);
+        assert_eq!(voxels[&(-50, 5, 0)], 2);
+    }
+}
@@ -0,0 +1,131 @@
+use glam::*;
+use rayon::prelude::*;
+
+use crate::voxels::{chunk_origin, Coords, Material, MeshingMode, Palette, VoxelGrid};
+
+// figures about a grid, in voxels, and in millimeters for a given size of
+// the voxels once printed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statistics {
+    pub voxels: usize,
+
+    // solid voxels of each material, leaving out the unused ones
+    pub materials: Vec<(Material, usize)>,
+
+    pub bounding_box: Option<(Coords, Coords)>,
+
+    // of the solid voxels, all weighing the same
+    pub center_of_mass: Option<Vec3>,
+
+    // in square millimeters, over the mesh of the surface
+    pub surface_area: f64,
+
+    // in cubic millimeters, enclosed by the mesh of the surface
+    pub volume: f64,
+}
+
+impl VoxelGrid {
+    // voxel_size is the length of the side of a voxel, in millimeters
+    pub fn statistics(&self, mode: MeshingMode, voxel_size: f32) -> Statistics {
+        let chunks: Vec<_> = self.chunks.iter().collect();
+        let (materials, sum) = chunks
+            .par_iter()
+            .map(|(chunk_coords, chunk)| {
+                let origin = IVec3::from(chunk_origin(**chunk_coords)).as_dvec3();
+                let mut materials = [0usize; 256];
+                let mut sum = DVec3::ZERO;
+                for z in 0..64 {
+                    for y in 0..64 {
+                        let mut bits = chunk.row(y, z);
+                        while bits != 0 {
+                            let x = bits.trailing_zeros() as usize;
+                            bits &= bits - 1;
+                            materials[chunk.read_material((x, y, z)) as usize] += 1;
+                            sum += origin + dvec3(x as f64, y as f64, z as f64);
+                        }
+                    }
+                }
+                (materials, sum)
+            })
+            .reduce(
+                || ([0usize; 256], DVec3::ZERO),
+                |(mut materials, sum), (other_materials, other_sum)| {
+                    for (count, other_count) in materials.iter_mut().zip(other_materials) {
+                        *count += other_count;
+                    }
+                    (materials, sum + other_sum)
+                },
+            );
+        let voxels: usize = materials.iter().sum();
+
+        // colors make no difference to the shape of the mesh
+        let (vertices, indices) = self.generate_mesh(mode, &Palette::default());
+        let mut area = 0.0;
+        let mut volume = 0.0;
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|index| vertices[index as usize].position.as_dvec3());
+            area += (b - a).cross(c - a).length() * 0.5;
+
+            // signed volume of the tetrahedron between the triangle and the
+            // origin; these add up to the volume inside a closed mesh
+            volume += a.dot(b.cross(c)) / 6.0;
+        }
+        let voxel_size = voxel_size as f64;
+
+        Statistics {
+            voxels,
+            materials: materials
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(material, count)| (material as Material, *count))
+                .collect(),
+            bounding_box: self.bounding_box(),
+            center_of_mass: (voxels > 0).then(|| (sum / voxels as f64).as_vec3()),
+            surface_area: area * voxel_size * voxel_size,
+            volume: volume * voxel_size * voxel_size * voxel_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_give_their_size_surface_and_volume() {
+        // two boxes in different chunks
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_cube((-10, 0, 0), (-1, 9, 9), 2);
+        voxel_grid.paint_cube((100, 0, 0), (109, 9, 4), 5);
+
+        for mode in [MeshingMode::Blocky, MeshingMode::Greedy] {
+            let statistics = voxel_grid.statistics(mode, 0.5);
+            assert_eq!(statistics.voxels, 1500);
+            assert_eq!(statistics.materials, vec![(2, 1000), (5, 500)]);
+            assert_eq!(statistics.bounding_box, Some(((-10, 0, 0), (109, 9, 9))));
+            let center = statistics.center_of_mass.unwrap();
+            let expected = (vec3(-5.5, 4.5, 4.5) * 1000.0 + vec3(104.5, 4.5, 2.0) * 500.0) / 1500.0;
+            assert!(center.distance(expected) < 1e-4, "{}", center);
+            assert!((statistics.surface_area - (600.0 + 400.0) * 0.25).abs() < 1e-6);
+            assert!((statistics.volume - 1500.0 * 0.125).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn empty_grids_have_no_figures() {
+        let statistics = VoxelGrid::new().statistics(MeshingMode::SurfaceNets, 1.0);
+        assert_eq!(
+            statistics,
+            Statistics {
+                voxels: 0,
+                materials: vec![],
+                bounding_box: None,
+                center_of_mass: None,
+                surface_area: 0.0,
+                volume: 0.0,
+            }
+        );
+    }
+}
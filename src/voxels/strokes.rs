@@ -0,0 +1,245 @@
+use glam::*;
+
+use crate::voxels::{clamp_to_world, Material, Shape, VoxelGrid, DISTANCE_BAND};
+
+// segments shorter than this are enough to follow curves within a fraction
+// of a voxel
+const CURVE_STEP: f32 = 2.0;
+const MAX_CURVE_SEGMENTS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrokePoint {
+    pub position: Vec3,
+    pub radius: f32,
+}
+
+// sphere swept along a path, its radius changing linearly between the points
+// of the path; each segment is the hull of the spheres at its ends
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke {
+    segments: Vec<(StrokePoint, StrokePoint)>,
+}
+
+impl Stroke {
+    pub fn polyline(points: &[StrokePoint]) -> Self {
+        let segments = match points {
+            [] => vec![],
+            [point] => vec![(*point, *point)],
+            _ => points.windows(2).map(|pair| (pair[0], pair[1])).collect(),
+        };
+
+        Self { segments }
+    }
+
+    // cubic curve from the first point to the last one, pulled towards the
+    // two others, which also set how the radius changes along it
+    pub fn bezier(control_points: [StrokePoint; 4]) -> Self {
+        let length: f32 = control_points
+            .windows(2)
+            .map(|pair| pair[0].position.distance(pair[1].position))
+            .sum();
+        let count = ((length / CURVE_STEP).ceil() as usize).clamp(1, MAX_CURVE_SEGMENTS);
+
+        let points: Vec<StrokePoint> = (0..=count)
+            .map(|i| {
+                let t = i as f32 / count as f32;
+                let s = 1.0 - t;
+                let weights = [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t];
+                control_points.iter().zip(weights).fold(
+                    StrokePoint {
+                        position: Vec3::ZERO,
+                        radius: 0.0,
+                    },
+                    |point, (control_point, weight)| StrokePoint {
+                        position: point.position + control_point.position * weight,
+                        radius: point.radius + control_point.radius * weight,
+                    },
+                )
+            })
+            .collect();
+
+        Self::polyline(&points)
+    }
+
+    // signed distance to the closest segment
+    pub fn distance(&self, point: Vec3) -> f32 {
+        self.segments
+            .iter()
+            .map(|(start, end)| segment_distance(point, start, end))
+            .fold(f32::MAX, f32::min)
+    }
+}
+
+impl Shape for Stroke {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        self.segments.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), (start, end)| {
+                let (segment_min, segment_max) = segment_bounds(start, end, 0.0);
+                (min.min(segment_min), max.max(segment_max))
+            },
+        )
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        self.distance(point) <= 0.0
+    }
+}
+
+impl VoxelGrid {
+    // writes the signed distance to the stroke rather than hard voxels, like
+    // smooth brushes do; segments are written one at a time, each over its
+    // own bounds, and chunks left with distances alone are dropped
+    pub fn paint_stroke(&mut self, stroke: &Stroke, material: Material) {
+        self.begin_edit();
+
+        for (start, end) in &stroke.segments {
+            let (min, max) = segment_bounds(start, end, DISTANCE_BAND);
+            if !min.is_finite() || !max.is_finite() {
+                continue;
+            }
+            let min = min.floor().max(Vec3::splat(i32::MIN as f32)).as_ivec3();
+            let max = max.ceil().min(Vec3::splat(i32::MAX as f32)).as_ivec3();
+            let min = clamp_to_world(min.into());
+            let max = clamp_to_world(max.into());
+
+            for z in min.2..=max.2 {
+                for y in min.1..=max.1 {
+                    for x in min.0..=max.0 {
+                        let distance =
+                            segment_distance(vec3(x as f32, y as f32, z as f32), start, end);
+                        if distance <= DISTANCE_BAND {
+                            self.union_distance((x, y, z), distance, material);
+                        }
+                    }
+                }
+            }
+
+            self.remove_empty_chunks(min, max);
+        }
+    }
+}
+
+fn segment_bounds(start: &StrokePoint, end: &StrokePoint, margin: f32) -> (Vec3, Vec3) {
+    let (start_radius, end_radius) = (start.radius.max(0.0), end.radius.max(0.0));
+    (
+        (start.position - start_radius).min(end.position - end_radius) - margin,
+        (start.position + start_radius).max(end.position + end_radius) + margin,
+    )
+}
+
+// distance to the hull of two spheres, following the round cone by Inigo
+// Quilez
+fn segment_distance(point: Vec3, start: &StrokePoint, end: &StrokePoint) -> f32 {
+    let (a, b) = (start.position, end.position);
+    let (start_radius, end_radius) = (start.radius.max(0.0), end.radius.max(0.0));
+
+    // a sphere within the other one adds nothing to it
+    let axis = b - a;
+    let length_squared = axis.length_squared();
+    let radius_change = start_radius - end_radius;
+    if length_squared <= radius_change * radius_change {
+        return if start_radius >= end_radius {
+            point.distance(a) - start_radius
+        } else {
+            point.distance(b) - end_radius
+        };
+    }
+
+    let cone = length_squared - radius_change * radius_change;
+    let to_point = point - a;
+    let along = to_point.dot(axis);
+    let past_end = along - length_squared;
+    let across_squared = (to_point * length_squared - axis * along).length_squared();
+    let along_squared = along * along * length_squared;
+    let past_end_squared = past_end * past_end * length_squared;
+    let slope = radius_change.signum() * radius_change * radius_change * across_squared;
+
+    if past_end.signum() * cone * past_end_squared > slope {
+        (across_squared + past_end_squared).sqrt() / length_squared - end_radius
+    } else if along.signum() * cone * along_squared < slope {
+        (across_squared + along_squared).sqrt() / length_squared - start_radius
+    } else {
+        ((across_squared * cone / length_squared).sqrt() + along * radius_change) / length_squared
+            - start_radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    fn point(x: f32, y: f32, z: f32, radius: f32) -> StrokePoint {
+        StrokePoint {
+            position: vec3(x, y, z),
+            radius,
+        }
+    }
+
+    #[test]
+    fn strokes_only_keep_chunks_with_solid_voxels() {
+        // the tube ends right before the next chunk along z, which only gets
+        // distances
+        let mut voxel_grid = VoxelGrid::new();
+        let stroke = Stroke::polyline(&[point(32.0, 32.0, 2.0, 1.5), point(32.0, 32.0, 62.0, 1.5)]);
+        voxel_grid.paint_stroke(&stroke, 1);
+        assert_eq!(voxel_grid.read((32, 32, 63)), 1);
+        assert_eq!(
+            voxel_grid.chunk_coords().collect::<Vec<_>>(),
+            vec![(0, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn polylines_paint_the_voxels_within_their_radius() {
+        let points = [
+            point(-20.0, 3.0, 0.5, 4.0),
+            point(10.0, -6.0, 2.0, 6.5),
+            point(30.0, 12.0, -9.0, 2.5),
+        ];
+        let stroke = Stroke::polyline(&points);
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_stroke(&stroke, 3);
+
+        let voxels = solid(&voxel_grid);
+        assert!(!voxels.is_empty());
+        for z in -20..=20 {
+            for y in -20..=20 {
+                for x in -30..=40 {
+                    let distance = stroke.distance(vec3(x as f32, y as f32, z as f32));
+                    // distances are stored with a limited precision
+                    if distance.abs() < 0.01 {
+                        continue;
+                    }
+                    let expected = (distance < 0.0).then_some(3);
+                    assert_eq!(
+                        voxels.get(&(x, y, z)).copied(),
+                        expected,
+                        "{:?} {}",
+                        (x, y, z),
+                        distance
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn curves_go_through_their_end_points() {
+        let stroke = Stroke::bezier([
+            point(-40.0, 0.0, 0.0, 2.0),
+            point(-10.0, 50.0, 0.0, 2.0),
+            point(10.0, -50.0, 10.0, 4.0),
+            point(40.0, 0.0, 10.0, 4.0),
+        ]);
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.paint_stroke(&stroke, 2);
+
+        let voxels = solid(&voxel_grid);
+        assert_eq!(voxels.get(&(-40, 0, 0)), Some(&2));
+        assert_eq!(voxels.get(&(40, 0, 10)), Some(&2));
+        assert_eq!(voxel_grid.read((-40, -4, 0)), 0);
+        assert_eq!(voxel_grid.read((40, 5, 10)), 0);
+    }
+}
@@ -0,0 +1,289 @@
+use glam::*;
+
+use crate::voxels::{Coords, Material, Shape, VoxelGrid};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerrainKind {
+    // ground up to a height varying over x and y
+    Heightfield,
+
+    // ground from a 3D noise fading with height, with overhangs and floating
+    // bits
+    Volume,
+}
+
+// ground generated from fractal noise (fBm, octaves of simplex noise each at
+// twice the frequency and half the amplitude of the previous one), within a
+// region of the world; the same parameters always give the same voxels
+#[derive(Clone, Debug, PartialEq)]
+pub struct Terrain {
+    pub kind: TerrainKind,
+    pub seed: u64,
+    pub octaves: u32,
+
+    // of the first octave, in cycles per voxel
+    pub frequency: f32,
+
+    // lowers the ground as it goes up; on average, the ground reaches the top
+    // of the region at -1, halfway up at 0, and its bottom at 1
+    pub threshold: f32,
+
+    // from 0, without caves, to 1, where about half the ground is carved out
+    pub cave_density: f32,
+
+    // min and max corners, inclusive
+    pub region: (Coords, Coords),
+}
+
+impl VoxelGrid {
+    // paints the ground of the terrain, keeping the voxels already there
+    pub fn generate_terrain(&mut self, terrain: &Terrain, material: Material) {
+        let shape = TerrainShape {
+            terrain,
+            ground: Noise::new(terrain.seed),
+
+            // caves must not line up with the ground
+            caves: Noise::new(terrain.seed ^ 0x9e37_79b9_7f4a_7c15),
+        };
+        self.paint_shape(&shape, material);
+    }
+}
+
+struct TerrainShape<'a> {
+    terrain: &'a Terrain,
+    ground: Noise,
+    caves: Noise,
+}
+
+impl<'a> Shape for TerrainShape<'a> {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let (min, max) = self.terrain.region;
+        (IVec3::from(min).as_vec3(), IVec3::from(max).as_vec3())
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        let terrain = self.terrain;
+        let point = point.as_dvec3();
+        let frequency = terrain.frequency as f64;
+        let octaves = terrain.octaves.max(1);
+
+        // the ground fades from 1 at the bottom of the region to -1 at its top
+        let (min, max) = terrain.region;
+        let height = (point.z - min.2 as f64) / (max.2 - min.2).max(1) as f64;
+        let ground = match terrain.kind {
+            TerrainKind::Heightfield => fbm(point, octaves, frequency, |point| {
+                self.ground.simplex2(point.truncate())
+            }),
+            TerrainKind::Volume => fbm(point, octaves, frequency, |point| {
+                self.ground.simplex3(point)
+            }),
+        };
+        if ground + 1.0 - 2.0 * height <= terrain.threshold as f64 {
+            return false;
+        }
+
+        terrain.cave_density <= 0.0
+            || fbm(point, 2, frequency * 2.0, |point| {
+                self.caves.simplex3(point)
+            }) <= 1.0 - terrain.cave_density as f64
+    }
+}
+
+// simplex noise, after Stefan Gustavson's "Simplex noise demystified", with
+// the gradients picked by a permutation shuffled from a seed; values are
+// within [-1, 1]
+struct Noise {
+    permutation: [u8; 512],
+}
+
+// gradients of 3D noise, towards the middle of the edges of a cube; 2D noise
+// uses their x and y
+const GRADIENTS: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+impl Noise {
+    fn new(seed: u64) -> Self {
+        let mut state = seed;
+        let mut values: Vec<u8> = (0..=255).collect();
+        for i in (1..values.len()).rev() {
+            let j = (split_mix(&mut state) % (i as u64 + 1)) as usize;
+            values.swap(i, j);
+        }
+
+        let mut permutation = [0; 512];
+        for (i, value) in permutation.iter_mut().enumerate() {
+            *value = values[i & 255];
+        }
+        Self { permutation }
+    }
+
+    fn gradient(&self, i: i64, j: i64, k: i64) -> DVec3 {
+        let p = &self.permutation;
+        let index =
+            p[(i & 255) as usize + p[(j & 255) as usize + p[(k & 255) as usize] as usize] as usize];
+        DVec3::from_array(GRADIENTS[index as usize % 12])
+    }
+
+    fn simplex2(&self, point: DVec2) -> f64 {
+        let skew = 0.5 * (3.0f64.sqrt() - 1.0);
+        let unskew = (3.0 - 3.0f64.sqrt()) / 6.0;
+
+        // triangle holding the point, and the corner it starts from
+        let cell = (point + (point.x + point.y) * skew).floor();
+        let first = point - (cell - (cell.x + cell.y) * unskew);
+        let step = if first.x > first.y {
+            dvec2(1.0, 0.0)
+        } else {
+            dvec2(0.0, 1.0)
+        };
+
+        let corners = [
+            (DVec2::ZERO, first),
+            (step, first - step + unskew),
+            (DVec2::ONE, first - 1.0 + 2.0 * unskew),
+        ];
+        let mut sum = 0.0;
+        for (offset, position) in corners {
+            let t = 0.5 - position.length_squared();
+            if t > 0.0 {
+                let corner = cell + offset;
+                let gradient = self.gradient(corner.x as i64, corner.y as i64, 0);
+                sum += t.powi(4) * gradient.truncate().dot(position);
+            }
+        }
+        70.0 * sum
+    }
+
+    fn simplex3(&self, point: DVec3) -> f64 {
+        let skew = 1.0 / 3.0;
+        let unskew = 1.0 / 6.0;
+
+        // tetrahedron holding the point, with the corners it goes through
+        // from the first, along the axes in decreasing order of the offset
+        let cell = (point + (point.x + point.y + point.z) * skew).floor();
+        let first = point - (cell - (cell.x + cell.y + cell.z) * unskew);
+        let mut axes = [0, 1, 2];
+        axes.sort_by(|a, b| first[*b].total_cmp(&first[*a]));
+        let mut second = DVec3::ZERO;
+        second[axes[0]] = 1.0;
+        let mut third = second;
+        third[axes[1]] = 1.0;
+
+        let corners = [
+            (DVec3::ZERO, first),
+            (second, first - second + unskew),
+            (third, first - third + 2.0 * unskew),
+            (DVec3::ONE, first - 1.0 + 3.0 * unskew),
+        ];
+        let mut sum = 0.0;
+        for (offset, position) in corners {
+            let t = 0.6 - position.length_squared();
+            if t > 0.0 {
+                let corner = cell + offset;
+                let gradient = self.gradient(corner.x as i64, corner.y as i64, corner.z as i64);
+                sum += t.powi(4) * gradient.dot(position);
+            }
+        }
+        32.0 * sum
+    }
+}
+
+// sums octaves of noise at the point, scaled to the frequency of each octave;
+// the octaves are also shifted, so that they do not all meet at the origin.
+// the sum is scaled back within [-1, 1]
+fn fbm(point: DVec3, octaves: u32, frequency: f64, noise: impl Fn(DVec3) -> f64) -> f64 {
+    let (mut sum, mut total) = (0.0, 0.0);
+    let (mut frequency, mut amplitude) = (frequency, 1.0);
+    for octave in 0..octaves {
+        let shift = DVec3::splat(octave as f64 * 31.7);
+        sum += amplitude * noise(point * frequency + shift);
+        total += amplitude;
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+    sum / total
+}
+
+// next value of a SplitMix64 generator
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::tests::solid;
+
+    fn terrain(kind: TerrainKind, seed: u64) -> Terrain {
+        Terrain {
+            kind,
+            seed,
+            octaves: 4,
+            frequency: 0.02,
+            threshold: 0.0,
+            cave_density: 0.3,
+            region: ((-40, -20, -10), (30, 50, 30)),
+        }
+    }
+
+    fn generate(terrain: &Terrain) -> VoxelGrid {
+        let mut voxel_grid = VoxelGrid::new();
+        voxel_grid.generate_terrain(terrain, 4);
+        voxel_grid
+    }
+
+    #[test]
+    fn terrains_only_depend_on_their_parameters() {
+        for kind in [TerrainKind::Heightfield, TerrainKind::Volume] {
+            let voxels = solid(&generate(&terrain(kind, 7)));
+            assert!(!voxels.is_empty(), "{:?}", kind);
+            assert_eq!(solid(&generate(&terrain(kind, 7))), voxels, "{:?}", kind);
+            assert_ne!(solid(&generate(&terrain(kind, 8))), voxels, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn terrains_stay_within_their_region() {
+        let terrain = terrain(TerrainKind::Volume, 3);
+        let (min, max) = generate(&terrain).bounding_box().unwrap();
+        let region = terrain.region;
+        assert!(min.0 >= region.0 .0 && min.1 >= region.0 .1 && min.2 >= region.0 .2);
+        assert!(max.0 <= region.1 .0 && max.1 <= region.1 .1 && max.2 <= region.1 .2);
+    }
+
+    #[test]
+    fn heightfields_have_no_overhangs() {
+        let terrain = Terrain {
+            cave_density: 0.0,
+            ..terrain(TerrainKind::Heightfield, 11)
+        };
+        let voxel_grid = generate(&terrain);
+        let (min, max) = terrain.region;
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                let height = (min.2..=max.2)
+                    .take_while(|z| voxel_grid.read((x, y, *z)) == 1)
+                    .count() as i32;
+                for z in min.2 + height..=max.2 {
+                    assert_eq!(voxel_grid.read((x, y, z)), 0, "{:?}", (x, y, z));
+                }
+            }
+        }
+    }
+}